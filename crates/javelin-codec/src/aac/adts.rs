@@ -1,5 +1,8 @@
+use std::convert::TryFrom;
+
 use bytes::BufMut;
 
+use super::common::{AudioObjectType, ChannelConfiguration, SamplingFrequencyIndex};
 use super::config::AudioSpecificConfiguration;
 use crate::aac::error::AacError;
 use crate::aac::Aac;
@@ -32,6 +35,50 @@ pub struct AudioDataTransportStream;
 impl AudioDataTransportStream {
     const PROTECTION_ABSENCE: u16 = 0x0001;
     const SYNCWORD: u16 = 0xFFF0;
+
+    /// Splits ADTS input into raw AAC frames, along with the configuration from their headers.
+    pub fn read_frames(input: &[u8]) -> Result<Vec<(AudioSpecificConfiguration, Aac)>, AacError> {
+        let mut frames = Vec::new();
+        let mut remaining = input;
+
+        while !remaining.is_empty() {
+            let header = remaining
+                .get(..7)
+                .ok_or(AacError::NotEnoughData("ADTS header"))?;
+
+            if u16::from_be_bytes([header[0], header[1]]) & Self::SYNCWORD != Self::SYNCWORD {
+                return Err(AacError::MissingSyncWord);
+            }
+
+            let header_length = if header[1] & 0x01 == 0x01 { 7 } else { 9 };
+            let frame_length = ((header[3] as usize & 0x03) << 11)
+                | ((header[4] as usize) << 3)
+                | (header[5] as usize >> 5);
+
+            let payload = remaining
+                .get(header_length..frame_length)
+                .ok_or(AacError::NotEnoughData("ADTS frame"))?;
+
+            let asc = AudioSpecificConfiguration {
+                object_type: AudioObjectType::try_from((header[2] >> 6) + 1)?,
+                sampling_frequency_index: SamplingFrequencyIndex::try_from(
+                    (header[2] >> 2) & 0x0F,
+                )?,
+                sampling_frequency: None,
+                channel_configuration: ChannelConfiguration::try_from(
+                    ((header[2] & 0x01) << 2) | (header[3] >> 6),
+                )?,
+                frame_length_flag: false,
+                depends_on_core_coder: false,
+                extension_flag: false,
+            };
+
+            frames.push((asc, payload.into()));
+            remaining = &remaining[frame_length..];
+        }
+
+        Ok(frames)
+    }
 }
 
 impl WriteFormat<Aac> for AudioDataTransportStream {
@@ -51,12 +98,13 @@ impl WriteFormat<Aac> for AudioDataTransportStream {
         let object_type = ctx.object_type as u8;
         let profile = (object_type - 1) << 6;
 
-        let sampling_frequency_index = u8::from(ctx.sampling_frequency_index) << 2;
+        let sampling_frequency_index = u8::from(ctx.sampling_frequency_index);
         if sampling_frequency_index == 0x0F {
             return Err(AacError::ForbiddenSamplingFrequencyIndex(
                 sampling_frequency_index,
             ));
         }
+        let sampling_frequency_index = sampling_frequency_index << 2;
 
         let channel_configuration: u8 = ctx.channel_configuration.into();
         let channel_configuration1 = (channel_configuration & 0x07) >> 2;
@@ -85,3 +133,67 @@ impl WriteFormat<Aac> for AudioDataTransportStream {
         Ok(tmp)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // AAC LC, 44.1 kHz, stereo
+    const ASC: [u8; 2] = [0x12, 0x10];
+
+    fn config() -> AudioSpecificConfiguration {
+        AudioSpecificConfiguration::try_from(&ASC[..]).unwrap()
+    }
+
+    #[test]
+    fn writes_header() {
+        let frame = AudioDataTransportStream
+            .write_format(Aac::from(&[0xAB; 9][..]), &config())
+            .unwrap();
+
+        assert_eq!(frame[..7], [0xFF, 0xF1, 0x50, 0x80, 0x02, 0x1F, 0xFC]);
+        assert_eq!(frame[7..], [0xAB; 9]);
+    }
+
+    #[test]
+    fn round_trips_frames() {
+        let payloads: [&[u8]; 2] = [&[0x21, 0x10, 0x04, 0x60], &[0x8C; 300]];
+
+        let mut stream = Vec::new();
+        for payload in payloads {
+            stream.extend(
+                AudioDataTransportStream
+                    .write_format(Aac::from(payload), &config())
+                    .unwrap(),
+            );
+        }
+
+        let frames = AudioDataTransportStream::read_frames(&stream).unwrap();
+
+        assert_eq!(frames.len(), payloads.len());
+        for ((asc, aac), payload) in frames.into_iter().zip(payloads) {
+            assert_eq!(Vec::from(&asc), ASC);
+            assert_eq!(Vec::from(aac), payload);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_frame() {
+        let frame = AudioDataTransportStream
+            .write_format(Aac::from(&[0x8C; 32][..]), &config())
+            .unwrap();
+
+        assert!(AudioDataTransportStream::read_frames(&frame[..20]).is_err());
+    }
+
+    #[test]
+    fn rejects_forbidden_frequency_index() {
+        let mut asc = config();
+        asc.sampling_frequency_index = SamplingFrequencyIndex::try_from(0x0F).unwrap();
+
+        assert!(AudioDataTransportStream
+            .write_format(Aac::from(&[0x8C; 4][..]), &asc)
+            .is_err());
+    }
+}
@@ -5,6 +5,17 @@ use super::AacError;
 #[derive(Debug, Clone, Copy)]
 pub struct SamplingFrequencyIndex(u8);
 
+impl SamplingFrequencyIndex {
+    const FREQUENCIES: [u32; 13] = [
+        96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+    ];
+
+    /// Frequency in Hertz, unknown if it is given explicitly instead of through the index.
+    pub fn frequency(&self) -> Option<u32> {
+        Self::FREQUENCIES.get(self.0 as usize).copied()
+    }
+}
+
 impl From<SamplingFrequencyIndex> for u8 {
     fn from(val: SamplingFrequencyIndex) -> Self {
         val.0
@@ -62,3 +62,48 @@ impl TryFrom<&[u8]> for AudioSpecificConfiguration {
         })
     }
 }
+
+impl From<&AudioSpecificConfiguration> for Vec<u8> {
+    fn from(val: &AudioSpecificConfiguration) -> Self {
+        let object_type = val.object_type as u8;
+        let sf_idx = u8::from(val.sampling_frequency_index);
+        let channel_configuration = u8::from(val.channel_configuration);
+        let flags = ((val.frame_length_flag as u8) << 2)
+            | ((val.depends_on_core_coder as u8) << 1)
+            | val.extension_flag as u8;
+
+        vec![
+            (object_type << 3) | (sf_idx >> 1),
+            ((sf_idx & 0x01) << 7) | (channel_configuration << 3) | flags,
+        ]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        // AAC LC 44.1 kHz stereo, AAC Main 48 kHz mono with the frame length flag
+        for bytes in [[0x12, 0x10], [0x09, 0x8C]] {
+            let asc = AudioSpecificConfiguration::try_from(&bytes[..]).unwrap();
+            assert_eq!(Vec::from(&asc), bytes);
+        }
+    }
+
+    #[test]
+    fn reads_fields() {
+        let asc = AudioSpecificConfiguration::try_from(&[0x12, 0x10][..]).unwrap();
+
+        assert_eq!(asc.object_type, AudioObjectType::AacLowComplexity);
+        assert_eq!(asc.sampling_frequency_index.frequency(), Some(44100));
+        assert_eq!(u8::from(asc.channel_configuration), 2);
+    }
+
+    #[test]
+    fn rejects_short_input() {
+        assert!(AudioSpecificConfiguration::try_from(&[0x12][..]).is_err());
+    }
+}
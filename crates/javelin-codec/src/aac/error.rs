@@ -20,4 +20,7 @@ pub enum AacError {
 
     #[error("Got forbidden sampling frequency index {0}")]
     ForbiddenSamplingFrequencyIndex(u8),
+
+    #[error("ADTS frame does not start with a sync word")]
+    MissingSyncWord,
 }
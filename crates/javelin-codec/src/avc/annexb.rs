@@ -1,9 +1,11 @@
+use std::convert::TryFrom;
+
 use tracing::debug;
 
 use crate::avc::config::DecoderConfigurationRecord;
 use crate::avc::error::AvcError;
 use crate::avc::{nal, Avc};
-use crate::WriteFormat;
+use crate::{ReadFormat, WriteFormat};
 
 
 pub struct AnnexB;
@@ -69,3 +71,112 @@ impl WriteFormat<Avc> for AnnexB {
         Ok(out_buffer)
     }
 }
+
+impl ReadFormat<Avc> for AnnexB {
+    type Context = ();
+    type Error = AvcError;
+
+    fn read_format(&self, input: &[u8], _ctx: &Self::Context) -> Result<Avc, Self::Error> {
+        let mut nal_units = Vec::new();
+
+        for unit in split_units(input) {
+            match nal::Unit::try_from(unit) {
+                Ok(nal_unit) => nal_units.push(nal_unit),
+                Err(AvcError::UnsupportedNalUnitType(t)) => {
+                    debug!("Skipping unsupported NALU type {}", t)
+                }
+                Err(why) => return Err(why),
+            }
+        }
+
+        Ok(nal_units.into())
+    }
+}
+
+
+/// Splits a byte stream at its start codes, leading zero bytes of four byte start codes are
+/// trimmed from the preceding unit.
+fn split_units(input: &[u8]) -> Vec<&[u8]> {
+    let mut units = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+
+    while i + AnnexB::DELIMITER1.len() <= input.len() {
+        if &input[i..i + AnnexB::DELIMITER1.len()] != AnnexB::DELIMITER1 {
+            i += 1;
+            continue;
+        }
+
+        if let Some(start) = start {
+            units.push(trim_trailing_zeros(&input[start..i]));
+        }
+
+        i += AnnexB::DELIMITER1.len();
+        start = Some(i);
+    }
+
+    if let Some(start) = start {
+        units.push(&input[start..]);
+    }
+
+    units.retain(|unit| !unit.is_empty());
+    units
+}
+
+fn trim_trailing_zeros(unit: &[u8]) -> &[u8] {
+    let end = unit.iter().rposition(|&b| b != 0).map_or(0, |pos| pos + 1);
+    &unit[..end]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: &[u8] = &[0x67, 0x42, 0xC0, 0x1E, 0xDA, 0x02, 0x80];
+    const PPS: &[u8] = &[0x68, 0xCE, 0x3C, 0x80];
+    const IDR: &[u8] = &[0x65, 0x88, 0x84, 0x00, 0x21];
+
+    #[test]
+    fn splits_at_both_start_codes() {
+        let mut input = vec![0x00, 0x00, 0x00, 0x01];
+        input.extend(SPS);
+        input.extend([0x00, 0x00, 0x01]);
+        input.extend(PPS);
+        input.extend([0x00, 0x00, 0x00, 0x01]);
+        input.extend(IDR);
+
+        assert_eq!(split_units(&input), [SPS, PPS, IDR]);
+    }
+
+    #[test]
+    fn skips_empty_units() {
+        let mut input = vec![0x00, 0x00, 0x01, 0x00, 0x00, 0x01];
+        input.extend(IDR);
+
+        assert_eq!(split_units(&input), [IDR]);
+        assert!(split_units(&[0xAB, 0xCD]).is_empty());
+    }
+
+    #[test]
+    fn round_trips_access_unit() {
+        let mut input = AnnexB::ACCESS_UNIT_DELIMITER.to_vec();
+        for (delimiter, unit) in [
+            (AnnexB::DELIMITER2, SPS),
+            (AnnexB::DELIMITER2, PPS),
+            (AnnexB::DELIMITER1, IDR),
+        ] {
+            input.extend(delimiter);
+            input.extend(unit);
+        }
+
+        let avc = AnnexB.read_format(&input, &()).unwrap();
+        let dcr = DecoderConfigurationRecord::from_parameter_sets(
+            nal::Unit::try_from(SPS).unwrap(),
+            nal::Unit::try_from(PPS).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(AnnexB.write_format(avc, &dcr).unwrap(), input);
+    }
+}
@@ -1,12 +1,12 @@
 use std::convert::TryFrom;
 use std::io::Cursor;
 
-use bytes::Buf;
+use bytes::{Buf, BufMut};
 
 use crate::avc::config::DecoderConfigurationRecord;
 use crate::avc::error::AvcError;
 use crate::avc::{nal, Avc};
-use crate::ReadFormat;
+use crate::{ReadFormat, WriteFormat};
 
 
 pub struct Avcc;
@@ -42,3 +42,21 @@ impl ReadFormat<Avc> for Avcc {
         Ok(nal_units.into())
     }
 }
+
+impl WriteFormat<Avc> for Avcc {
+    type Context = DecoderConfigurationRecord;
+    type Error = AvcError;
+
+    fn write_format(&self, input: Avc, ctx: &Self::Context) -> Result<Vec<u8>, Self::Error> {
+        let mut out_buffer = Vec::new();
+        let nalus: Vec<nal::Unit> = input.into();
+
+        for nalu in nalus {
+            let nalu_data: Vec<u8> = nalu.into();
+            out_buffer.put_uint(nalu_data.len() as u64, ctx.nalu_size as usize);
+            out_buffer.extend(nalu_data);
+        }
+
+        Ok(out_buffer)
+    }
+}
@@ -1,7 +1,7 @@
 use std::convert::TryFrom;
 use std::io::Cursor;
 
-use bytes::Buf;
+use bytes::{Buf, BufMut};
 
 use super::{nal, AvcError};
 
@@ -32,6 +32,26 @@ pub struct DecoderConfigurationRecord {
     pub pps: Vec<nal::Unit>,
 }
 
+impl DecoderConfigurationRecord {
+    /// Builds a record from in-band parameter sets, like the ones repeated in Annex B streams.
+    pub fn from_parameter_sets(sps: nal::Unit, pps: nal::Unit) -> Result<Self, AvcError> {
+        let (profile_indication, profile_compatability, level_indication) = match sps.payload() {
+            [profile, compatability, level, ..] => (*profile, *compatability, *level),
+            _ => return Err(AvcError::NotEnoughData("SPS")),
+        };
+
+        Ok(Self {
+            version: 1,
+            profile_indication,
+            profile_compatability,
+            level_indication,
+            nalu_size: 4,
+            sps: vec![sps],
+            pps: vec![pps],
+        })
+    }
+}
+
 impl TryFrom<&[u8]> for DecoderConfigurationRecord {
     type Error = AvcError;
 
@@ -98,3 +118,78 @@ impl TryFrom<&[u8]> for DecoderConfigurationRecord {
         })
     }
 }
+
+impl From<&DecoderConfigurationRecord> for Vec<u8> {
+    fn from(val: &DecoderConfigurationRecord) -> Self {
+        let mut tmp = Vec::new();
+
+        tmp.put_u8(val.version);
+        tmp.put_u8(val.profile_indication);
+        tmp.put_u8(val.profile_compatability);
+        tmp.put_u8(val.level_indication);
+        tmp.put_u8(0xFC | (val.nalu_size - 1));
+
+        tmp.put_u8(0xE0 | val.sps.len() as u8);
+        for sps in &val.sps {
+            let sps: Vec<u8> = sps.into();
+            tmp.put_u16(sps.len() as u16);
+            tmp.extend(sps);
+        }
+
+        tmp.put_u8(val.pps.len() as u8);
+        for pps in &val.pps {
+            let pps: Vec<u8> = pps.into();
+            tmp.put_u16(pps.len() as u16);
+            tmp.extend(pps);
+        }
+
+        tmp
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: &[u8] = &[
+        0x67, 0x64, 0x00, 0x1F, 0xAC, 0xD9, 0x40, 0x50, 0x05, 0xBB, 0x01, 0x10, 0x00, 0x00, 0x03,
+        0x00, 0x10, 0x00, 0x00, 0x03, 0x03, 0xC0, 0xF1, 0x83, 0x19, 0x60,
+    ];
+    const PPS: &[u8] = &[0x68, 0xEB, 0xE3, 0xCB, 0x22, 0xC0];
+
+    fn record() -> Vec<u8> {
+        let mut bytes = vec![0x01, 0x64, 0x00, 0x1F, 0xFF, 0xE1, 0x00, SPS.len() as u8];
+        bytes.extend(SPS);
+        bytes.extend([0x01, 0x00, PPS.len() as u8]);
+        bytes.extend(PPS);
+        bytes
+    }
+
+    #[test]
+    fn round_trips() {
+        let bytes = record();
+        let dcr = DecoderConfigurationRecord::try_from(&bytes[..]).unwrap();
+
+        assert_eq!(dcr.profile_indication, 0x64);
+        assert_eq!(dcr.level_indication, 0x1F);
+        assert_eq!(dcr.nalu_size, 4);
+        assert_eq!(Vec::from(&dcr), bytes);
+    }
+
+    #[test]
+    fn builds_from_parameter_sets() {
+        let sps = nal::Unit::try_from(SPS).unwrap();
+        let pps = nal::Unit::try_from(PPS).unwrap();
+        let dcr = DecoderConfigurationRecord::from_parameter_sets(sps, pps).unwrap();
+
+        assert_eq!(Vec::from(&dcr), record());
+    }
+
+    #[test]
+    fn rejects_truncated_record() {
+        let bytes = record();
+
+        assert!(DecoderConfigurationRecord::try_from(&bytes[..20]).is_err());
+    }
+}
@@ -17,4 +17,7 @@ pub enum AvcError {
 
     #[error("Unsupported or unknown NAL unit type {0}")]
     UnsupportedNalUnitType(u8),
+
+    #[error("Forbidden zero bit of NAL unit header is set")]
+    ForbiddenZeroBitSet,
 }
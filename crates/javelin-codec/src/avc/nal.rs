@@ -74,11 +74,16 @@ impl TryFrom<&[u8]> for Unit {
     type Error = AvcError;
 
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.is_empty() {
+            return Err(AvcError::NotEnoughData("NALU header"));
+        }
+
         let mut buf = Cursor::new(bytes);
 
         let header = buf.get_u8();
-        // FIXME: return error
-        assert_eq!(header >> 7, 0);
+        if header >> 7 != 0 {
+            return Err(AvcError::ForbiddenZeroBitSet);
+        }
 
         let ref_idc = (header >> 5) & 0x03;
         let kind = UnitType::try_from(header & 0x1F)?;
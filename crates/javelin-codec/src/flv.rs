@@ -1,5 +1,7 @@
 pub mod error;
 pub mod tag;
+pub mod writer;
 
 
 pub use self::error::FlvError;
+pub use self::writer::{FlvWriter, ScriptValue, TagType};
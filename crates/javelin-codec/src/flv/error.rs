@@ -21,6 +21,9 @@ pub enum FlvError {
     #[error("Audio format with id {0} is not supported")]
     UnsupportedAudioFormat(u8),
 
+    #[error("Tag of {0} bytes does not fit into an FLV tag")]
+    TagTooLarge(usize),
+
     #[error("Not enough data: {0}")]
     NotEnoughData(&'static str),
 
@@ -2,5 +2,5 @@ pub mod audio;
 pub mod video;
 
 
-pub use audio::AudioData;
-pub use video::VideoData;
+pub use audio::{AudioData, AudioTagHeader};
+pub use video::{VideoData, VideoTagHeader};
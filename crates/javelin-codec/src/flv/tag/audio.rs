@@ -2,7 +2,7 @@ use std::convert::TryFrom;
 use std::fmt::{self, Debug};
 use std::io::{Cursor, Read};
 
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes};
 
 use crate::flv::error::FlvError;
 
@@ -11,6 +11,12 @@ use crate::flv::error::FlvError;
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Frequency(u32);
 
+impl From<u32> for Frequency {
+    fn from(val: u32) -> Self {
+        Self(val)
+    }
+}
+
 
 #[non_exhaustive]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -112,6 +118,31 @@ impl TryFrom<&[u8]> for AudioData {
     }
 }
 
+impl From<AudioData> for Vec<u8> {
+    fn from(val: AudioData) -> Self {
+        let format: u8 = match val.format {
+            AudioFormat::Aac => 10,
+        };
+        let sampling_rate: u8 = match val.sampling_rate.0 {
+            0..=5500 => 0,
+            5501..=11025 => 1,
+            11026..=22050 => 2,
+            _ => 3,
+        };
+        let sample_size = (val.sample_size == 16) as u8;
+
+        let mut tmp = Vec::with_capacity(2 + val.body.len());
+        tmp.put_u8((format << 4) | (sampling_rate << 2) | (sample_size << 1) | val.stereo as u8);
+        match val.aac_packet_type {
+            AacPacketType::SequenceHeader => tmp.put_u8(0),
+            AacPacketType::Raw => tmp.put_u8(1),
+            AacPacketType::None => (),
+        }
+        tmp.put(val.body);
+        tmp
+    }
+}
+
 impl Debug for AudioData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("AudioData")
@@ -125,6 +156,52 @@ impl Debug for AudioData {
 }
 
 
+/// Packet type of an audio tag, read without copying the tag body.
+///
+/// Understands legacy AAC and MP3 tags as well as enhanced RTMP tags of any codec. Codecs
+/// without a sequence header only carry raw frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioTagHeader {
+    pub packet_type: AacPacketType,
+}
+
+impl AudioTagHeader {
+    pub fn is_sequence_header(&self) -> bool {
+        self.packet_type == AacPacketType::SequenceHeader
+    }
+}
+
+impl TryFrom<&[u8]> for AudioTagHeader {
+    type Error = FlvError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() < 2 {
+            return Err(FlvError::NotEnoughData("FLV Audio Tag header"));
+        }
+
+        let packet_type = match bytes[0] >> 4 {
+            10 => AacPacketType::try_from(bytes[1])?,
+            2 | 14 => AacPacketType::None,
+            // enhanced RTMP: 4 bit packet type followed by a FourCC
+            9 => {
+                if bytes.len() < 5 {
+                    return Err(FlvError::NotEnoughData("FLV Audio Tag extended header"));
+                }
+
+                match bytes[0] & 0x0F {
+                    0 => AacPacketType::SequenceHeader,
+                    1 => AacPacketType::Raw,
+                    _ => AacPacketType::None,
+                }
+            }
+            format => return Err(FlvError::UnsupportedAudioFormat(format)),
+        };
+
+        Ok(Self { packet_type })
+    }
+}
+
+
 fn try_convert_sampling_rate(val: u8) -> Result<Frequency, FlvError> {
     Ok(match val {
         0 => Frequency(5500),
@@ -2,7 +2,7 @@ use std::convert::TryFrom;
 use std::fmt::{self, Debug};
 use std::io::{Cursor, Read};
 
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes};
 
 use crate::flv::error::FlvError;
 
@@ -89,6 +89,30 @@ impl Debug for VideoData {
     }
 }
 
+impl From<VideoData> for Vec<u8> {
+    fn from(val: VideoData) -> Self {
+        let frame_type: u8 = match val.frame_type {
+            FrameType::KeyFrame => 1,
+            FrameType::InterFrame => 2,
+            FrameType::DisposableInterFrame => 3,
+            FrameType::GeneratedKeyframe => 4,
+            FrameType::VideoInfoFrame => 5,
+        };
+        let packet_type: u8 = match val.packet_type {
+            AvcPacketType::SequenceHeader => 0,
+            AvcPacketType::NalUnit | AvcPacketType::None => 1,
+            AvcPacketType::EndOfSequence => 2,
+        };
+
+        let mut tmp = Vec::with_capacity(5 + val.body.len());
+        tmp.put_u8((frame_type << 4) | 7);
+        tmp.put_u8(packet_type);
+        tmp.put_int(val.composition_time.into(), 3);
+        tmp.put(val.body);
+        tmp
+    }
+}
+
 impl TryFrom<&[u8]> for VideoData {
     type Error = FlvError;
 
@@ -126,3 +150,69 @@ impl TryFrom<&[u8]> for VideoData {
         })
     }
 }
+
+
+/// Frame and packet type of a video tag, read without copying the tag body.
+///
+/// Understands legacy AVC and HEVC tags as well as enhanced RTMP tags of any codec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoTagHeader {
+    pub frame_type: FrameType,
+    pub packet_type: AvcPacketType,
+}
+
+impl VideoTagHeader {
+    pub fn is_sequence_header(&self) -> bool {
+        self.packet_type == AvcPacketType::SequenceHeader
+    }
+
+    pub fn is_keyframe(&self) -> bool {
+        self.frame_type == FrameType::KeyFrame
+    }
+
+    /// Frames no other frame depends on, safe to drop without corrupting the picture.
+    pub fn is_disposable(&self) -> bool {
+        self.frame_type == FrameType::DisposableInterFrame
+    }
+}
+
+impl TryFrom<&[u8]> for VideoTagHeader {
+    type Error = FlvError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() < 2 {
+            return Err(FlvError::NotEnoughData("FLV Video Tag header"));
+        }
+
+        // enhanced RTMP: 1 bit flag, 3 bit frame type, 4 bit packet type, FourCC
+        if bytes[0] & 0x80 != 0 {
+            if bytes.len() < 5 {
+                return Err(FlvError::NotEnoughData("FLV Video Tag extended header"));
+            }
+
+            let packet_type = match bytes[0] & 0x0F {
+                0 => AvcPacketType::SequenceHeader,
+                // coded frames, with or without composition time
+                1 | 3 => AvcPacketType::NalUnit,
+                2 => AvcPacketType::EndOfSequence,
+                _ => AvcPacketType::None,
+            };
+
+            return Ok(Self {
+                frame_type: FrameType::try_from((bytes[0] >> 4) & 0x07)?,
+                packet_type,
+            });
+        }
+
+        // AVC and the legacy HEVC extension share the same layout
+        let codec_id = bytes[0] & 0x0F;
+        if codec_id != 7 && codec_id != 12 {
+            return Err(FlvError::UnsupportedVideoFormat(codec_id));
+        }
+
+        Ok(Self {
+            frame_type: FrameType::try_from(bytes[0] >> 4)?,
+            packet_type: AvcPacketType::try_from(bytes[1])?,
+        })
+    }
+}
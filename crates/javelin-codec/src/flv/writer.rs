@@ -0,0 +1,261 @@
+use std::io::Write;
+
+use bytes::BufMut;
+
+use crate::flv::error::FlvError;
+
+
+const FLV_VERSION: u8 = 1;
+const HEADER_SIZE: u32 = 9;
+const TAG_HEADER_SIZE: usize = 11;
+const MAX_TAG_SIZE: usize = 0xFF_FF_FF;
+
+const AMF0_NUMBER: u8 = 0x00;
+const AMF0_BOOLEAN: u8 = 0x01;
+const AMF0_STRING: u8 = 0x02;
+const AMF0_ECMA_ARRAY: u8 = 0x08;
+const AMF0_OBJECT_END: u8 = 0x09;
+const AMF0_LONG_STRING: u8 = 0x0C;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagType {
+    Audio,
+    Video,
+    ScriptData,
+}
+
+impl From<TagType> for u8 {
+    fn from(val: TagType) -> Self {
+        match val {
+            TagType::Audio => 8,
+            TagType::Video => 9,
+            TagType::ScriptData => 18,
+        }
+    }
+}
+
+
+/// Value of an `onMetaData` property.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptValue {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+}
+
+
+/// Writes an FLV file, tag bodies are expected as they are sent over RTMP.
+///
+/// Every tag is followed by the size of the tag before it, starting with a zero
+/// right after the file header.
+pub struct FlvWriter<W> {
+    inner: W,
+    bytes_written: u64,
+}
+
+impl<W> FlvWriter<W>
+where
+    W: Write,
+{
+    /// Writes the file header, the flags announce which kinds of tags follow.
+    pub fn new(mut inner: W, has_audio: bool, has_video: bool) -> Result<Self, FlvError> {
+        let flags = ((has_audio as u8) << 2) | has_video as u8;
+
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize + 4);
+        header.put_slice(b"FLV");
+        header.put_u8(FLV_VERSION);
+        header.put_u8(flags);
+        header.put_u32(HEADER_SIZE);
+        header.put_u32(0);
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            bytes_written: header.len() as u64,
+        })
+    }
+
+    /// Writes an `onMetaData` script tag, usually right after the header.
+    pub fn write_metadata(&mut self, properties: &[(&str, ScriptValue)]) -> Result<(), FlvError> {
+        let mut data = Vec::new();
+        put_amf0_string(&mut data, "onMetaData");
+
+        data.put_u8(AMF0_ECMA_ARRAY);
+        data.put_u32(properties.len() as u32);
+        for (name, value) in properties {
+            put_amf0_key(&mut data, name);
+            match value {
+                ScriptValue::Number(number) => {
+                    data.put_u8(AMF0_NUMBER);
+                    data.put_f64(*number);
+                }
+                ScriptValue::Boolean(boolean) => {
+                    data.put_u8(AMF0_BOOLEAN);
+                    data.put_u8(*boolean as u8);
+                }
+                ScriptValue::String(string) => put_amf0_string(&mut data, string),
+            }
+        }
+        put_amf0_key(&mut data, "");
+        data.put_u8(AMF0_OBJECT_END);
+
+        self.write_tag(TagType::ScriptData, 0, &data)
+    }
+
+    /// Timestamps are in milliseconds and wrap around after about 49.7 days, like on RTMP.
+    pub fn write_tag(
+        &mut self,
+        tag_type: TagType,
+        timestamp: u32,
+        data: &[u8],
+    ) -> Result<(), FlvError> {
+        if data.len() > MAX_TAG_SIZE {
+            return Err(FlvError::TagTooLarge(data.len()));
+        }
+
+        let tag_size = TAG_HEADER_SIZE + data.len();
+
+        let mut header = Vec::with_capacity(TAG_HEADER_SIZE);
+        header.put_u8(tag_type.into());
+        header.put_uint(data.len() as u64, 3);
+        header.put_uint((timestamp & 0x00_FF_FF_FF).into(), 3);
+        header.put_u8((timestamp >> 24) as u8);
+        header.put_uint(0, 3);
+
+        self.inner.write_all(&header)?;
+        self.inner.write_all(data)?;
+        self.inner.write_all(&(tag_size as u32).to_be_bytes())?;
+        self.bytes_written += tag_size as u64 + 4;
+
+        Ok(())
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    pub fn flush(&mut self) -> Result<(), FlvError> {
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+
+fn put_amf0_key(buf: &mut Vec<u8>, key: &str) {
+    let key = &key.as_bytes()[..key.len().min(u16::MAX as usize)];
+    buf.put_u16(key.len() as u16);
+    buf.put_slice(key);
+}
+
+
+fn put_amf0_string(buf: &mut Vec<u8>, string: &str) {
+    match u16::try_from(string.len()) {
+        Ok(len) => {
+            buf.put_u8(AMF0_STRING);
+            buf.put_u16(len);
+        }
+        Err(_) => {
+            buf.put_u8(AMF0_LONG_STRING);
+            buf.put_u32(string.len() as u32);
+        }
+    }
+    buf.put_slice(string.as_bytes());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_only(has_audio: bool, has_video: bool) -> Vec<u8> {
+        FlvWriter::new(Vec::new(), has_audio, has_video)
+            .unwrap()
+            .into_inner()
+    }
+
+    #[test]
+    fn writes_header() {
+        let bytes = header_only(true, true);
+
+        assert_eq!(bytes, [
+            b'F', b'L', b'V', 0x01, 0x05, 0x00, 0x00, 0x00, 0x09, // header
+            0x00, 0x00, 0x00, 0x00, // PreviousTagSize0
+        ]);
+        assert_eq!(header_only(true, false)[4], 0x04);
+        assert_eq!(header_only(false, true)[4], 0x01);
+    }
+
+    #[test]
+    fn frames_tags() {
+        let mut writer = FlvWriter::new(Vec::new(), true, true).unwrap();
+        writer
+            .write_tag(TagType::Video, 0x12_34_56_78, &[0x17, 0x01, 0xAA])
+            .unwrap();
+        writer.write_tag(TagType::Audio, 40, &[0xAF, 0x01]).unwrap();
+        assert_eq!(writer.bytes_written(), 13 + (11 + 3 + 4) + (11 + 2 + 4));
+
+        let bytes = writer.into_inner();
+        assert_eq!(bytes[13..], [
+            // video tag, 3 bytes, timestamp in 24 bits plus extension byte, stream ID 0
+            0x09, 0x00, 0x00, 0x03, 0x34, 0x56, 0x78, 0x12, 0x00, 0x00, 0x00, //
+            0x17, 0x01, 0xAA, //
+            0x00, 0x00, 0x00, 0x0E, // PreviousTagSize
+            0x08, 0x00, 0x00, 0x02, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x00, //
+            0xAF, 0x01, //
+            0x00, 0x00, 0x00, 0x0D,
+        ]);
+    }
+
+    #[test]
+    fn rejects_oversized_tag() {
+        let mut writer = FlvWriter::new(Vec::new(), false, true).unwrap();
+        let data = vec![0; MAX_TAG_SIZE + 1];
+
+        assert!(writer.write_tag(TagType::Video, 0, &data).is_err());
+        assert_eq!(writer.into_inner().len(), 13);
+    }
+
+    #[test]
+    fn writes_metadata() {
+        let mut writer = FlvWriter::new(Vec::new(), false, true).unwrap();
+        writer
+            .write_metadata(&[
+                ("width", ScriptValue::Number(1280.0)),
+                ("stereo", ScriptValue::Boolean(true)),
+                ("encoder", ScriptValue::String("obs".to_string())),
+            ])
+            .unwrap();
+        let bytes = writer.into_inner();
+
+        let mut body = vec![0x02, 0x00, 0x0A];
+        body.extend(b"onMetaData");
+        body.extend([0x08, 0x00, 0x00, 0x00, 0x03]);
+        body.extend([0x00, 0x05]);
+        body.extend(b"width");
+        body.push(0x00);
+        body.extend(1280.0f64.to_be_bytes());
+        body.extend([0x00, 0x06]);
+        body.extend(b"stereo");
+        body.extend([0x01, 0x01]);
+        body.extend([0x00, 0x07]);
+        body.extend(b"encoder");
+        body.extend([0x02, 0x00, 0x03]);
+        body.extend(b"obs");
+        body.extend([0x00, 0x00, 0x09]);
+
+        let tag = &bytes[13..];
+        assert_eq!(tag[0], 18);
+        assert_eq!(tag[1..4], (body.len() as u32).to_be_bytes()[1..]);
+        assert_eq!(tag[4..11], [0; 7]);
+        assert_eq!(tag[11..11 + body.len()], body);
+        assert_eq!(
+            tag[11 + body.len()..],
+            ((11 + body.len()) as u32).to_be_bytes()
+        );
+    }
+}
@@ -1,5 +1,7 @@
+mod demuxer;
 mod error;
 pub mod transport_stream;
 
+pub use self::demuxer::{is_random_access, Demuxer, ElementaryPacket, StreamType};
 pub use self::error::TsError;
 pub use self::transport_stream::TransportStream;
@@ -0,0 +1,544 @@
+use std::collections::HashMap;
+
+use tracing::{debug, trace};
+
+use super::TsError;
+
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0;
+const STREAM_TYPE_ADTS_AAC: u8 = 0x0F;
+const STREAM_TYPE_H264: u8 = 0x1B;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamType {
+    H264,
+    AdtsAac,
+}
+
+impl StreamType {
+    fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            STREAM_TYPE_H264 => Some(Self::H264),
+            STREAM_TYPE_ADTS_AAC => Some(Self::AdtsAac),
+            _ => None,
+        }
+    }
+}
+
+
+/// Payload of a single PES packet, usually one video access unit or a few audio frames.
+///
+/// Timestamps are in 90 kHz ticks, the DTS equals the PTS if the stream did not carry one.
+#[derive(Debug, Clone)]
+pub struct ElementaryPacket {
+    pub stream_type: StreamType,
+    pub pts: u64,
+    pub dts: u64,
+    pub data: Vec<u8>,
+}
+
+
+struct PendingPes {
+    stream_type: StreamType,
+    pts: Option<u64>,
+    dts: Option<u64>,
+    expected_len: Option<usize>,
+    data: Vec<u8>,
+}
+
+impl PendingPes {
+    fn start(stream_type: StreamType, payload: &[u8]) -> Result<Self, TsError> {
+        // Bytes | Field
+        // ----- | -----
+        // 3     | Start code prefix 0x000001
+        // 1     | Stream ID
+        // 2     | Packet length, 0 if unbounded
+        // 2     | Flags, PTS/DTS indicator in the upper two bits of the second byte
+        // 1     | Header data length
+        // var   | Optional fields, starting with PTS and DTS
+        let header = payload.get(..9).ok_or(TsError::Malformed("PES header"))?;
+
+        if header[..3] != [0x00, 0x00, 0x01] {
+            return Err(TsError::Malformed("PES start code"));
+        }
+
+        let packet_len = u16::from_be_bytes([header[4], header[5]]) as usize;
+        let pts_dts_flags = header[7] >> 6;
+        let header_len = 9 + header[8] as usize;
+
+        let optional = payload
+            .get(9..header_len)
+            .ok_or(TsError::Malformed("PES optional header"))?;
+        let pts = if pts_dts_flags & 0x02 != 0 {
+            optional.get(..5).map(read_timestamp)
+        } else {
+            None
+        };
+        let dts = if pts_dts_flags == 0x03 {
+            optional.get(5..10).map(read_timestamp)
+        } else {
+            None
+        };
+
+        // the packet length covers everything after the length field itself
+        let expected_len = (packet_len != 0).then(|| packet_len.saturating_sub(header_len - 6));
+
+        Ok(Self {
+            stream_type,
+            pts,
+            dts,
+            expected_len,
+            data: payload[header_len..].to_vec(),
+        })
+    }
+
+    fn is_complete(&self) -> bool {
+        self.expected_len.is_some_and(|len| self.data.len() >= len)
+    }
+
+    fn finish(self) -> Option<ElementaryPacket> {
+        let Some(pts) = self.pts else {
+            debug!("Discarding PES packet without timestamp");
+            return None;
+        };
+
+        Some(ElementaryPacket {
+            stream_type: self.stream_type,
+            pts,
+            dts: self.dts.unwrap_or(pts),
+            data: self.data,
+        })
+    }
+}
+
+
+/// Reassembles H.264 and AAC elementary streams from a continuous transport stream.
+///
+/// Input does not need to be aligned to packet boundaries, incomplete packets are kept
+/// until the rest arrives. Only the first program announced in the PAT is followed.
+///
+/// After losing sync, a sync byte is only trusted if another one follows exactly one
+/// packet later, so a stray 0x47 inside a payload does not throw the demuxer off.
+#[derive(Default)]
+pub struct Demuxer {
+    buffer: Vec<u8>,
+    synced: bool,
+    pmt_pid: Option<u16>,
+    streams: HashMap<u16, StreamType>,
+    pending: HashMap<u16, PendingPes>,
+}
+
+impl Demuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds transport stream bytes and returns all PES packets completed by them.
+    pub fn push(&mut self, input: &[u8]) -> Result<Vec<ElementaryPacket>, TsError> {
+        self.buffer.extend_from_slice(input);
+
+        let mut output = Vec::new();
+        let mut offset = 0;
+        let mut packet = [0; PACKET_SIZE];
+
+        while self.buffer.len() - offset >= PACKET_SIZE {
+            if self.buffer[offset] != SYNC_BYTE {
+                if self.synced {
+                    debug!("Lost transport stream sync");
+                    self.synced = false;
+                }
+                offset += 1;
+                continue;
+            }
+
+            if !self.synced {
+                match self.buffer.get(offset + PACKET_SIZE) {
+                    Some(&SYNC_BYTE) => self.synced = true,
+                    Some(_) => {
+                        offset += 1;
+                        continue;
+                    }
+                    // wait for the next packet to confirm the boundary
+                    None => break,
+                }
+            }
+
+            packet.copy_from_slice(&self.buffer[offset..offset + PACKET_SIZE]);
+            offset += PACKET_SIZE;
+
+            if let Err(why) = self.handle_packet(&packet, &mut output) {
+                debug!("Skipping transport stream packet: {}", why);
+            }
+        }
+
+        self.buffer.drain(..offset);
+
+        Ok(output)
+    }
+
+    fn handle_packet(
+        &mut self,
+        packet: &[u8],
+        output: &mut Vec<ElementaryPacket>,
+    ) -> Result<(), TsError> {
+        let payload_unit_start = packet[1] & 0x40 != 0;
+        let pid = pid(packet);
+        let adaptation_field_control = (packet[3] >> 4) & 0x03;
+
+        let mut offset = 4;
+        if adaptation_field_control & 0x02 != 0 {
+            offset += 1 + packet[4] as usize;
+        }
+        if adaptation_field_control & 0x01 == 0 || offset >= PACKET_SIZE {
+            return Ok(());
+        }
+        let payload = &packet[offset..];
+
+        if pid == PAT_PID {
+            if payload_unit_start {
+                self.read_pat(payload)?;
+            }
+            return Ok(());
+        }
+
+        if Some(pid) == self.pmt_pid {
+            if payload_unit_start {
+                self.read_pmt(payload)?;
+            }
+            return Ok(());
+        }
+
+        let Some(&stream_type) = self.streams.get(&pid) else {
+            return Ok(());
+        };
+
+        if payload_unit_start {
+            if let Some(pes) = self.pending.remove(&pid) {
+                output.extend(pes.finish());
+            }
+            self.pending
+                .insert(pid, PendingPes::start(stream_type, payload)?);
+        } else if let Some(pes) = self.pending.get_mut(&pid) {
+            pes.data.extend_from_slice(payload);
+        }
+
+        if self.pending.get(&pid).is_some_and(PendingPes::is_complete) {
+            output.extend(self.pending.remove(&pid).and_then(PendingPes::finish));
+        }
+
+        Ok(())
+    }
+
+    fn read_pat(&mut self, payload: &[u8]) -> Result<(), TsError> {
+        let section = psi_section(payload, "PAT")?;
+
+        // transport stream ID, version and section numbers precede the program list
+        let pmt_pid = section
+            .get(5..)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .find(|program| program[..2] != [0, 0])
+            .map(|program| u16::from_be_bytes([program[2] & 0x1F, program[3]]));
+
+        if pmt_pid != self.pmt_pid {
+            trace!(?pmt_pid, "Found program map");
+            self.pmt_pid = pmt_pid;
+            self.streams.clear();
+            self.pending.clear();
+        }
+
+        Ok(())
+    }
+
+    fn read_pmt(&mut self, payload: &[u8]) -> Result<(), TsError> {
+        let section = psi_section(payload, "PMT")?;
+
+        // program number, version, section numbers and PCR PID precede the program info
+        let info_len = section
+            .get(7..9)
+            .map(|len| u16::from_be_bytes([len[0] & 0x0F, len[1]]) as usize)
+            .ok_or(TsError::Malformed("PMT"))?;
+
+        let mut entries = section.get(9 + info_len..).unwrap_or_default();
+        let mut streams = HashMap::new();
+
+        while let [stream_type, pid_a, pid_b, len_a, len_b, rest @ ..] = entries {
+            let pid = u16::from_be_bytes([pid_a & 0x1F, *pid_b]);
+            let es_info_len = u16::from_be_bytes([len_a & 0x0F, *len_b]) as usize;
+
+            match StreamType::from_u8(*stream_type) {
+                Some(stream_type) => {
+                    streams.insert(pid, stream_type);
+                }
+                None => trace!(pid, stream_type, "Ignoring unsupported elementary stream"),
+            }
+
+            entries = rest.get(es_info_len..).unwrap_or_default();
+        }
+
+        if streams != self.streams {
+            debug!(?streams, "Program map changed");
+            self.pending.retain(|pid, _| streams.contains_key(pid));
+            self.streams = streams;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Whether a chunk of whole transport stream packets opens with the PAT and carries a
+/// random access point, so a viewer can start decoding from it.
+pub fn is_random_access(bytes: &[u8]) -> bool {
+    let mut packets = bytes.chunks_exact(PACKET_SIZE);
+
+    let opens_with_tables = packets
+        .next()
+        .is_some_and(|packet| packet[0] == SYNC_BYTE && pid(packet) == PAT_PID);
+
+    // the flag lives in the adaptation field, right after its length
+    opens_with_tables
+        && packets.any(|packet| packet[3] & 0x20 != 0 && packet[4] > 0 && packet[5] & 0x40 != 0)
+}
+
+
+fn pid(packet: &[u8]) -> u16 {
+    u16::from_be_bytes([packet[1] & 0x1F, packet[2]])
+}
+
+
+/// Section body between the section length field and the CRC.
+fn psi_section<'a>(payload: &'a [u8], table: &'static str) -> Result<&'a [u8], TsError> {
+    let pointer = *payload.first().ok_or(TsError::Malformed(table))? as usize;
+    let section = payload
+        .get(1 + pointer..)
+        .ok_or(TsError::Malformed(table))?;
+
+    let section_len = section
+        .get(1..3)
+        .map(|len| u16::from_be_bytes([len[0] & 0x0F, len[1]]) as usize)
+        .ok_or(TsError::Malformed(table))?;
+
+    section
+        .get(3..(3 + section_len).saturating_sub(4))
+        .ok_or(TsError::Malformed(table))
+}
+
+
+/// Reads a 33 bit PTS or DTS spread across five bytes with marker bits.
+fn read_timestamp(bytes: &[u8]) -> u64 {
+    (((bytes[0] >> 1) & 0x07) as u64) << 30
+        | (bytes[1] as u64) << 22
+        | ((bytes[2] >> 1) as u64) << 15
+        | (bytes[3] as u64) << 7
+        | (bytes[4] >> 1) as u64
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PMT_PID: u16 = 0x1000;
+    const VIDEO_PID: u16 = 0x100;
+    const AUDIO_PID: u16 = 0x101;
+
+    fn packet(pid: u16, start: bool, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![
+            SYNC_BYTE,
+            ((start as u8) << 6) | (pid >> 8) as u8,
+            pid as u8,
+            0x10,
+        ];
+
+        // pad short payloads with adaptation field stuffing
+        if payload.len() < PACKET_SIZE - 4 {
+            let stuffing = PACKET_SIZE - 5 - payload.len();
+            packet[3] = 0x30;
+            packet.push(stuffing as u8);
+            if stuffing > 0 {
+                packet.push(0x00);
+                packet.resize(packet.len() + stuffing - 1, 0xFF);
+            }
+        }
+
+        packet.extend(payload);
+        packet
+    }
+
+    fn random_access(mut packet: Vec<u8>) -> Vec<u8> {
+        packet[5] |= 0x40;
+        packet
+    }
+
+    fn section(table_id: u8, body: &[u8]) -> Vec<u8> {
+        let len = body.len() + 4;
+        let mut section = vec![0x00, table_id, 0xB0 | (len >> 8) as u8, len as u8];
+        section.extend(body);
+        // the demuxer does not verify the CRC
+        section.extend([0; 4]);
+        section
+    }
+
+    fn tables() -> Vec<u8> {
+        let pat = section(0x00, &[
+            0x00, 0x01, 0xC1, 0x00, 0x00, 0x00, 0x01, 0xF0, 0x00,
+        ]);
+        let pmt = section(0x02, &[
+            0x00,
+            0x01,
+            0xC1,
+            0x00,
+            0x00,
+            0xE1,
+            0x00,
+            0xF0,
+            0x00, // header
+            STREAM_TYPE_H264,
+            0xE1,
+            0x00,
+            0xF0,
+            0x00, // video
+            STREAM_TYPE_ADTS_AAC,
+            0xE1,
+            0x01,
+            0xF0,
+            0x00, // audio
+        ]);
+
+        let mut tables = packet(PAT_PID, true, &pat);
+        tables.extend(packet(PMT_PID, true, &pmt));
+        tables
+    }
+
+    fn timestamp(marker: u8, ts: u64) -> [u8; 5] {
+        [
+            (marker << 4) | ((ts >> 29) as u8 & 0x0E) | 0x01,
+            (ts >> 22) as u8,
+            ((ts >> 14) as u8 & 0xFE) | 0x01,
+            (ts >> 7) as u8,
+            ((ts << 1) as u8) | 0x01,
+        ]
+    }
+
+    fn pes(pid: u16, pts: u64, dts: Option<u64>, data: &[u8], bounded: bool) -> Vec<u8> {
+        let mut optional = timestamp(if dts.is_some() { 0x03 } else { 0x02 }, pts).to_vec();
+        if let Some(dts) = dts {
+            optional.extend(timestamp(0x01, dts));
+        }
+
+        let stream_id = if pid == VIDEO_PID { 0xE0 } else { 0xC0 };
+        let len = if bounded {
+            3 + optional.len() + data.len()
+        } else {
+            0
+        };
+
+        let mut pes = vec![
+            0x00,
+            0x00,
+            0x01,
+            stream_id,
+            (len >> 8) as u8,
+            len as u8,
+            0x80,
+        ];
+        pes.push(if dts.is_some() { 0xC0 } else { 0x80 });
+        pes.push(optional.len() as u8);
+        pes.extend(optional);
+        pes.extend(data);
+
+        pes.chunks(PACKET_SIZE - 4)
+            .enumerate()
+            .flat_map(|(i, chunk)| packet(pid, i == 0, chunk))
+            .collect()
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut stream = tables();
+        stream.extend(pes(VIDEO_PID, 93003, Some(90000), &[0xAA; 300], false));
+        stream.extend(pes(AUDIO_PID, 90000, None, &[0xBB; 40], true));
+        stream.extend(pes(VIDEO_PID, 96006, Some(93003), &[0xCC; 20], false));
+        stream
+    }
+
+    fn summary(packets: &[ElementaryPacket]) -> Vec<(StreamType, u64, u64, Vec<u8>)> {
+        packets
+            .iter()
+            .map(|p| (p.stream_type, p.pts, p.dts, p.data.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn reassembles_pes_packets() {
+        let packets = Demuxer::new().push(&sample()).unwrap();
+
+        // the last video PES is unbounded and stays pending until the next one starts
+        assert_eq!(summary(&packets), [
+            (StreamType::AdtsAac, 90000, 90000, vec![0xBB; 40]),
+            (StreamType::H264, 93003, 90000, vec![0xAA; 300]),
+        ]);
+    }
+
+    #[test]
+    fn accepts_unaligned_input() {
+        let mut demuxer = Demuxer::new();
+        let packets: Vec<_> = sample()
+            .chunks(7)
+            .flat_map(|chunk| demuxer.push(chunk).unwrap())
+            .collect();
+
+        assert_eq!(
+            summary(&packets),
+            summary(&Demuxer::new().push(&sample()).unwrap())
+        );
+    }
+
+    #[test]
+    fn resyncs_past_stray_sync_bytes() {
+        let expected = summary(&Demuxer::new().push(&sample()).unwrap());
+
+        let mut stream = vec![0x47, 0x00, 0x47, 0x12];
+        stream.extend(sample());
+        assert_eq!(summary(&Demuxer::new().push(&stream).unwrap()), expected);
+
+        // lose sync between two packets of the first video PES
+        let mut stream = sample();
+        stream.splice(3 * PACKET_SIZE..3 * PACKET_SIZE, [0x00, 0x47, 0x47, 0x00]);
+        let packets = Demuxer::new().push(&stream).unwrap();
+        assert_eq!(summary(&packets), expected);
+    }
+
+    #[test]
+    fn detects_random_access_points() {
+        let keyframe = random_access(packet(VIDEO_PID, true, &[0xAA; 20]));
+        let interframe = packet(VIDEO_PID, true, &[0xAA; 20]);
+
+        let mut chunk = tables();
+        chunk.extend(&keyframe);
+        assert!(is_random_access(&chunk));
+
+        let mut chunk = tables();
+        chunk.extend(&interframe);
+        assert!(!is_random_access(&chunk));
+
+        // decoding can not start without the tables
+        assert!(!is_random_access(&keyframe));
+        assert!(!is_random_access(&[]));
+    }
+
+    #[test]
+    fn waits_for_second_sync_byte() {
+        let mut demuxer = Demuxer::new();
+        let stream = sample();
+
+        assert!(demuxer.push(&stream[..PACKET_SIZE]).unwrap().is_empty());
+        assert_eq!(demuxer.buffer.len(), PACKET_SIZE);
+
+        demuxer.push(&stream[PACKET_SIZE..PACKET_SIZE + 1]).unwrap();
+        assert_eq!(demuxer.buffer.len(), 1);
+    }
+}
@@ -21,4 +21,7 @@ pub enum TsError {
 
     #[error("Clock reference value of {0} exceeds maximum")]
     ClockValueOutOfRange(u64),
+
+    #[error("Malformed {0}")]
+    Malformed(&'static str),
 }
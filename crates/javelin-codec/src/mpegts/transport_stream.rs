@@ -1,5 +1,5 @@
 use std::fs::File;
-use std::io::Cursor;
+use std::io::{Cursor, Write};
 use std::path::Path;
 
 use bytes::Buf;
@@ -15,9 +15,13 @@ const VIDEO_ES_PID: u16 = 257;
 const AUDIO_ES_PID: u16 = 258;
 const PES_VIDEO_STREAM_ID: u8 = 224;
 const PES_AUDIO_STREAM_ID: u8 = 192;
+/// PTS, DTS and the PCR base count ticks of a 90 kHz clock in 33 bits.
+const CLOCK_ROLLOVER: u64 = 1 << 33;
 
 
 pub struct TransportStream {
+    pat_continuity_counter: ContinuityCounter,
+    pmt_continuity_counter: ContinuityCounter,
     video_continuity_counter: ContinuityCounter,
     audio_continuity_counter: ContinuityCounter,
     packets: Vec<TsPacket>,
@@ -31,28 +35,47 @@ impl TransportStream {
     pub fn write_to_file<P>(&mut self, filename: P) -> Result<(), TsError>
     where
         P: AsRef<Path>,
+    {
+        let file = File::create(filename)?;
+        self.write_packets(file, true)?;
+        Ok(())
+    }
+
+    /// Drains all pending packets into a buffer, for sending them out as a continuous stream.
+    ///
+    /// Tables have to be repeated every now and then, so viewers can start decoding at
+    /// any point of the stream.
+    pub fn take_bytes(&mut self, with_tables: bool) -> Result<Vec<u8>, TsError> {
+        self.write_packets(Vec::new(), with_tables)
+    }
+
+    fn write_packets<W>(&mut self, stream: W, with_tables: bool) -> Result<W, TsError>
+    where
+        W: Write,
     {
         use mpeg2ts::ts::{TsPacketWriter, WriteTsPacket};
 
-        let file = File::create(filename)?;
-        let packets: Vec<_> = self.packets.drain(..).collect();
-        let mut writer = TsPacketWriter::new(file);
+        let mut writer = TsPacketWriter::new(stream);
 
-        writer
-            .write_ts_packet(&default_pat_packet())
-            .map_err(|_| TsError::WriteError)?;
+        if with_tables {
+            writer
+                .write_ts_packet(&default_pat_packet(self.pat_continuity_counter))
+                .map_err(|_| TsError::WriteError)?;
+            self.pat_continuity_counter.increment();
 
-        writer
-            .write_ts_packet(&default_pmt_packet())
-            .map_err(|_| TsError::WriteError)?;
+            writer
+                .write_ts_packet(&default_pmt_packet(self.pmt_continuity_counter))
+                .map_err(|_| TsError::WriteError)?;
+            self.pmt_continuity_counter.increment();
+        }
 
-        for packet in &packets {
+        for packet in self.packets.drain(..) {
             writer
-                .write_ts_packet(packet)
+                .write_ts_packet(&packet)
                 .map_err(|_| TsError::WriteError)?;
         }
 
-        Ok(())
+        Ok(writer.into_stream())
     }
 
     pub fn push_video(
@@ -80,7 +103,7 @@ impl TransportStream {
             };
             buf.advance(data.len());
 
-            let pcr = make_clock_reference(timestamp * 90)?;
+            let pcr = make_clock_reference(clock(timestamp))?;
 
             let adaptation_field = if keyframe {
                 Some(AdaptationField {
@@ -97,8 +120,8 @@ impl TransportStream {
                 None
             };
 
-            let pts = make_timestamp((timestamp + composition_time) * 90)?;
-            let dts = make_timestamp(timestamp * 90)?;
+            let pts = make_timestamp(clock(timestamp + composition_time))?;
+            let dts = make_timestamp(clock(timestamp))?;
 
             TsPacket {
                 header: header.clone(),
@@ -177,7 +200,7 @@ impl TransportStream {
                     data_alignment_indicator: false,
                     copyright: false,
                     original_or_copy: false,
-                    pts: Some(make_timestamp(timestamp * 90)?),
+                    pts: Some(make_timestamp(clock(timestamp))?),
                     dts: None,
                     escr: None,
                 },
@@ -219,6 +242,8 @@ impl TransportStream {
 impl Default for TransportStream {
     fn default() -> Self {
         Self {
+            pat_continuity_counter: ContinuityCounter::new(),
+            pmt_continuity_counter: ContinuityCounter::new(),
             video_continuity_counter: ContinuityCounter::new(),
             audio_continuity_counter: ContinuityCounter::new(),
             packets: Vec::new(),
@@ -232,6 +257,12 @@ fn make_raw_payload(pes_data: &[u8]) -> Result<ts::payload::Bytes, TsError> {
 }
 
 
+/// Converts milliseconds to the 90 kHz clock, which wraps around after about 26.5 hours.
+fn clock(millis: u64) -> u64 {
+    (millis * 90) % CLOCK_ROLLOVER
+}
+
+
 fn make_timestamp(ts: u64) -> Result<Timestamp, TsError> {
     Timestamp::new(ts).map_err(|_| TsError::InvalidTimestamp(ts))
 }
@@ -255,12 +286,15 @@ fn default_ts_header(pid: u16) -> Result<TsHeader, TsError> {
 }
 
 
-fn default_pat_packet() -> TsPacket {
+fn default_pat_packet(continuity_counter: ContinuityCounter) -> TsPacket {
     use mpeg2ts::ts::payload::Pat;
     use mpeg2ts::ts::{ProgramAssociation, VersionNumber};
 
     TsPacket {
-        header: default_ts_header(0).unwrap(),
+        header: TsHeader {
+            continuity_counter,
+            ..default_ts_header(0).unwrap()
+        },
         adaptation_field: None,
         payload: Some(TsPayload::Pat(Pat {
             transport_stream_id: 1,
@@ -274,13 +308,16 @@ fn default_pat_packet() -> TsPacket {
 }
 
 
-fn default_pmt_packet() -> TsPacket {
+fn default_pmt_packet(continuity_counter: ContinuityCounter) -> TsPacket {
     use mpeg2ts::es::StreamType;
     use mpeg2ts::ts::payload::Pmt;
     use mpeg2ts::ts::{EsInfo, VersionNumber};
 
     TsPacket {
-        header: default_ts_header(PMT_PID).unwrap(),
+        header: TsHeader {
+            continuity_counter,
+            ..default_ts_header(PMT_PID).unwrap()
+        },
         adaptation_field: None,
         payload: Some(TsPayload::Pmt(Pmt {
             program_num: 1,
@@ -302,3 +339,52 @@ fn default_pmt_packet() -> TsPacket {
         })),
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pes_header(packet: &TsPacket) -> &PesHeader {
+        match &packet.payload {
+            Some(TsPayload::Pes(pes)) => &pes.header,
+            _ => panic!("not a PES packet"),
+        }
+    }
+
+    #[test]
+    fn converts_to_clock_ticks() {
+        let mut stream = TransportStream::new();
+        stream.push_video(1000, 40, true, vec![0; 10]).unwrap();
+        stream.push_audio(1020, vec![0; 10]).unwrap();
+
+        let video = &stream.packets[0];
+        let pcr = video.adaptation_field.as_ref().unwrap().pcr.unwrap();
+        assert_eq!(pcr.as_u64(), 90_000);
+        assert_eq!(pes_header(video).pts.unwrap().as_u64(), 93_600);
+        assert_eq!(pes_header(video).dts.unwrap().as_u64(), 90_000);
+
+        let audio = &stream.packets[1];
+        assert_eq!(pes_header(audio).pts.unwrap().as_u64(), 91_800);
+    }
+
+    #[test]
+    fn wraps_clock_after_rollover() {
+        // first millisecond after the 90 kHz clock wrapped around
+        let timestamp = CLOCK_ROLLOVER / 90 + 1;
+        let ticks = timestamp * 90 - CLOCK_ROLLOVER;
+
+        let mut stream = TransportStream::new();
+        stream.push_video(timestamp, 40, true, vec![0; 10]).unwrap();
+        stream.push_audio(timestamp, vec![0; 10]).unwrap();
+
+        let video = &stream.packets[0];
+        let pcr = video.adaptation_field.as_ref().unwrap().pcr.unwrap();
+        assert_eq!(pcr.as_u64(), ticks);
+        assert_eq!(pes_header(video).pts.unwrap().as_u64(), ticks + 3600);
+        assert_eq!(pes_header(video).dts.unwrap().as_u64(), ticks);
+
+        let audio = &stream.packets[1];
+        assert_eq!(pes_header(audio).pts.unwrap().as_u64(), ticks);
+    }
+}
@@ -1,10 +1,34 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
 
-use anyhow::Result;
-use serde::de::Deserialize;
+use anyhow::{Context, Result};
+use config::{ConfigError, Value};
+use serde::de::{Deserialize, DeserializeOwned};
+use tokio::sync::watch;
 
 
-pub struct Config(config::Config);
+/// Settings of all services, shared between them and re-read from disk on request.
+///
+/// Services look up their section whenever they start something new, like a session or
+/// connection, so reloaded settings apply from then on.
+#[derive(Clone)]
+pub struct Config {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    path: PathBuf,
+    current: RwLock<Arc<config::Config>>,
+    sections: Mutex<Vec<Section>>,
+    reloaded: watch::Sender<()>,
+}
+
+/// A section some service reads, checked before a reloaded file replaces the current one.
+struct Section {
+    key: &'static str,
+    validate: fn(&config::Config, &str) -> Result<(), ConfigError>,
+    restart_required: &'static [&'static str],
+}
 
 impl Config {
     pub fn try_from_path<P>(config_dir: P) -> Result<Self>
@@ -12,19 +36,98 @@ impl Config {
         P: AsRef<Path>,
     {
         let path = config_dir.as_ref().join("javelin.toml");
+        let config = load(&path)?;
+        let (reloaded, _) = watch::channel(());
 
-        let config = config::Config::builder()
-            .add_source(config::File::from(path))
-            .build()?;
-
-        Ok(Self(config))
+        Ok(Self {
+            shared: Arc::new(Shared {
+                path,
+                current: RwLock::new(Arc::new(config)),
+                sections: Mutex::new(Vec::new()),
+                reloaded,
+            }),
+        })
     }
 
     pub fn get<'de, V>(&self, key: &str) -> Result<V>
     where
         V: Deserialize<'de>,
     {
-        let value = self.0.get(key)?;
+        let value = self.current().get(key)?;
         Ok(value)
     }
+
+    /// Makes sure the section still deserializes into `V` before accepting a reload.
+    ///
+    /// Changes to the listed settings, relative to the section, are reported as requiring
+    /// a restart, e.g. listener addresses.
+    pub fn register_section<V>(&self, key: &'static str, restart_required: &'static [&'static str])
+    where
+        V: DeserializeOwned,
+    {
+        let section = Section {
+            key,
+            validate: |config, key| match config.get::<V>(key) {
+                Ok(_) | Err(ConfigError::NotFound(_)) => Ok(()),
+                Err(why) => Err(why),
+            },
+            restart_required,
+        };
+
+        self.shared.sections.lock().unwrap().push(section);
+    }
+
+    /// Reads the file again and replaces the current settings, if all sections are valid.
+    ///
+    /// Returns the changed settings that only take effect after a restart.
+    pub fn reload(&self) -> Result<Vec<String>> {
+        let config = load(&self.shared.path)?;
+        let sections = self.shared.sections.lock().unwrap();
+
+        for section in sections.iter() {
+            (section.validate)(&config, section.key)
+                .with_context(|| format!("Invalid [{}] section", section.key))?;
+        }
+
+        let current = self.current();
+        let restart_required = sections
+            .iter()
+            .flat_map(|section| {
+                section
+                    .restart_required
+                    .iter()
+                    .map(move |setting| format!("{}.{}", section.key, setting))
+            })
+            .filter(|key| value_of(&current, key) != value_of(&config, key))
+            .collect();
+
+        *self.shared.current.write().unwrap() = Arc::new(config);
+        self.shared.reloaded.send_replace(());
+
+        Ok(restart_required)
+    }
+
+    /// Notifies about every successful reload from now on.
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.shared.reloaded.subscribe()
+    }
+
+    fn current(&self) -> Arc<config::Config> {
+        self.shared.current.read().unwrap().clone()
+    }
+}
+
+
+fn load(path: &Path) -> Result<config::Config> {
+    let config = config::Config::builder()
+        .add_source(config::File::from(path))
+        .build()
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    Ok(config)
+}
+
+
+fn value_of(config: &config::Config, key: &str) -> Option<config::ValueKind> {
+    config.get::<Value>(key).ok().map(|value| value.kind)
 }
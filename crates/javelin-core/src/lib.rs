@@ -1,4 +1,7 @@
 pub mod config;
+pub mod metrics;
 pub mod session;
+pub mod shutdown;
 
 pub use config::Config;
+pub use shutdown::Shutdown;
@@ -0,0 +1,375 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+
+use ::metrics::{counter, describe_counter, describe_gauge, gauge, Counter, Gauge};
+use anyhow::Result;
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use serde::Deserialize;
+
+use crate::session::Protocol;
+use crate::Config;
+
+
+const SESSIONS: &str = "javelin_sessions";
+const PUBLISHERS: &str = "javelin_publishers";
+const VIEWERS: &str = "javelin_viewers";
+const INGRESS_BYTES: &str = "javelin_ingress_bytes_total";
+const EGRESS_BYTES: &str = "javelin_egress_bytes_total";
+const AUTH_ATTEMPTS: &str = "javelin_auth_attempts_total";
+const HANDSHAKE_ERRORS: &str = "javelin_handshake_errors_total";
+const LAGGED_PACKETS: &str = "javelin_lagged_packets_total";
+const HLS_SEGMENTS_WRITTEN: &str = "javelin_hls_segments_written_total";
+const HLS_SEGMENTS_CLEANED: &str = "javelin_hls_segments_cleaned_total";
+const HLS_CLEANUP_QUEUE: &str = "javelin_hls_cleanup_queue_depth";
+
+/// Label of every application past the limit.
+const OTHER_APPS: &str = "_other";
+
+/// Label of denied authorization attempts, their application names are made up by clients.
+const DENIED_APPS: &str = "_denied";
+
+
+static EXPORTER: OnceLock<PrometheusHandle> = OnceLock::new();
+static APP_LABELS: OnceLock<AppLabels> = OnceLock::new();
+
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetricsConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// Applications that get their own label, any others are reported together.
+    #[serde(default = "default_max_apps")]
+    pub max_apps: usize,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            max_apps: default_max_apps(),
+        }
+    }
+}
+
+
+/// Starts collecting metrics, unless disabled.
+///
+/// Has to run before any service starts, everything recorded earlier is lost.
+pub fn install(config: &Config) -> Result<()> {
+    config.register_section::<MetricsConfig>("metrics", &["enabled", "max_apps"]);
+    let config: MetricsConfig = config.get("metrics").unwrap_or_default();
+
+    if !config.enabled {
+        return Ok(());
+    }
+
+    let handle = PrometheusBuilder::new().install_recorder()?;
+    let _ = APP_LABELS.set(AppLabels::new(config.max_apps));
+    let _ = EXPORTER.set(handle);
+    describe();
+
+    Ok(())
+}
+
+
+/// All metrics in the Prometheus text format, if they are collected.
+pub fn render() -> Option<String> {
+    EXPORTER.get().map(PrometheusHandle::render)
+}
+
+
+fn describe() {
+    describe_gauge!(SESSIONS, "Running sessions");
+    describe_gauge!(PUBLISHERS, "Connected publishers");
+    describe_gauge!(
+        VIEWERS,
+        "Connected viewers, recent playlist requests for HLS"
+    );
+    describe_counter!(INGRESS_BYTES, "Bytes received from publishers");
+    describe_counter!(EGRESS_BYTES, "Bytes sent to viewers");
+    describe_counter!(
+        AUTH_ATTEMPTS,
+        "Authorization decisions on publishing and playback"
+    );
+    describe_counter!(
+        HANDSHAKE_ERRORS,
+        "Connections that failed the protocol handshake"
+    );
+    describe_counter!(
+        LAGGED_PACKETS,
+        "Packets viewers missed because they fell behind"
+    );
+    describe_counter!(HLS_SEGMENTS_WRITTEN, "HLS segments written");
+    describe_counter!(
+        HLS_SEGMENTS_CLEANED,
+        "HLS segments removed after leaving the playlist"
+    );
+    describe_gauge!(HLS_CLEANUP_QUEUE, "HLS segments waiting to be removed");
+}
+
+
+/// Keeps the number of distinct application labels bounded.
+///
+/// Labels are only handed out to applications that went live, names given by clients that
+/// were not authorized can't take them up.
+struct AppLabels {
+    max_apps: usize,
+    seen: Mutex<HashSet<String>>,
+}
+
+impl AppLabels {
+    fn new(max_apps: usize) -> Self {
+        Self {
+            max_apps,
+            seen: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn get(&self, app_name: &str) -> String {
+        let seen = self.seen.lock().unwrap();
+
+        if seen.contains(app_name) {
+            return app_name.to_string();
+        }
+
+        OTHER_APPS.to_string()
+    }
+
+    fn assign(&self, app_name: &str) -> String {
+        let mut seen = self.seen.lock().unwrap();
+
+        if seen.contains(app_name) {
+            return app_name.to_string();
+        }
+
+        if seen.len() >= self.max_apps {
+            return OTHER_APPS.to_string();
+        }
+
+        seen.insert(app_name.to_string());
+        app_name.to_string()
+    }
+}
+
+
+fn app_label(app_name: &str) -> String {
+    match APP_LABELS.get() {
+        Some(labels) => labels.get(app_name),
+        None => OTHER_APPS.to_string(),
+    }
+}
+
+
+fn assign_app_label(app_name: &str) -> String {
+    match APP_LABELS.get() {
+        Some(labels) => labels.assign(app_name),
+        None => OTHER_APPS.to_string(),
+    }
+}
+
+
+/// Counts a running session and its publisher for as long as it is alive.
+pub(crate) struct SessionMetrics {
+    app_name: String,
+    sessions: Gauge,
+    ingress: Counter,
+    publisher: Option<Gauge>,
+}
+
+impl SessionMetrics {
+    pub fn new(app_name: &str, protocol: Protocol) -> Self {
+        let app_name = assign_app_label(app_name);
+        let sessions = gauge!(SESSIONS, "app" => app_name.clone());
+        sessions.increment(1.0);
+
+        let mut metrics = Self {
+            app_name,
+            sessions,
+            ingress: Counter::noop(),
+            publisher: None,
+        };
+        metrics.publisher_joined(protocol);
+        metrics
+    }
+
+    pub fn publisher_joined(&mut self, protocol: Protocol) {
+        self.publisher_left();
+
+        let labels = [
+            ("protocol", protocol.to_string()),
+            ("app", self.app_name.clone()),
+        ];
+        let publisher = gauge!(PUBLISHERS, &labels);
+        publisher.increment(1.0);
+        self.publisher = Some(publisher);
+        self.ingress = counter!(INGRESS_BYTES, &labels);
+    }
+
+    pub fn publisher_left(&mut self) {
+        if let Some(publisher) = self.publisher.take() {
+            publisher.decrement(1.0);
+        }
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.ingress.increment(bytes as u64);
+    }
+
+    pub fn viewer(&self, protocol: Protocol, internal: bool) -> ViewerMetrics {
+        let labels = [
+            ("protocol", protocol.to_string()),
+            ("app", self.app_name.clone()),
+        ];
+
+        let lagged = counter!(LAGGED_PACKETS, &labels);
+
+        // internal consumers like the HLS writer are no audience
+        if internal {
+            return ViewerMetrics {
+                viewers: None,
+                egress: Counter::noop(),
+                lagged,
+            };
+        }
+
+        let viewers = gauge!(VIEWERS, &labels);
+        viewers.increment(1.0);
+
+        ViewerMetrics {
+            viewers: Some(viewers),
+            egress: counter!(EGRESS_BYTES, &labels),
+            lagged,
+        }
+    }
+}
+
+impl Drop for SessionMetrics {
+    fn drop(&mut self) {
+        self.publisher_left();
+        self.sessions.decrement(1.0);
+    }
+}
+
+
+/// Counts a viewer for as long as it is watching, payloads it received count as egress.
+pub(crate) struct ViewerMetrics {
+    viewers: Option<Gauge>,
+    egress: Counter,
+    lagged: Counter,
+}
+
+impl ViewerMetrics {
+    pub fn delivered(&self, bytes: usize) {
+        self.egress.increment(bytes as u64);
+    }
+
+    pub fn lagged(&self, count: u64) {
+        self.lagged.increment(count);
+    }
+}
+
+impl Drop for ViewerMetrics {
+    fn drop(&mut self) {
+        if let Some(viewers) = &self.viewers {
+            viewers.decrement(1.0);
+        }
+    }
+}
+
+
+pub(crate) fn auth_attempt(action: &'static str, app_name: &str, permitted: bool) {
+    let (result, app) = match (permitted, action) {
+        (false, _) => ("denied", DENIED_APPS.to_string()),
+        // publishers presented a valid key, their application is about to go live
+        (true, "publish") => ("permitted", assign_app_label(app_name)),
+        (true, _) => ("permitted", app_label(app_name)),
+    };
+    counter!(
+        AUTH_ATTEMPTS,
+        "action" => action,
+        "result" => result,
+        "app" => app
+    )
+    .increment(1);
+}
+
+
+pub fn handshake_failed(protocol: Protocol) {
+    counter!(HANDSHAKE_ERRORS, "protocol" => protocol.to_string()).increment(1);
+}
+
+
+pub fn bytes_sent(protocol: Protocol, app_name: &str, bytes: usize) {
+    counter!(
+        EGRESS_BYTES,
+        "protocol" => protocol.to_string(),
+        "app" => app_label(app_name)
+    )
+    .increment(bytes as u64);
+}
+
+
+/// Sets the number of HLS clients that recently requested a playlist, per application.
+///
+/// Applications sharing a label are summed up, so all counts have to be given at once.
+pub fn set_hls_viewers<'a>(counts: impl IntoIterator<Item = (&'a str, usize)>) {
+    let mut per_label = HashMap::new();
+    for (app_name, count) in counts {
+        *per_label.entry(app_label(app_name)).or_insert(0) += count;
+    }
+
+    let protocol = Protocol::Hls.to_string();
+    for (label, count) in per_label {
+        gauge!(VIEWERS, "protocol" => protocol.clone(), "app" => label).set(count as f64);
+    }
+}
+
+
+pub fn hls_segment_written(app_name: &str) {
+    counter!(HLS_SEGMENTS_WRITTEN, "app" => app_label(app_name)).increment(1);
+}
+
+
+pub fn hls_cleanup_queued(count: usize) {
+    gauge!(HLS_CLEANUP_QUEUE).increment(count as f64);
+}
+
+
+pub fn hls_segments_cleaned(count: usize) {
+    gauge!(HLS_CLEANUP_QUEUE).decrement(count as f64);
+    counter!(HLS_SEGMENTS_CLEANED).increment(count as u64);
+}
+
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_max_apps() -> usize {
+    100
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assigns_labels_up_to_limit() {
+        let labels = AppLabels::new(1);
+
+        assert_eq!(labels.get("live"), OTHER_APPS);
+        assert_eq!(labels.assign("live"), "live");
+        assert_eq!(labels.get("live"), "live");
+        assert_eq!(labels.assign("other"), OTHER_APPS);
+    }
+
+    #[test]
+    fn lookups_take_no_label() {
+        let labels = AppLabels::new(1);
+
+        assert_eq!(labels.get("junk"), OTHER_APPS);
+        assert_eq!(labels.assign("live"), "live");
+    }
+}
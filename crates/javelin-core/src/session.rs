@@ -1,13 +1,150 @@
+mod config;
+mod event;
+mod gop_cache;
 mod instance;
+mod limits;
 pub mod manager;
+mod stats;
+mod timeline;
 mod transport;
+mod ts_muxer;
+mod webhook;
+
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 
-type Event = &'static str;
 type AppName = String;
+type StreamName = String;
 type StreamKey = String;
 
+
+/// Identifies a single publishing session.
+///
+/// Every session created by the manager gets a new ID, so requests from a previous publisher
+/// of the same application can be told apart from the current one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(transparent)]
+pub struct SessionId(u64);
+
+impl SessionId {
+    fn next(&mut self) -> Self {
+        let current = *self;
+        self.0 += 1;
+        current
+    }
+}
+
+impl Display for SessionId {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}", self.0)
+    }
+}
+
+
+/// Identifies a viewer within its session, e.g. to disconnect it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ViewerId(u64);
+
+impl ViewerId {
+    fn next(&mut self) -> Self {
+        let current = *self;
+        self.0 += 1;
+        current
+    }
+}
+
+impl Display for ViewerId {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}", self.0)
+    }
+}
+
+
+/// Addresses a single stream, an application can host any number of them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct StreamPath {
+    pub app_name: AppName,
+    pub stream_name: StreamName,
+}
+
+impl StreamPath {
+    pub fn new<A, S>(app_name: A, stream_name: S) -> Self
+    where
+        A: Into<AppName>,
+        S: Into<StreamName>,
+    {
+        Self {
+            app_name: app_name.into(),
+            stream_name: stream_name.into(),
+        }
+    }
+
+    /// Both names end up as directory names, e.g. for HLS, so they must be plain path segments.
+    pub fn is_valid(&self) -> bool {
+        fn is_segment(name: &str) -> bool {
+            !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+        }
+
+        is_segment(&self.app_name) && is_segment(&self.stream_name)
+    }
+}
+
+impl FromStr for StreamPath {
+    type Err = InvalidStreamPath;
+
+    /// Parses the `app/stream` form used by resource names and URLs.
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let (app_name, stream_name) = path
+            .trim_matches('/')
+            .split_once('/')
+            .ok_or_else(|| InvalidStreamPath(path.to_string()))?;
+        let path = Self::new(app_name, stream_name);
+
+        if !path.is_valid() {
+            return Err(InvalidStreamPath(path.to_string()));
+        }
+
+        Ok(path)
+    }
+}
+
+impl Display for StreamPath {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}/{}", self.app_name, self.stream_name)
+    }
+}
+
+
+#[derive(Debug, Error)]
+#[error("Invalid stream path {0}, expected app/stream")]
+pub struct InvalidStreamPath(String);
+
+
+/// Describes a running session and its publisher.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: SessionId,
+    #[serde(flatten)]
+    pub path: StreamPath,
+    pub publisher: PeerInfo,
+}
+
+
+pub use self::config::{
+    AppConfig, Config, GopCacheConfig, RepublishPolicy, ViewerConfig, WebhookConfig,
+};
+pub use self::event::{Event, EventKind, EventReceiver, PeerInfo, Protocol};
+pub use self::limits::ViewerSlot;
 pub use self::manager::Manager;
+pub use self::stats::{Stats, WatcherStats};
+pub use self::timeline::{TimelinePosition, ViewerTimeline};
 pub use self::transport::{
-    trigger_channel, Handle, ManagerHandle, ManagerMessage, Message, Watcher,
+    Admitted, Handle, InitData, JoinError, Joined, ManagerHandle, ManagerMessage, Message,
+    RecorderHandle, Recording, RecordingError, RecordingRequest, RecvError, Watcher,
 };
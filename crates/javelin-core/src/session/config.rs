@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::AppName;
+
+
+/// What to do when a publisher claims an application that is already live.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepublishPolicy {
+    /// Refuse the new publisher and keep the current one.
+    #[default]
+    Reject,
+
+    /// Disconnect the current publisher and hand the application to the new one.
+    Replace,
+
+    /// Keep the new publisher waiting until the current one releases the application.
+    Queue,
+}
+
+
+/// Limits for the group of pictures kept for newly joining viewers.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct GopCacheConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    #[serde(default = "default_gop_max_bytes")]
+    pub max_bytes: usize,
+
+    #[serde(default = "default_gop_max_duration")]
+    pub max_duration: Duration,
+}
+
+impl Default for GopCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            max_bytes: default_gop_max_bytes(),
+            max_duration: default_gop_max_duration(),
+        }
+    }
+}
+
+
+/// How packets are delivered to viewers that can not keep up with the publisher.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ViewerConfig {
+    /// Number of packets buffered per viewer.
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: usize,
+
+    /// How long a viewer may stay behind before being disconnected.
+    #[serde(default = "default_max_lag")]
+    pub max_lag: Duration,
+}
+
+impl Default for ViewerConfig {
+    fn default() -> Self {
+        Self {
+            buffer_size: default_buffer_size(),
+            max_lag: default_max_lag(),
+        }
+    }
+}
+
+
+/// External HTTP service deciding who may publish and play.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    /// Authorization is left to the user repository while unset.
+    #[serde(default)]
+    pub url: Option<String>,
+
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout: Duration,
+
+    /// Allow requests while the webhook can not be reached.
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            timeout: default_webhook_timeout(),
+            fail_open: false,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub republish: RepublishPolicy,
+
+    #[serde(default)]
+    pub gop_cache: GopCacheConfig,
+
+    #[serde(default)]
+    pub viewers: ViewerConfig,
+
+    /// How long a session outlives the unexpected loss of its publisher.
+    #[serde(default)]
+    pub grace_period: Duration,
+
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+
+    #[serde(default)]
+    pub apps: HashMap<AppName, AppConfig>,
+}
+
+impl Config {
+    pub fn republish_policy(&self, app_name: &str) -> RepublishPolicy {
+        self.apps
+            .get(app_name)
+            .and_then(|app| app.republish)
+            .unwrap_or(self.republish)
+    }
+
+    pub fn gop_cache(&self, app_name: &str) -> GopCacheConfig {
+        self.apps
+            .get(app_name)
+            .and_then(|app| app.gop_cache)
+            .unwrap_or(self.gop_cache)
+    }
+
+    pub fn viewers(&self, app_name: &str) -> ViewerConfig {
+        self.apps
+            .get(app_name)
+            .and_then(|app| app.viewers)
+            .unwrap_or(self.viewers)
+    }
+
+    pub fn grace_period(&self, app_name: &str) -> Duration {
+        self.apps
+            .get(app_name)
+            .and_then(|app| app.grace_period)
+            .unwrap_or(self.grace_period)
+    }
+}
+
+
+/// Per application overrides of the global session settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AppConfig {
+    #[serde(default)]
+    pub republish: Option<RepublishPolicy>,
+
+    #[serde(default)]
+    pub gop_cache: Option<GopCacheConfig>,
+
+    #[serde(default)]
+    pub viewers: Option<ViewerConfig>,
+
+    #[serde(default)]
+    pub grace_period: Option<Duration>,
+}
+
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_gop_max_bytes() -> usize {
+    8 * 1024 * 1024
+}
+
+fn default_gop_max_duration() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_buffer_size() -> usize {
+    64
+}
+
+fn default_max_lag() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_webhook_timeout() -> Duration {
+    Duration::from_secs(5)
+}
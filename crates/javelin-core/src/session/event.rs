@@ -0,0 +1,100 @@
+use std::fmt::{self, Display};
+use std::net::SocketAddr;
+
+use javelin_types::Metadata;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use super::{SessionId, StreamPath};
+
+
+pub type EventReceiver = broadcast::Receiver<Event>;
+pub(super) type EventSender = broadcast::Sender<Event>;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Rtmp,
+    Rtmps,
+    Srt,
+    Hls,
+    Flv,
+}
+
+impl Display for Protocol {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Rtmp => "rtmp",
+            Self::Rtmps => "rtmps",
+            Self::Srt => "srt",
+            Self::Hls => "hls",
+            Self::Flv => "flv",
+        };
+        write!(fmt, "{}", name)
+    }
+}
+
+
+/// A client attached to a session, either as publisher or as viewer.
+///
+/// Internal consumers like the HLS writer have no remote address.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerInfo {
+    pub protocol: Protocol,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addr: Option<SocketAddr>,
+}
+
+impl PeerInfo {
+    pub fn new(protocol: Protocol, addr: SocketAddr) -> Self {
+        Self {
+            protocol,
+            addr: Some(addr),
+        }
+    }
+
+    pub fn internal(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            addr: None,
+        }
+    }
+
+    /// Consumers running inside the server, not subject to authorization.
+    pub fn is_internal(&self) -> bool {
+        self.addr.is_none()
+    }
+}
+
+impl Display for PeerInfo {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.addr {
+            Some(addr) => write!(fmt, "{}://{}", self.protocol, addr),
+            None => write!(fmt, "{}://internal", self.protocol),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub enum EventKind {
+    PublishStarted,
+    PublishStopped,
+    PublisherLost,
+    PublishResumed,
+    ViewerJoined,
+    ViewerLeft,
+    MetadataUpdated(Metadata),
+    AuthFailed,
+}
+
+
+/// Session lifecycle notification, delivered to every subscriber of the manager.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    pub path: StreamPath,
+    pub session_id: Option<SessionId>,
+    pub peer: PeerInfo,
+}
@@ -0,0 +1,75 @@
+use std::collections::VecDeque;
+
+use javelin_types::Packet;
+use tracing::debug;
+
+use super::config::GopCacheConfig;
+
+
+/// Keeps every packet since the most recent keyframe, so new viewers can start decoding
+/// immediately instead of waiting for the next one.
+pub(super) struct GopCache {
+    config: GopCacheConfig,
+    packets: VecDeque<Packet>,
+    size: usize,
+}
+
+impl GopCache {
+    pub fn new(config: GopCacheConfig) -> Self {
+        Self {
+            config,
+            packets: VecDeque::new(),
+            size: 0,
+        }
+    }
+
+    pub fn push(&mut self, packet: &Packet, keyframe: bool) {
+        if !self.config.enabled {
+            return;
+        }
+
+        if keyframe {
+            self.clear();
+        } else if self.packets.is_empty() {
+            // Without a keyframe at the front the cached frames can not be decoded
+            return;
+        }
+
+        self.size += packet.payload.len();
+        self.packets.push_back(packet.clone());
+
+        if self.exceeds_limits() {
+            debug!(
+                size = self.size,
+                "GOP exceeds cache limits, waiting for next keyframe"
+            );
+            self.clear();
+        }
+    }
+
+    pub fn packets(&self) -> Vec<Packet> {
+        self.packets.iter().cloned().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.packets.clear();
+        self.size = 0;
+    }
+
+    fn exceeds_limits(&self) -> bool {
+        if self.size > self.config.max_bytes {
+            return true;
+        }
+
+        let first = self.packets.front().and_then(|p| p.timestamp);
+        let last = self.packets.back().and_then(|p| p.timestamp);
+
+        match (first, last) {
+            (Some(first), Some(last)) => {
+                let duration = u64::from(last).saturating_sub(first.into());
+                duration > self.config.max_duration.as_millis() as u64
+            }
+            _ => false,
+        }
+    }
+}
@@ -1,89 +1,267 @@
-use anyhow::Result;
-use javelin_types::{packet, Packet};
-use tracing::{error, info};
+use std::convert::TryFrom;
+use std::sync::Arc;
 
-use super::transport::{IncomingBroadcast, Message, OutgoingBroadcast};
+use javelin_codec::flv::tag::{AudioTagHeader, VideoTagHeader};
+use javelin_types::packet::{self, Container, MediaKind};
+use javelin_types::{Metadata, Packet};
+use tokio::sync::broadcast;
+use tracing::{debug, error, info};
+
+use super::config::{GopCacheConfig, ViewerConfig};
+use super::event::{Event, EventKind, EventSender, Protocol};
+use super::gop_cache::GopCache;
+use super::limits::Usage;
+use super::stats::StatsCollector;
+use super::timeline::Timeline;
+use super::transport::{IncomingBroadcast, InitData, Message, OutgoingBroadcast, Watcher};
+use super::ts_muxer::TsMuxer;
+use super::{PeerInfo, SessionInfo};
+use crate::metrics::SessionMetrics;
 
 
 pub struct Session {
+    info: SessionInfo,
     incoming: IncomingBroadcast,
     outgoing: OutgoingBroadcast,
+    events: EventSender,
     metadata: Option<Packet>,
     video_seq_header: Option<Packet>,
     audio_seq_header: Option<Packet>,
+    gop_cache: GopCache,
+    timeline: Timeline,
+    ts_muxer: Option<TsMuxer>,
+    stats: StatsCollector,
+    metrics: SessionMetrics,
+    usage: Arc<Usage>,
+    viewers: ViewerConfig,
     closing: bool,
 }
 
 impl Session {
     #[allow(clippy::new_without_default)]
-    pub fn new(incoming: IncomingBroadcast, outgoing: OutgoingBroadcast) -> Self {
+    pub fn new(
+        info: SessionInfo,
+        incoming: IncomingBroadcast,
+        outgoing: OutgoingBroadcast,
+        events: EventSender,
+        gop_cache: GopCacheConfig,
+        viewers: ViewerConfig,
+        usage: Arc<Usage>,
+    ) -> Self {
+        let metrics = SessionMetrics::new(&info.path.app_name, info.publisher.protocol);
+
         Self {
+            info,
             incoming,
             outgoing,
+            events,
             metadata: None,
             video_seq_header: None,
             audio_seq_header: None,
+            gop_cache: GopCache::new(gop_cache),
+            timeline: Timeline::new(),
+            ts_muxer: None,
+            stats: StatsCollector::new(),
+            metrics,
+            usage,
+            viewers,
             closing: false,
         }
     }
 
     pub async fn run(mut self) {
         while !self.closing {
-            if let Some(message) = self.incoming.recv().await {
-                self.handle_message(message);
+            match self.incoming.recv().await {
+                Some(message) => self.handle_message(message),
+                None => self.closing = true,
             }
         }
+
+        self.emit(EventKind::PublishStopped, self.info.publisher.clone());
     }
 
     fn handle_message(&mut self, message: Message) {
         match message {
-            Message::Packet(packet) => {
-                self.set_cache(&packet)
-                    .expect("Failed to set session cache");
+            Message::Packet(mut packet) => {
+                packet.timestamp = packet.timestamp.map(|ts| self.timeline.normalize(ts));
+                self.stats.record(&packet);
+                self.metrics.received(packet.payload.len());
+                self.usage.received(packet.payload.len());
+                self.set_cache(&packet);
+                self.mux_packet(&packet);
                 self.broadcast_packet(packet);
             }
-            Message::GetInitData(responder) => {
-                let response = (
-                    self.metadata.clone(),
-                    self.video_seq_header.clone(),
-                    self.audio_seq_header.clone(),
+            Message::Subscribe(peer, viewer_slot, responder) => {
+                // SRT viewers get the transport stream, which starts at the next keyframe
+                let (init_data, receiver) = match peer.protocol {
+                    Protocol::Srt => (InitData::default(), self.subscribe_ts()),
+                    _ => {
+                        let init_data = InitData {
+                            metadata: self.metadata.clone(),
+                            video_seq_header: self.video_seq_header.clone(),
+                            audio_seq_header: self.audio_seq_header.clone(),
+                            gop: self.gop_cache.packets(),
+                        };
+                        (init_data, self.outgoing.subscribe())
+                    }
+                };
+                let leave_event = self.event(EventKind::ViewerLeft, peer.clone());
+                let counters = self.stats.add_watcher(peer.clone());
+                let metrics = self.metrics.viewer(peer.protocol, peer.is_internal());
+                let watcher = Watcher::new(
+                    receiver,
+                    self.events.clone(),
+                    leave_event,
+                    counters,
+                    metrics,
+                    viewer_slot,
+                    self.viewers,
+                    peer.is_internal(),
                 );
-                if responder.send(response).is_err() {
+                if responder.send(Ok((init_data, watcher))).is_err() {
                     error!("Failed to send init data");
+                    return;
+                }
+                self.emit(EventKind::ViewerJoined, peer);
+            }
+            Message::QueryStats(responder) => {
+                let metadata = self
+                    .metadata
+                    .clone()
+                    .and_then(|packet| Metadata::try_from(packet).ok());
+                let stats = self.stats.snapshot(&self.info, metadata);
+                if responder.send(stats).is_err() {
+                    error!("Failed to send session statistics");
                 }
             }
+            Message::DisconnectViewer(id, responder) => {
+                let found = self.stats.disconnect_watcher(id);
+                if found {
+                    info!(session_id = %self.info.id, "Disconnecting viewer {}", id);
+                }
+                if responder.send(found).is_err() {
+                    error!("Failed to send disconnect result");
+                }
+            }
+            Message::PublisherLost => {
+                // frames from before the gap are useless to anyone joining afterwards
+                self.gop_cache.clear();
+                self.metrics.publisher_left();
+                self.emit(EventKind::PublisherLost, self.info.publisher.clone());
+            }
+            Message::PublisherResumed(peer) => {
+                // viewers see the restart as a jump back, like before
+                self.timeline.restart();
+                self.metrics.publisher_joined(peer.protocol);
+                self.info.publisher = peer;
+                self.emit(EventKind::PublishResumed, self.info.publisher.clone());
+            }
             Message::Disconnect => {
                 self.closing = true;
             }
         }
     }
 
+    /// Starts muxing the transport stream when the first viewer needs it.
+    fn subscribe_ts(&mut self) -> broadcast::Receiver<Packet> {
+        if let Some(ts_muxer) = &self.ts_muxer {
+            return ts_muxer.subscribe();
+        }
+
+        debug!(session_id = %self.info.id, "Starting transport stream muxer");
+        let mut ts_muxer = TsMuxer::new(self.viewers.buffer_size);
+        for packet in self.video_seq_header.iter().chain(&self.audio_seq_header) {
+            if let Err(why) = ts_muxer.push(packet) {
+                error!("Failed to prepare transport stream muxer: {}", why);
+            }
+        }
+
+        let receiver = ts_muxer.subscribe();
+        self.ts_muxer = Some(ts_muxer);
+        receiver
+    }
+
+    fn mux_packet(&mut self, packet: &Packet) {
+        let Some(ts_muxer) = &mut self.ts_muxer else {
+            return;
+        };
+
+        if ts_muxer.is_idle() {
+            debug!(session_id = %self.info.id, "Stopping transport stream muxer");
+            self.ts_muxer = None;
+            return;
+        }
+
+        if let Err(why) = ts_muxer.push(packet) {
+            error!("Failed to mux packet: {}", why);
+        }
+    }
+
     fn broadcast_packet(&self, packet: Packet) {
         if self.outgoing.receiver_count() != 0 && self.outgoing.send(packet).is_err() {
             error!("Failed to broadcast packet");
         }
     }
 
-    fn set_cache(&mut self, packet: &Packet) -> Result<()> {
-        match packet.content_type {
-            packet::METADATA if self.metadata.is_none() => {
-                self.metadata = Some(packet.clone());
+    fn set_cache(&mut self, packet: &Packet) {
+        if packet.content_type.container == Container::Flv {
+            match packet.content_type.kind() {
+                MediaKind::Video => self.cache_video(packet),
+                MediaKind::Audio => self.cache_audio(packet),
+                _ => (),
             }
-            packet::FLV_VIDEO_H264 if self.video_seq_header.is_none() => {
+            return;
+        }
+
+        if packet.content_type == packet::METADATA {
+            self.metadata = Some(packet.clone());
+            match Metadata::try_from(packet.clone()) {
+                Ok(metadata) => {
+                    let kind = EventKind::MetadataUpdated(metadata);
+                    self.emit(kind, self.info.publisher.clone());
+                }
+                Err(why) => error!("Failed to read metadata: {}", why),
+            }
+        }
+    }
+
+    fn cache_video(&mut self, packet: &Packet) {
+        match VideoTagHeader::try_from(&*packet.payload) {
+            Ok(header) if header.is_sequence_header() => {
                 self.video_seq_header = Some(packet.clone());
             }
-            packet::FLV_AUDIO_AAC if self.audio_seq_header.is_none() => {
+            Ok(header) => self.gop_cache.push(packet, header.is_keyframe()),
+            Err(why) => error!("Failed to read video tag header: {}", why),
+        }
+    }
+
+    fn cache_audio(&mut self, packet: &Packet) {
+        match AudioTagHeader::try_from(&*packet.payload) {
+            Ok(header) if header.is_sequence_header() => {
                 self.audio_seq_header = Some(packet.clone());
             }
-            _ => (),
+            Ok(_) => self.gop_cache.push(packet, false),
+            Err(why) => error!("Failed to read audio tag header: {}", why),
         }
+    }
+
+    fn event(&self, kind: EventKind, peer: PeerInfo) -> Event {
+        Event {
+            kind,
+            path: self.info.path.clone(),
+            session_id: Some(self.info.id),
+            peer,
+        }
+    }
 
-        Ok(())
+    fn emit(&self, kind: EventKind, peer: PeerInfo) {
+        // nobody listening for events is not an error
+        let _ = self.events.send(self.event(kind, peer));
     }
 }
 
 impl Drop for Session {
     fn drop(&mut self) {
-        info!("Closing session");
+        info!(session_id = %self.info.id, "Closing session {}", self.info.path);
     }
 }
@@ -0,0 +1,150 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use javelin_types::models::Limits;
+use thiserror::Error;
+
+
+/// How often running sessions are checked against the limits of their owner.
+pub(super) const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time span the ingest bitrate is averaged over, so keyframes don't count as bursts.
+const BITRATE_WINDOW: Duration = Duration::from_secs(5);
+
+
+#[derive(Debug, Error)]
+pub(super) enum LimitExceeded {
+    #[error("Reached the limit of {0} concurrent streams")]
+    Sessions(u32),
+
+    #[error("Reached the limit of {0} viewers")]
+    Viewers(u32),
+
+    #[error("Ingest bitrate of {0} bit/s is above the limit of {1} bit/s")]
+    Bitrate(u64, u64),
+
+    #[error("Reached the maximum stream duration of {0:?}")]
+    Duration(Duration),
+}
+
+
+/// Counters a session shares with the manager, which compares them against its limits.
+#[derive(Debug, Default)]
+pub(super) struct Usage {
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    viewers: AtomicUsize,
+    peak_viewers: AtomicUsize,
+}
+
+impl Usage {
+    pub fn received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Counts the viewer for as long as the returned slot is kept.
+    pub fn join(self: &Arc<Self>) -> ViewerSlot {
+        let viewers = self.viewers.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_viewers.fetch_max(viewers, Ordering::Relaxed);
+        ViewerSlot(self.clone())
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn peak_viewers(&self) -> u64 {
+        self.peak_viewers.load(Ordering::Relaxed) as u64
+    }
+}
+
+
+/// Place of a viewer counted against [`Limits::max_viewers`].
+#[derive(Debug)]
+pub struct ViewerSlot(Arc<Usage>);
+
+impl ViewerSlot {
+    /// Adds to the bytes sent out over the session.
+    pub fn delivered(&self, bytes: usize) {
+        self.0.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+impl Drop for ViewerSlot {
+    fn drop(&mut self) {
+        self.0.viewers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+
+/// Tracks a single session against the limits that applied when it started.
+pub(super) struct Quota {
+    limits: Limits,
+    usage: Arc<Usage>,
+    started_at: Instant,
+    checked_at: Instant,
+    checked_bytes: u64,
+}
+
+impl Quota {
+    pub fn new(limits: Limits) -> Self {
+        let now = Instant::now();
+
+        Self {
+            limits,
+            usage: Arc::new(Usage::default()),
+            started_at: now,
+            checked_at: now,
+            checked_bytes: 0,
+        }
+    }
+
+    pub fn usage(&self) -> Arc<Usage> {
+        self.usage.clone()
+    }
+
+    /// Reserves a place for another viewer, if there is one left.
+    pub fn join(&self) -> Result<ViewerSlot, LimitExceeded> {
+        if let Some(max_viewers) = self.limits.max_viewers {
+            if self.usage.viewers.load(Ordering::Relaxed) >= max_viewers as usize {
+                return Err(LimitExceeded::Viewers(max_viewers));
+            }
+        }
+
+        Ok(self.usage.join())
+    }
+
+    /// Compares the duration so far, and the bitrate once enough time has passed to tell.
+    pub fn check(&mut self, now: Instant) -> Result<(), LimitExceeded> {
+        if let Some(max_duration) = self.limits.max_duration {
+            if now.duration_since(self.started_at) >= max_duration {
+                return Err(LimitExceeded::Duration(max_duration));
+            }
+        }
+
+        let elapsed = now.duration_since(self.checked_at);
+        if elapsed < BITRATE_WINDOW {
+            return Ok(());
+        }
+
+        let bytes = self.usage.bytes_received();
+        let received = bytes - self.checked_bytes;
+        self.checked_at = now;
+        self.checked_bytes = bytes;
+
+        if let Some(max_bitrate) = self.limits.max_bitrate {
+            let bitrate = (received as f64 * 8.0 / elapsed.as_secs_f64()) as u64;
+            if bitrate > max_bitrate {
+                return Err(LimitExceeded::Bitrate(bitrate, max_bitrate));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,45 +1,155 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Display};
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::{bail, Result};
-use javelin_types::models::UserRepository;
-use tokio::sync::{broadcast, mpsc, RwLock};
-use tracing::{debug, error};
+use chrono::{DateTime, Utc};
+use javelin_types::models::{Limits, SessionRecord, UserRepository};
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::{broadcast, mpsc, oneshot, watch, RwLock};
+use tracing::{debug, error, info, warn};
 
+use super::config::{Config as SessionConfig, RepublishPolicy};
+use super::event::{Event, EventKind, EventSender};
 use super::instance::Session;
+use super::limits::{self, LimitExceeded, Quota};
 use super::transport::{
-    Handle, ManagerHandle, ManagerMessage, ManagerReceiver, OutgoingBroadcast, Trigger,
+    Handle, JoinError, ManagerHandle, ManagerMessage, ManagerReceiver, Message, RecorderHandle,
+    RecordingError, RecordingRequest, Responder,
 };
-use super::{AppName, Event};
+use super::webhook::{Action, Webhook};
+use super::{PeerInfo, SessionId, SessionInfo, StreamKey, StreamPath};
+use crate::{metrics, Config};
+
+
+const EVENT_CAPACITY: usize = 256;
+
+
+type Standby = VecDeque<(StreamKey, PeerInfo, Responder<(SessionId, Handle)>)>;
+
+/// Grace period of a session that lost its publisher at the given instant ran out.
+type GraceExpired = (StreamPath, SessionId, Instant);
+
+/// A publish or play request returning with its decision, made outside the manager loop.
+type Authorized = (ManagerMessage, Result<()>);
+
+
+struct Entry {
+    id: SessionId,
+    key: StreamKey,
+    publisher: PeerInfo,
+    handle: Handle,
+    standby: Standby,
+    lost_at: Option<Instant>,
+    quota: Quota,
+    started_at: DateTime<Utc>,
+}
+
+impl Entry {
+    fn record(&self, path: &StreamPath, reason: EndReason) -> SessionRecord {
+        let usage = self.quota.usage();
+
+        SessionRecord {
+            app_name: path.app_name.to_string(),
+            stream_name: path.stream_name.to_string(),
+            protocol: self.publisher.protocol.to_string(),
+            client_ip: self.publisher.addr.map(|addr| addr.ip().to_string()),
+            started_at: self.started_at,
+            ended_at: Utc::now(),
+            bytes_in: usage.bytes_received(),
+            bytes_out: usage.bytes_sent(),
+            peak_viewers: usage.peak_viewers(),
+            end_reason: reason.to_string(),
+        }
+    }
+}
+
+
+/// Why a session was closed, kept in its history record.
+#[derive(Debug)]
+enum EndReason {
+    Finished,
+    Lost,
+    NotReturned,
+    Replaced,
+    Terminated,
+    Limit(LimitExceeded),
+    Shutdown,
+}
+
+impl Display for EndReason {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Finished => write!(fmt, "Publisher finished"),
+            Self::Lost => write!(fmt, "Lost connection to publisher"),
+            Self::NotReturned => write!(fmt, "Publisher did not return"),
+            Self::Replaced => write!(fmt, "Replaced by another publisher"),
+            Self::Terminated => write!(fmt, "Terminated by an operator"),
+            Self::Limit(why) => write!(fmt, "{}", why),
+            Self::Shutdown => write!(fmt, "Server shut down"),
+        }
+    }
+}
 
 
 pub struct Manager<D>
 where
-    D: UserRepository + Send + Sync + 'static,
+    D: UserRepository + Clone + Send + Sync + 'static,
 {
     handle: ManagerHandle,
     incoming: ManagerReceiver,
+    events: EventSender,
     user_repo: D,
-    sessions: Arc<RwLock<HashMap<AppName, (Handle, OutgoingBroadcast)>>>,
-    triggers: Arc<RwLock<HashMap<Event, Vec<Trigger>>>>,
+    webhook: Option<Webhook>,
+    config: SessionConfig,
+    source: Config,
+    reloaded: watch::Receiver<()>,
+    next_id: SessionId,
+    sessions: Arc<RwLock<HashMap<StreamPath, Entry>>>,
+    grace_timers: mpsc::UnboundedSender<GraceExpired>,
+    expired: mpsc::UnboundedReceiver<GraceExpired>,
+    authorizations: mpsc::UnboundedSender<Authorized>,
+    authorized: mpsc::UnboundedReceiver<Authorized>,
+    recorder: Option<RecorderHandle>,
+    shutting_down: bool,
 }
 
 impl<D> Manager<D>
 where
-    D: UserRepository + Send + Sync + 'static,
+    D: UserRepository + Clone + Send + Sync + 'static,
 {
-    pub fn new(user_repo: D) -> Self {
-        let (handle, incoming) = mpsc::unbounded_channel();
+    pub fn new(user_repo: D, config: &Config) -> Result<Self> {
+        let (sender, incoming) = mpsc::unbounded_channel();
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let handle = ManagerHandle::new(sender, events.clone());
         let sessions = Arc::new(RwLock::new(HashMap::new()));
-        let triggers = Arc::new(RwLock::new(HashMap::new()));
+        let (grace_timers, expired) = mpsc::unbounded_channel();
+        let (authorizations, authorized) = mpsc::unbounded_channel();
+        let source = config.clone();
+        let reloaded = config.subscribe();
+        config.register_section::<SessionConfig>("session", &[]);
+        let config: SessionConfig = config.get("session").unwrap_or_default();
+        let webhook = Webhook::new(&config.webhook)?;
 
-        Self {
+        Ok(Self {
             handle,
             incoming,
+            events,
             sessions,
-            triggers,
             user_repo,
-        }
+            webhook,
+            config,
+            source,
+            reloaded,
+            next_id: SessionId::default(),
+            grace_timers,
+            expired,
+            authorizations,
+            authorized,
+            recorder: None,
+            shutting_down: false,
+        })
     }
 
     pub fn handle(&self) -> ManagerHandle {
@@ -48,70 +158,623 @@ where
 
     async fn process_message(&mut self, message: ManagerMessage) -> Result<()> {
         match message {
-            ManagerMessage::CreateSession((name, key, responder)) => {
-                self.authenticate(&name, &key).await?;
-                let (handle, incoming) = mpsc::unbounded_channel();
-                let (outgoing, _watcher) = broadcast::channel(64);
-                let mut sessions = self.sessions.write().await;
-                sessions.insert(name.clone(), (handle.clone(), outgoing.clone()));
+            ManagerMessage::CreateSession((path, ..)) if self.shutting_down => {
+                bail!("Not accepting publisher for {} during shutdown", path);
+            }
+            ManagerMessage::JoinSession((path, _, _, responder)) if self.shutting_down => {
+                // the viewer is gone already if this fails
+                let _ = responder.send(Err(JoinError::Unavailable));
+                bail!("Not accepting viewer for {} during shutdown", path);
+            }
+            message @ (ManagerMessage::CreateSession(_)
+            | ManagerMessage::JoinSession(_)
+            | ManagerMessage::AuthorizeViewer(_)) => {
+                self.authorize(message);
+            }
+            ManagerMessage::QueryStats((app_name, responder)) => {
+                let sessions = self.sessions.read().await;
+                let pending = sessions
+                    .iter()
+                    .filter(|(path, _)| app_name.as_ref().is_none_or(|name| *name == path.app_name))
+                    .filter_map(|(_, entry)| {
+                        let (request, response) = oneshot::channel();
+                        entry.handle.send(Message::QueryStats(request)).ok()?;
+                        Some(response)
+                    })
+                    .collect::<Vec<_>>();
 
-                let triggers = self.triggers.read().await;
-                if let Some(event_triggers) = triggers.get("create_session") {
-                    for trigger in event_triggers {
-                        trigger.send((name.clone(), outgoing.subscribe()))?;
+                // sessions answer on their own, don't hold up other requests meanwhile
+                tokio::spawn(async move {
+                    let mut stats = Vec::with_capacity(pending.len());
+                    for response in pending {
+                        if let Ok(session_stats) = response.await {
+                            stats.push(session_stats);
+                        }
                     }
-                }
+                    if responder.send(stats).is_err() {
+                        error!("Failed to send session statistics");
+                    }
+                });
+            }
+            ManagerMessage::QuerySession((path, responder)) => {
+                let response = {
+                    let sessions = self.sessions.read().await;
+                    sessions.get(&path).and_then(|entry| {
+                        let (request, response) = oneshot::channel();
+                        entry.handle.send(Message::QueryStats(request)).ok()?;
+                        Some(response)
+                    })
+                };
 
                 tokio::spawn(async move {
-                    Session::new(incoming, outgoing).run().await;
+                    let stats = match response {
+                        Some(response) => response.await.ok(),
+                        None => None,
+                    };
+                    if responder.send(stats).is_err() {
+                        error!("Failed to send session statistics");
+                    }
                 });
+            }
+            ManagerMessage::TerminateSession((path, responder)) => {
+                let id = self.sessions.read().await.get(&path).map(|entry| entry.id);
+
+                if let Some(id) = id {
+                    info!(session_id = %id, "Terminating session {}", path);
+                    self.release_session(path, id, EndReason::Terminated)
+                        .await?;
+                }
 
-                if responder.send(handle).is_err() {
-                    bail!("Failed to send response");
+                if responder.send(id.is_some()).is_err() {
+                    error!("Failed to send termination result");
                 }
             }
-            ManagerMessage::JoinSession((name, responder)) => {
+            ManagerMessage::DisconnectViewer((path, viewer_id, responder)) => {
                 let sessions = self.sessions.read().await;
-                if let Some((handle, watcher)) = sessions.get(&name) {
-                    if responder
-                        .send((handle.clone(), watcher.subscribe()))
+                match sessions.get(&path) {
+                    Some(entry) => {
+                        let message = Message::DisconnectViewer(viewer_id, responder);
+                        if entry.handle.send(message).is_err() {
+                            bail!("Failed to forward disconnect request to session");
+                        }
+                    }
+                    None => {
+                        if responder.send(false).is_err() {
+                            error!("Failed to send disconnect result");
+                        }
+                    }
+                }
+            }
+            ManagerMessage::RequestRecording((path, record, responder)) => {
+                let session_id = self.sessions.read().await.get(&path).map(|entry| entry.id);
+                let Some(session_id) = session_id else {
+                    if responder.send(Err(RecordingError::NotLive)).is_err() {
+                        error!("Failed to send recording result");
+                    }
+                    return Ok(());
+                };
+
+                let request = RecordingRequest {
+                    path,
+                    session_id,
+                    record,
+                    responder,
+                };
+                let unanswered = match &self.recorder {
+                    Some(recorder) => recorder
+                        .send(request)
+                        .err()
+                        .map(|SendError(request)| request),
+                    None => Some(request),
+                };
+
+                // recorder not compiled in or already gone
+                if let Some(request) = unanswered {
+                    if request
+                        .responder
+                        .send(Err(RecordingError::Unavailable))
                         .is_err()
                     {
-                        bail!("Failed to send response");
+                        error!("Failed to send recording result");
                     }
                 }
             }
-            ManagerMessage::ReleaseSession(name) => {
+            ManagerMessage::RegisterRecorder(recorder) => {
+                self.recorder = Some(recorder);
+            }
+            ManagerMessage::ReleaseSession((path, id)) => {
+                self.release_session(path, id, EndReason::Finished).await?;
+            }
+            ManagerMessage::PublisherLost((path, id)) => {
+                let grace_period = self.config.grace_period(&path.app_name);
+                if grace_period.is_zero() || self.shutting_down {
+                    return self.release_session(path, id, EndReason::Lost).await;
+                }
+
                 let mut sessions = self.sessions.write().await;
-                sessions.remove(&name);
+                let Some(entry) = sessions.get_mut(&path).filter(|entry| entry.id == id) else {
+                    debug!(session_id = %id, "Ignoring loss of stale session");
+                    return Ok(());
+                };
+
+                info!(
+                    session_id = %id,
+                    "Lost publisher of {}, waiting {:?} for it to return", path, grace_period
+                );
+                let lost_at = Instant::now();
+                entry.lost_at = Some(lost_at);
+                if entry.handle.send(Message::PublisherLost).is_err() {
+                    debug!("Session already closed");
+                }
+
+                let grace_timers = self.grace_timers.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(grace_period).await;
+                    // manager is gone, nothing left to expire
+                    let _ = grace_timers.send((path, id, lost_at));
+                });
+            }
+            ManagerMessage::Shutdown => {
+                self.shut_down().await;
             }
-            ManagerMessage::RegisterTrigger(event, trigger) => {
-                debug!("Registering trigger for {}", event);
-                let mut triggers = self.triggers.write().await;
-                triggers.entry(event).or_insert_with(Vec::new).push(trigger);
+        }
+
+        Ok(())
+    }
+
+    /// Carries on with a request once its authorization is decided.
+    async fn complete(&mut self, message: ManagerMessage, result: Result<()>) -> Result<()> {
+        match message {
+            ManagerMessage::CreateSession((path, key, peer, responder)) => {
+                metrics::auth_attempt("publish", &path.app_name, result.is_ok());
+                if let Err(why) = result {
+                    self.emit(EventKind::AuthFailed, &path, None, peer);
+                    return Err(why);
+                }
+
+                // shutdown may have started while waiting for the decision
+                if self.shutting_down {
+                    bail!("Not accepting publisher for {} during shutdown", path);
+                }
+
+                let limits = self.user_repo.limits_of(&path.app_name).await?;
+
+                let mut replaced = None;
+                let standby = {
+                    let mut sessions = self.sessions.write().await;
+                    match sessions.get_mut(&path) {
+                        Some(entry) if entry.lost_at.is_some() && entry.key == key => {
+                            info!(
+                                session_id = %entry.id,
+                                "{} resumed publishing to {}", peer, path
+                            );
+                            entry.lost_at = None;
+                            entry.publisher = peer.clone();
+                            if entry.handle.send(Message::PublisherResumed(peer)).is_err() {
+                                bail!("Failed to resume session");
+                            }
+                            if responder.send((entry.id, entry.handle.clone())).is_err() {
+                                bail!("Failed to send response");
+                            }
+                            return Ok(());
+                        }
+                        Some(entry) => match self.config.republish_policy(&path.app_name) {
+                            RepublishPolicy::Reject => {
+                                bail!("Stream {} is already being published", path);
+                            }
+                            RepublishPolicy::Replace => {
+                                info!(session_id = %entry.id, "Replacing publisher of {}", path);
+                                let mut entry = sessions.remove(&path).unwrap();
+                                let standby = std::mem::take(&mut entry.standby);
+                                replaced = Some(entry);
+                                standby
+                            }
+                            RepublishPolicy::Queue => {
+                                info!(session_id = %entry.id, "Queueing publisher for {}", path);
+                                entry.standby.push_back((key, peer, responder));
+                                return Ok(());
+                            }
+                        },
+                        None => {
+                            // replacing or queueing publishers doesn't add to the running streams
+                            if let Some(max_sessions) = limits.max_sessions {
+                                let running = sessions
+                                    .keys()
+                                    .filter(|running| running.app_name == path.app_name)
+                                    .count();
+                                if running >= max_sessions as usize {
+                                    let why = LimitExceeded::Sessions(max_sessions);
+                                    bail!("Rejected {} publishing to {}: {}", peer, path, why);
+                                }
+                            }
+                            Standby::new()
+                        }
+                    }
+                };
+
+                if let Some(entry) = replaced {
+                    self.end_session(&path, &entry, EndReason::Replaced).await;
+                }
+
+                self.start_session(path, key, peer, responder, standby, limits)
+                    .await?;
+            }
+            ManagerMessage::JoinSession((path, _, peer, responder)) => {
+                if !peer.is_internal() {
+                    metrics::auth_attempt("play", &path.app_name, result.is_ok());
+                }
+                if let Err(why) = result {
+                    let _ = responder.send(Err(JoinError::NotAuthorized));
+                    self.emit(EventKind::AuthFailed, &path, None, peer);
+                    return Err(why);
+                }
+
+                if self.shutting_down {
+                    let _ = responder.send(Err(JoinError::Unavailable));
+                    bail!("Not accepting viewer for {} during shutdown", path);
+                }
+
+                let sessions = self.sessions.read().await;
+                let Some(entry) = sessions.get(&path) else {
+                    let _ = responder.send(Err(JoinError::NotLive));
+                    return Ok(());
+                };
+
+                let viewer_slot = if peer.is_internal() {
+                    None
+                } else {
+                    match entry.quota.join() {
+                        Ok(viewer_slot) => Some(viewer_slot),
+                        Err(why) => {
+                            let _ = responder.send(Err(JoinError::Unavailable));
+                            bail!("Rejected {} watching {}: {}", peer, path, why);
+                        }
+                    }
+                };
+
+                if entry
+                    .handle
+                    .send(Message::Subscribe(peer, viewer_slot, responder))
+                    .is_err()
+                {
+                    bail!("Failed to forward join request to session");
+                }
+            }
+            ManagerMessage::AuthorizeViewer((path, _, peer, responder)) => {
+                metrics::auth_attempt("play", &path.app_name, result.is_ok());
+                if let Err(why) = &result {
+                    debug!("{}", why);
+                    let _ = responder.send(Err(JoinError::NotAuthorized));
+                    self.emit(EventKind::AuthFailed, &path, None, peer);
+                    return Ok(());
+                }
+
+                // files of a stream that just ended can still be served without a slot
+                let admitted = match self.sessions.read().await.get(&path) {
+                    Some(entry) => entry.quota.join().map(Some).map_err(|why| {
+                        debug!("Rejected {} watching {}: {}", peer, path, why);
+                        JoinError::Unavailable
+                    }),
+                    None => Ok(None),
+                };
+
+                if responder.send(admitted).is_err() {
+                    error!("Failed to send authorization result");
+                }
             }
+            // nothing else is sent out for authorization
+            _ => (),
         }
 
         Ok(())
     }
 
     pub async fn run(mut self) {
-        while let Some(message) = self.incoming.recv().await {
-            if let Err(err) = self.process_message(message).await {
+        let mut limit_checks = tokio::time::interval(limits::CHECK_INTERVAL);
+
+        loop {
+            let result = tokio::select! {
+                message = self.incoming.recv() => match message {
+                    Some(message) => self.process_message(message).await,
+                    None => break,
+                },
+                Some((path, id, lost_at)) = self.expired.recv() => {
+                    self.expire_session(path, id, lost_at).await
+                }
+                Some((message, result)) = self.authorized.recv() => {
+                    self.complete(message, result).await
+                }
+                Ok(()) = self.reloaded.changed() => self.reload_config(),
+                _ = limit_checks.tick() => self.enforce_limits().await,
+            };
+
+            if let Err(err) = result {
                 error!("{}", err);
             };
         }
     }
 
-    async fn authenticate(&self, app_name: &str, stream_key: &str) -> Result<()> {
-        if stream_key.is_empty() {
-            bail!("Stream key can not be empty");
+    /// Picks up reloaded settings, running sessions keep the ones they were started with.
+    fn reload_config(&mut self) -> Result<()> {
+        let config: SessionConfig = self.source.get("session").unwrap_or_default();
+        self.webhook = Webhook::new(&config.webhook)?;
+        self.config = config;
+        info!("Reloaded session settings");
+        Ok(())
+    }
+
+    async fn start_session(
+        &mut self,
+        path: StreamPath,
+        key: StreamKey,
+        publisher: PeerInfo,
+        responder: Responder<(SessionId, Handle)>,
+        standby: Standby,
+        limits: Limits,
+    ) -> Result<()> {
+        let id = self.next_id.next();
+        let (handle, incoming) = mpsc::unbounded_channel();
+        let viewers = self.config.viewers(&path.app_name);
+        let (outgoing, _watcher) = broadcast::channel(viewers.buffer_size);
+        let quota = Quota::new(limits);
+        let usage = quota.usage();
+        let mut sessions = self.sessions.write().await;
+        sessions.insert(path.clone(), Entry {
+            id,
+            key,
+            publisher: publisher.clone(),
+            handle: handle.clone(),
+            standby,
+            lost_at: None,
+            quota,
+            started_at: Utc::now(),
+        });
+
+        let info = SessionInfo {
+            id,
+            path: path.clone(),
+            publisher: publisher.clone(),
+        };
+        let events = self.events.clone();
+        let gop_cache = self.config.gop_cache(&path.app_name);
+        tokio::spawn(async move {
+            Session::new(info, incoming, outgoing, events, gop_cache, viewers, usage)
+                .run()
+                .await;
+        });
+
+        if responder.send((id, handle)).is_err() {
+            bail!("Failed to send response");
+        }
+
+        info!(session_id = %id, "{} started publishing to {}", publisher, path);
+        self.emit(EventKind::PublishStarted, &path, Some(id), publisher);
+
+        Ok(())
+    }
+
+    async fn release_session(
+        &mut self,
+        path: StreamPath,
+        id: SessionId,
+        reason: EndReason,
+    ) -> Result<()> {
+        let entry = {
+            let mut sessions = self.sessions.write().await;
+            match sessions.get(&path) {
+                Some(entry) if entry.id == id => sessions.remove(&path),
+                _ => None,
+            }
+        };
+
+        let Some(entry) = entry else {
+            debug!(session_id = %id, "Ignoring release of stale session");
+            return Ok(());
+        };
+
+        self.end_session(&path, &entry, reason).await;
+
+        self.promote_standby(path, entry.standby).await
+    }
+
+    /// Closes every session, viewers receive what is left in their buffers before they are let go.
+    async fn shut_down(&mut self) {
+        self.shutting_down = true;
+
+        let entries: Vec<_> = self.sessions.write().await.drain().collect();
+        info!("Closing {} sessions for shutdown", entries.len());
+
+        for (path, entry) in entries {
+            // queued publishers learn about it from their dropped responders
+            self.end_session(&path, &entry, EndReason::Shutdown).await;
+        }
+    }
+
+    /// Disconnects the publisher of a removed session, tells the webhook and records the session.
+    async fn end_session(&mut self, path: &StreamPath, entry: &Entry, reason: EndReason) {
+        if entry.handle.send(Message::Disconnect).is_err() {
+            debug!(session_id = %entry.id, "Session already closed");
+        }
+
+        if let Some(webhook) = &self.webhook {
+            let action = Action::PublishDone;
+            webhook.notify(action, path, Some(&entry.key), &entry.publisher);
+        }
+
+        self.record_session(entry.record(path, reason)).await;
+    }
+
+    /// Adds a closed session to the history, failing to do so doesn't affect streaming.
+    async fn record_session(&mut self, record: SessionRecord) {
+        if let Err(why) = self.user_repo.record_session(&record).await {
+            error!(
+                "Failed to record session {}/{}: {}",
+                record.app_name, record.stream_name, why
+            );
+        }
+    }
+
+    /// Closes the session unless its publisher returned in the meantime.
+    async fn expire_session(
+        &mut self,
+        path: StreamPath,
+        id: SessionId,
+        lost_at: Instant,
+    ) -> Result<()> {
+        let still_lost = {
+            let sessions = self.sessions.read().await;
+            sessions
+                .get(&path)
+                .is_some_and(|entry| entry.id == id && entry.lost_at == Some(lost_at))
+        };
+
+        if !still_lost {
+            return Ok(());
+        }
+
+        info!(session_id = %id, "Publisher of {} did not return in time", path);
+        self.release_session(path, id, EndReason::NotReturned).await
+    }
+
+    /// Ends sessions that ran for too long or take in more than their owner may publish.
+    async fn enforce_limits(&mut self) -> Result<()> {
+        let now = Instant::now();
+
+        let exceeded: Vec<_> = {
+            let mut sessions = self.sessions.write().await;
+            sessions
+                .iter_mut()
+                .filter_map(|(path, entry)| {
+                    let why = entry.quota.check(now).err()?;
+                    Some((path.clone(), entry.id, why))
+                })
+                .collect()
+        };
+
+        for (path, id, why) in exceeded {
+            warn!(session_id = %id, "Ending session {}: {}", path, why);
+            if let Err(why) = self
+                .release_session(path.clone(), id, EndReason::Limit(why))
+                .await
+            {
+                error!(session_id = %id, "Failed to end session {}: {}", path, why);
+            }
         }
 
-        if !self.user_repo.user_has_key(app_name, stream_key).await? {
-            bail!("Stream key {} not permitted for {}", stream_key, app_name);
+        Ok(())
+    }
+
+    /// Hands the application over to the first queued publisher that is still waiting.
+    async fn promote_standby(&mut self, path: StreamPath, mut standby: Standby) -> Result<()> {
+        while let Some((key, peer, responder)) = standby.pop_front() {
+            if responder.is_closed() {
+                continue;
+            }
+
+            info!("Promoting queued publisher for {}", path);
+            let limits = self.user_repo.limits_of(&path.app_name).await?;
+            return self
+                .start_session(path, key, peer, responder, standby, limits)
+                .await;
         }
 
         Ok(())
     }
+
+    /// Decides on a publisher or viewer in its own task, as the webhook or the user repository
+    /// may take a while, and hands the request back to [`Self::complete`].
+    fn authorize(&self, message: ManagerMessage) {
+        let user_repo = self.user_repo.clone();
+        let webhook = self.webhook.clone();
+        let authorizations = self.authorizations.clone();
+
+        tokio::spawn(async move {
+            let webhook = webhook.as_ref();
+            let result = match &message {
+                ManagerMessage::CreateSession((path, key, peer, _)) => {
+                    authenticate(&user_repo, webhook, path, key, peer).await
+                }
+                ManagerMessage::JoinSession((path, key, peer, _))
+                | ManagerMessage::AuthorizeViewer((path, key, peer, _)) => {
+                    authorize_viewer(&user_repo, webhook, path, key.as_deref(), peer).await
+                }
+                _ => Ok(()),
+            };
+
+            // manager is gone, nobody is waiting for the decision
+            let _ = authorizations.send((message, result));
+        });
+    }
+
+    fn emit(
+        &self,
+        kind: EventKind,
+        path: &StreamPath,
+        session_id: Option<SessionId>,
+        peer: PeerInfo,
+    ) {
+        // nobody listening for events is not an error
+        let _ = self.events.send(Event {
+            kind,
+            path: path.clone(),
+            session_id,
+            peer,
+        });
+    }
+}
+
+
+async fn authenticate<D: UserRepository + Sync>(
+    user_repo: &D,
+    webhook: Option<&Webhook>,
+    path: &StreamPath,
+    stream_key: &str,
+    peer: &PeerInfo,
+) -> Result<()> {
+    if !path.is_valid() {
+        bail!("Invalid stream path {}", path);
+    }
+
+    if stream_key.is_empty() {
+        bail!("Stream key can not be empty");
+    }
+
+    if let Some(webhook) = webhook {
+        let action = Action::Publish;
+        return webhook
+            .authorize(action, path, Some(stream_key), peer)
+            .await;
+    }
+
+    if !user_repo
+        .user_has_key(&path.app_name, &path.stream_name, stream_key)
+        .await?
+    {
+        bail!("Stream key not permitted for {}", path);
+    }
+
+    Ok(())
+}
+
+
+async fn authorize_viewer<D: UserRepository + Sync>(
+    user_repo: &D,
+    webhook: Option<&Webhook>,
+    path: &StreamPath,
+    key: Option<&str>,
+    peer: &PeerInfo,
+) -> Result<()> {
+    if peer.is_internal() {
+        return Ok(());
+    }
+
+    if let Some(webhook) = webhook {
+        return webhook.authorize(Action::Play, path, key, peer).await;
+    }
+
+    if !user_repo.viewer_has_access(&path.app_name, key).await? {
+        bail!("{} not permitted to watch {}", peer, path);
+    }
+
+    Ok(())
 }
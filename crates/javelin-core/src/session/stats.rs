@@ -0,0 +1,278 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use javelin_codec::flv::tag::VideoTagHeader;
+use javelin_types::packet::{Container, MediaKind};
+use javelin_types::{Metadata, Packet};
+use serde::{Serialize, Serializer};
+use tokio::sync::Notify;
+
+use super::{PeerInfo, Protocol, SessionInfo, ViewerId};
+
+
+/// Time span over which rates are averaged.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+
+/// Point in time view of a session.
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    #[serde(flatten)]
+    pub session: SessionInfo,
+
+    /// Stream properties announced by the publisher.
+    pub metadata: Option<Metadata>,
+
+    #[serde(serialize_with = "as_secs")]
+    pub uptime: Duration,
+
+    /// Ingest bitrate in bits per second.
+    pub bitrate: u64,
+
+    /// Video frames per second.
+    pub frame_rate: f64,
+
+    #[serde(serialize_with = "as_secs_opt")]
+    pub keyframe_interval: Option<Duration>,
+
+    pub video_packets: u64,
+    pub audio_packets: u64,
+    pub bytes_received: u64,
+    /// Remote viewers per protocol, internal consumers are only listed in `watchers`.
+    pub viewers: HashMap<Protocol, usize>,
+    pub watchers: Vec<WatcherStats>,
+}
+
+impl Stats {
+    /// Drops client addresses, for responses that are not restricted to operators.
+    pub fn without_addresses(mut self) -> Self {
+        self.session.publisher.addr = None;
+        for watcher in &mut self.watchers {
+            watcher.peer.addr = None;
+        }
+        self
+    }
+}
+
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatcherStats {
+    pub id: ViewerId,
+    pub peer: PeerInfo,
+
+    /// Packets the viewer missed because it could not keep up.
+    pub lagged: u64,
+
+    /// Packets deliberately skipped on behalf of the viewer.
+    pub dropped: u64,
+}
+
+
+/// Counters shared between a session and one of its watchers.
+///
+/// Also carries the request to let the viewer go, as the session has no other way to reach it.
+#[derive(Debug, Default)]
+pub(super) struct WatcherCounters {
+    lagged: AtomicU64,
+    dropped: AtomicU64,
+    disconnect: Notify,
+}
+
+impl WatcherCounters {
+    pub fn add_lagged(&self, count: u64) {
+        self.lagged.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn add_dropped(&self, count: u64) {
+        self.dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Resolves once the viewer should be disconnected, even if that was requested before.
+    pub async fn disconnected(&self) {
+        self.disconnect.notified().await;
+    }
+}
+
+
+struct Sample {
+    received_at: Instant,
+    size: usize,
+    video_frame: bool,
+}
+
+
+/// Accumulates the numbers behind [`Stats`] while a session is running.
+pub(super) struct StatsCollector {
+    started_at: Instant,
+    samples: VecDeque<Sample>,
+    video_packets: u64,
+    audio_packets: u64,
+    bytes_received: u64,
+    last_keyframe: Option<u64>,
+    keyframe_interval: Option<Duration>,
+    next_viewer: ViewerId,
+    watchers: Vec<(ViewerId, PeerInfo, Weak<WatcherCounters>)>,
+}
+
+impl StatsCollector {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            samples: VecDeque::new(),
+            video_packets: 0,
+            audio_packets: 0,
+            bytes_received: 0,
+            last_keyframe: None,
+            keyframe_interval: None,
+            next_viewer: ViewerId::default(),
+            watchers: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, packet: &Packet) {
+        let size = packet.payload.len();
+        let mut video_frame = false;
+
+        match packet.content_type.kind() {
+            MediaKind::Video => {
+                self.video_packets += 1;
+                video_frame = true;
+
+                if packet.content_type.container == Container::Flv {
+                    if let Ok(header) = VideoTagHeader::try_from(&*packet.payload) {
+                        video_frame = !header.is_sequence_header();
+                        if header.is_keyframe() && !header.is_sequence_header() {
+                            self.record_keyframe(packet);
+                        }
+                    }
+                }
+            }
+            MediaKind::Audio => self.audio_packets += 1,
+            _ => (),
+        }
+
+        self.bytes_received += size as u64;
+
+        let now = Instant::now();
+        self.samples.push_back(Sample {
+            received_at: now,
+            size,
+            video_frame,
+        });
+        self.expire_samples(now);
+    }
+
+    pub fn add_watcher(&mut self, peer: PeerInfo) -> Arc<WatcherCounters> {
+        let counters = Arc::new(WatcherCounters::default());
+        let id = self.next_viewer.next();
+        self.watchers.push((id, peer, Arc::downgrade(&counters)));
+        counters
+    }
+
+    /// Returns whether the viewer was still watching.
+    pub fn disconnect_watcher(&mut self, id: ViewerId) -> bool {
+        let counters = self
+            .watchers
+            .iter()
+            .find(|(viewer_id, ..)| *viewer_id == id)
+            .and_then(|(.., counters)| counters.upgrade());
+
+        match counters {
+            Some(counters) => {
+                counters.disconnect.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn snapshot(&mut self, session: &SessionInfo, metadata: Option<Metadata>) -> Stats {
+        let now = Instant::now();
+        self.expire_samples(now);
+        self.watchers
+            .retain(|(.., counters)| counters.strong_count() > 0);
+
+        let uptime = now.duration_since(self.started_at);
+        let window = uptime.min(RATE_WINDOW).as_secs_f64();
+        let (bytes, frames) = self.samples.iter().fold((0, 0), |(bytes, frames), s| {
+            (bytes + s.size as u64, frames + u64::from(s.video_frame))
+        });
+
+        let (bitrate, frame_rate) = if window > 0.0 {
+            ((bytes as f64 * 8.0 / window) as u64, frames as f64 / window)
+        } else {
+            (0, 0.0)
+        };
+
+        let mut viewers = HashMap::new();
+        let watchers = self
+            .watchers
+            .iter()
+            .filter_map(|(id, peer, counters)| {
+                let counters = counters.upgrade()?;
+                if !peer.is_internal() {
+                    *viewers.entry(peer.protocol).or_insert(0) += 1;
+                }
+                Some(WatcherStats {
+                    id: *id,
+                    peer: peer.clone(),
+                    lagged: counters.lagged.load(Ordering::Relaxed),
+                    dropped: counters.dropped.load(Ordering::Relaxed),
+                })
+            })
+            .collect();
+
+        Stats {
+            session: session.clone(),
+            metadata,
+            uptime,
+            bitrate,
+            frame_rate,
+            keyframe_interval: self.keyframe_interval,
+            video_packets: self.video_packets,
+            audio_packets: self.audio_packets,
+            bytes_received: self.bytes_received,
+            viewers,
+            watchers,
+        }
+    }
+
+    fn record_keyframe(&mut self, packet: &Packet) {
+        let Some(timestamp) = packet.timestamp.map(u64::from) else {
+            return;
+        };
+
+        if let Some(last) = self.last_keyframe.replace(timestamp) {
+            if timestamp > last {
+                self.keyframe_interval = Some(Duration::from_millis(timestamp - last));
+            }
+        }
+    }
+
+    fn expire_samples(&mut self, now: Instant) {
+        while let Some(sample) = self.samples.front() {
+            if now.duration_since(sample.received_at) <= RATE_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+}
+
+
+fn as_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+fn as_secs_opt<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => as_secs(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
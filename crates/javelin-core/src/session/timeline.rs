@@ -0,0 +1,288 @@
+use javelin_types::{Timescale, Timestamp};
+use tracing::debug;
+
+
+/// Maps publisher timestamps onto a session timeline starting at zero.
+///
+/// Timestamps wrap around after 32 bits in milliseconds (RTMP) or 33 bits in 90 kHz ticks
+/// (MPEG-TS), the rollover is undone so the timeline keeps counting up. Packets slightly
+/// older than the previous one, as with interleaved audio and video, are passed on as they
+/// are, packets from before the start of the timeline are moved to zero. A publisher
+/// switching timescales continues from where the previous timescale left off.
+///
+/// The timeline itself never wraps around, consumers with a limited clock, like the 33 bit
+/// clock of MPEG-TS, have to wrap it themselves.
+#[derive(Default)]
+pub(super) struct Timeline {
+    state: Option<State>,
+}
+
+struct State {
+    timescale: Timescale,
+    /// Position on the timeline the first timestamp of this timescale maps to.
+    origin: u64,
+    base: u64,
+    offset: u64,
+    last: u64,
+    position: u64,
+}
+
+impl State {
+    fn new(timescale: Timescale, value: u64, origin: u64) -> Self {
+        Self {
+            timescale,
+            origin,
+            base: value,
+            offset: 0,
+            last: value,
+            position: origin,
+        }
+    }
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn normalize(&mut self, timestamp: Timestamp) -> Timestamp {
+        let timescale = timestamp.timescale();
+        let value = timestamp.value();
+
+        let state = match &mut self.state {
+            Some(state) if state.timescale == timescale => state,
+            Some(state) => {
+                debug!(from = ?state.timescale, to = ?timescale, "Timescale changed");
+                let origin = Timestamp::new(state.position, state.timescale)
+                    .rescale(timescale)
+                    .value();
+                *state = State::new(timescale, value, origin);
+                state
+            }
+            None => self.state.insert(State::new(timescale, value, 0)),
+        };
+
+        let period = rollover_period(timescale);
+        let half = period / 2;
+
+        let unwrapped = if value < state.last && state.last - value > half {
+            debug!(?timescale, "Timestamps rolled over");
+            state.offset += period;
+            state.last = value;
+            state.offset + value
+        } else if value > state.last && value - state.last > half && state.offset >= period {
+            // straggler from before the rollover
+            return Timestamp::new(
+                state.origin + (state.offset - period + value).saturating_sub(state.base),
+                timescale,
+            );
+        } else {
+            state.last = value;
+            state.offset + value
+        };
+
+        state.position = state.origin + unwrapped.saturating_sub(state.base);
+        Timestamp::new(state.position, timescale)
+    }
+
+    /// Starts over at zero with the next packet, e.g. for a new publisher.
+    pub fn restart(&mut self) {
+        self.state = None;
+    }
+}
+
+
+/// Jumping back further than this, in milliseconds, means the session timeline started over.
+const MAX_BACKWARDS_JUMP: u64 = 1000;
+
+
+/// Follows a session timeline from the viewer side, where it starts over at zero whenever a
+/// lost publisher comes back, and maps it onto one that never jumps back.
+#[derive(Debug, Default)]
+pub struct ViewerTimeline {
+    offset: i64,
+    latest: Option<u64>,
+    last_output: u64,
+}
+
+/// Where a packet lands on a [`ViewerTimeline`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimelinePosition {
+    /// Milliseconds since the first packet, continuing across restarts.
+    pub timestamp: u64,
+    /// The session timeline started over with this packet.
+    pub restarted: bool,
+}
+
+impl ViewerTimeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps a session timestamp in milliseconds. Packets slightly older than the latest one
+    /// are passed on as they are, as audio and video are not always interleaved in order.
+    pub fn map(&mut self, timestamp: u64) -> TimelinePosition {
+        let restarted = match self.latest {
+            None => {
+                self.offset = -(timestamp as i64);
+                false
+            }
+            Some(latest) if timestamp + MAX_BACKWARDS_JUMP < latest => {
+                self.offset = self.last_output as i64 - timestamp as i64;
+                true
+            }
+            Some(_) => false,
+        };
+
+        self.latest = match self.latest {
+            Some(latest) if !restarted => Some(latest.max(timestamp)),
+            _ => Some(timestamp),
+        };
+
+        let output = (timestamp as i64 + self.offset).max(0) as u64;
+        self.last_output = self.last_output.max(output);
+
+        TimelinePosition {
+            timestamp: output,
+            restarted,
+        }
+    }
+
+    /// Latest session timestamp since the timeline last started over.
+    pub fn latest(&self) -> u64 {
+        self.latest.unwrap_or_default()
+    }
+
+    /// Latest timestamp handed out by [`map`](Self::map).
+    pub fn position(&self) -> u64 {
+        self.last_output
+    }
+}
+
+
+fn rollover_period(timescale: Timescale) -> u64 {
+    match timescale {
+        Timescale::Milliseconds => 1 << 32,
+        Timescale::Mpeg => 1 << 33,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS_PERIOD: u64 = 1 << 32;
+
+    fn normalize(timeline: &mut Timeline, value: u64, timescale: Timescale) -> u64 {
+        timeline.normalize(Timestamp::new(value, timescale)).value()
+    }
+
+    fn millis(timeline: &mut Timeline, value: u64) -> u64 {
+        normalize(timeline, value, Timescale::Milliseconds)
+    }
+
+    #[test]
+    fn starts_at_zero() {
+        let mut timeline = Timeline::new();
+
+        assert_eq!(millis(&mut timeline, 5000), 0);
+        assert_eq!(millis(&mut timeline, 5040), 40);
+    }
+
+    #[test]
+    fn unwraps_rollover() {
+        let mut timeline = Timeline::new();
+
+        assert_eq!(millis(&mut timeline, MS_PERIOD - 20), 0);
+        assert_eq!(millis(&mut timeline, MS_PERIOD - 1), 19);
+        assert_eq!(millis(&mut timeline, 10), 30);
+        assert_eq!(millis(&mut timeline, 50), 70);
+    }
+
+    #[test]
+    fn unwraps_mpeg_rollover() {
+        let mut timeline = Timeline::new();
+        let period = 1 << 33;
+
+        assert_eq!(normalize(&mut timeline, period - 3000, Timescale::Mpeg), 0);
+        assert_eq!(normalize(&mut timeline, 3000, Timescale::Mpeg), 6000);
+    }
+
+    #[test]
+    fn keeps_straggler_before_rollover() {
+        let mut timeline = Timeline::new();
+
+        assert_eq!(millis(&mut timeline, MS_PERIOD - 20), 0);
+        assert_eq!(millis(&mut timeline, 10), 30);
+        // interleaved packet stamped just before the rollover
+        assert_eq!(millis(&mut timeline, MS_PERIOD - 5), 15);
+        assert_eq!(millis(&mut timeline, 40), 60);
+    }
+
+    #[test]
+    fn passes_small_backward_jumps() {
+        let mut timeline = Timeline::new();
+
+        assert_eq!(millis(&mut timeline, 1000), 0);
+        assert_eq!(millis(&mut timeline, 1040), 40);
+        assert_eq!(millis(&mut timeline, 1020), 20);
+        assert_eq!(millis(&mut timeline, 1060), 60);
+        // before the start of the timeline
+        assert_eq!(millis(&mut timeline, 900), 0);
+    }
+
+    #[test]
+    fn rebases_on_timescale_switch() {
+        let mut timeline = Timeline::new();
+
+        assert_eq!(millis(&mut timeline, 1000), 0);
+        assert_eq!(millis(&mut timeline, 3000), 2000);
+
+        // continues at two seconds in 90 kHz ticks
+        assert_eq!(normalize(&mut timeline, 500_000, Timescale::Mpeg), 180_000);
+        assert_eq!(normalize(&mut timeline, 590_000, Timescale::Mpeg), 270_000);
+
+        // and back, three seconds in
+        assert_eq!(millis(&mut timeline, 70), 3000);
+        assert_eq!(millis(&mut timeline, 110), 3040);
+    }
+
+    #[test]
+    fn viewer_timeline_starts_at_zero() {
+        let mut timeline = ViewerTimeline::new();
+
+        assert_eq!(timeline.map(5000).timestamp, 0);
+        assert_eq!(timeline.map(5040).timestamp, 40);
+        // straggler
+        assert_eq!(timeline.map(5020).timestamp, 20);
+        assert_eq!(timeline.latest(), 5040);
+    }
+
+    #[test]
+    fn viewer_timeline_continues_across_restart() {
+        let mut timeline = ViewerTimeline::new();
+
+        assert_eq!(timeline.map(0).timestamp, 0);
+        assert!(!timeline.map(8000).restarted);
+
+        let position = timeline.map(0);
+        assert_eq!(position.timestamp, 8000);
+        assert!(position.restarted);
+        assert_eq!(timeline.latest(), 0);
+
+        assert_eq!(timeline.map(40).timestamp, 8040);
+        assert!(!timeline.map(40).restarted);
+    }
+
+    #[test]
+    fn restarts_at_zero() {
+        let mut timeline = Timeline::new();
+
+        assert_eq!(millis(&mut timeline, 1000), 0);
+        assert_eq!(millis(&mut timeline, 3000), 2000);
+
+        timeline.restart();
+        assert_eq!(millis(&mut timeline, 500), 0);
+    }
+}
@@ -1,39 +1,321 @@
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Instant;
+
+use javelin_codec::flv::tag::VideoTagHeader;
+use javelin_codec::mpegts;
+use javelin_types::packet::{Container, MediaKind};
 use javelin_types::Packet;
+use thiserror::Error;
+use tokio::sync::mpsc::error::SendError;
 use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::debug;
 
-use super::{AppName, Event, StreamKey};
+use super::config::ViewerConfig;
+use super::event::{Event, EventReceiver, EventSender};
+use super::limits::ViewerSlot;
+use super::stats::{Stats, WatcherCounters};
+use super::{AppName, PeerInfo, SessionId, StreamKey, StreamPath, ViewerId};
+use crate::metrics::ViewerMetrics;
 
 
 pub type Responder<P> = oneshot::Sender<P>;
 
+/// Answer to a viewer asking to join a session.
+pub type Joined = Result<(InitData, Watcher), JoinError>;
+
+/// Answer to a viewer asking to watch without joining, the slot holds its place while the
+/// stream is live.
+pub type Admitted = Result<Option<ViewerSlot>, JoinError>;
+
+/// Answer to an operator asking for a stream to be recorded or not.
+pub type Recording = Result<(), RecordingError>;
+
+/// Where the session manager forwards recording requests for live streams.
+pub type RecorderHandle = mpsc::UnboundedSender<RecordingRequest>;
+
 // session manager
 pub enum ManagerMessage {
-    CreateSession((AppName, StreamKey, Responder<Handle>)),
-    ReleaseSession(AppName),
-    JoinSession((AppName, Responder<(Handle, Watcher)>)),
-    RegisterTrigger(Event, Trigger),
+    CreateSession(
+        (
+            StreamPath,
+            StreamKey,
+            PeerInfo,
+            Responder<(SessionId, Handle)>,
+        ),
+    ),
+    ReleaseSession((StreamPath, SessionId)),
+    /// The publisher went away without finishing its stream, it may come back.
+    PublisherLost((StreamPath, SessionId)),
+    JoinSession((StreamPath, Option<StreamKey>, PeerInfo, Responder<Joined>)),
+    /// Checks whether a viewer may watch without joining the session, e.g. for HLS.
+    AuthorizeViewer((StreamPath, Option<StreamKey>, PeerInfo, Responder<Admitted>)),
+    /// Statistics of all streams of an application, or of all running sessions if none is given.
+    QueryStats((Option<AppName>, Responder<Vec<Stats>>)),
+    /// Statistics of a single stream, if it is live.
+    QuerySession((StreamPath, Responder<Option<Stats>>)),
+    /// Closes a session and disconnects its publisher, responds whether the stream was live.
+    TerminateSession((StreamPath, Responder<bool>)),
+    /// Disconnects a viewer of a stream, responds whether it was found.
+    DisconnectViewer((StreamPath, ViewerId, Responder<bool>)),
+    /// Asks the recorder to start or stop recording a stream.
+    RequestRecording((StreamPath, bool, Responder<Recording>)),
+    /// Recording requests go to this recorder from now on.
+    RegisterRecorder(RecorderHandle),
+    /// Closes all sessions and turns away publishers and viewers from then on.
+    Shutdown,
+}
+
+#[derive(Debug, Clone)]
+pub struct ManagerHandle {
+    sender: mpsc::UnboundedSender<ManagerMessage>,
+    events: EventSender,
+}
+
+impl ManagerHandle {
+    pub(super) fn new(sender: mpsc::UnboundedSender<ManagerMessage>, events: EventSender) -> Self {
+        Self { sender, events }
+    }
+
+    #[allow(clippy::result_large_err)]
+    pub fn send(&self, message: ManagerMessage) -> Result<(), SendError<ManagerMessage>> {
+        self.sender.send(message)
+    }
+
+    /// Receive all session lifecycle events from now on.
+    pub fn subscribe(&self) -> EventReceiver {
+        self.events.subscribe()
+    }
 }
 
-pub type ManagerHandle = mpsc::UnboundedSender<ManagerMessage>;
 pub(super) type ManagerReceiver = mpsc::UnboundedReceiver<ManagerMessage>;
 
 
-pub type Trigger = mpsc::UnboundedSender<(String, Watcher)>;
-pub(super) type TriggerHandle = mpsc::UnboundedReceiver<(String, Watcher)>;
+/// Everything a new viewer needs before it can decode live packets.
+#[derive(Clone, Default)]
+pub struct InitData {
+    pub metadata: Option<Packet>,
+    pub video_seq_header: Option<Packet>,
+    pub audio_seq_header: Option<Packet>,
+    pub gop: Vec<Packet>,
+}
 
-pub fn trigger_channel() -> (Trigger, TriggerHandle) {
-    mpsc::unbounded_channel()
+impl InitData {
+    /// All cached packets, in the order they should be delivered.
+    pub fn into_packets(self) -> impl Iterator<Item = Packet> {
+        self.metadata
+            .into_iter()
+            .chain(self.video_seq_header)
+            .chain(self.audio_seq_header)
+            .chain(self.gop)
+    }
 }
 
 
 // session instance
 pub enum Message {
     Packet(Packet),
-    GetInitData(Responder<(Option<Packet>, Option<Packet>, Option<Packet>)>),
+    /// External viewers hold a slot counted against the viewer limit.
+    Subscribe(PeerInfo, Option<ViewerSlot>, Responder<Joined>),
+    QueryStats(Responder<Stats>),
+    DisconnectViewer(ViewerId, Responder<bool>),
+    PublisherLost,
+    PublisherResumed(PeerInfo),
     Disconnect,
 }
 
 pub type Handle = mpsc::UnboundedSender<Message>;
 pub(super) type IncomingBroadcast = mpsc::UnboundedReceiver<Message>;
 pub(super) type OutgoingBroadcast = broadcast::Sender<Packet>;
-pub type Watcher = broadcast::Receiver<Packet>;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum JoinError {
+    #[error("Stream is not live")]
+    NotLive,
+
+    #[error("Not authorized to play stream")]
+    NotAuthorized,
+
+    #[error("Stream is not accepting viewers")]
+    Unavailable,
+}
+
+
+/// Start or stop of a recording, forwarded to the recorder once the stream is known to be live.
+#[derive(Debug)]
+pub struct RecordingRequest {
+    pub path: StreamPath,
+    pub session_id: SessionId,
+    pub record: bool,
+    pub responder: Responder<Recording>,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum RecordingError {
+    #[error("Stream is not live")]
+    NotLive,
+
+    #[error("Recording is off for the application")]
+    Off,
+
+    #[error("No recorder is running")]
+    Unavailable,
+}
+
+
+#[derive(Debug, Error)]
+pub enum RecvError {
+    #[error("Session closed")]
+    Closed,
+
+    #[error("Viewer stayed behind for too long")]
+    TooSlow,
+
+    #[error("Viewer was disconnected")]
+    Disconnected,
+}
+
+
+/// Receives the live packets of a session.
+///
+/// Viewers that fall behind first lose disposable frames, then skip ahead to the next keyframe
+/// once packets were lost, so the picture never has to be decoded from missing references.
+/// Transport stream viewers skip ahead to the next chunk that repeats the tables at a keyframe.
+/// Internal consumers are never disconnected for lagging, as nothing would restart them.
+/// Announces the viewer leaving once dropped.
+pub struct Watcher {
+    receiver: broadcast::Receiver<Packet>,
+    events: EventSender,
+    leave_event: Option<Event>,
+    counters: Arc<WatcherCounters>,
+    metrics: ViewerMetrics,
+    viewer_slot: Option<ViewerSlot>,
+    config: ViewerConfig,
+    internal: bool,
+    behind_since: Option<Instant>,
+    awaiting_keyframe: bool,
+}
+
+impl Watcher {
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn new(
+        receiver: broadcast::Receiver<Packet>,
+        events: EventSender,
+        leave_event: Event,
+        counters: Arc<WatcherCounters>,
+        metrics: ViewerMetrics,
+        viewer_slot: Option<ViewerSlot>,
+        config: ViewerConfig,
+        internal: bool,
+    ) -> Self {
+        Self {
+            receiver,
+            events,
+            leave_event: Some(leave_event),
+            counters,
+            metrics,
+            viewer_slot,
+            config,
+            internal,
+            behind_since: None,
+            awaiting_keyframe: false,
+        }
+    }
+
+    pub async fn recv(&mut self) -> Result<Packet, RecvError> {
+        loop {
+            let result = tokio::select! {
+                biased;
+                _ = self.counters.disconnected() => return Err(RecvError::Disconnected),
+                result = self.receiver.recv() => result,
+            };
+
+            let packet = match result {
+                Ok(packet) => packet,
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    self.counters.add_lagged(count);
+                    self.metrics.lagged(count);
+                    self.awaiting_keyframe = true;
+                    self.check_lag(true)?;
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return Err(RecvError::Closed),
+            };
+
+            self.check_lag(false)?;
+
+            if self.should_deliver(&packet) {
+                self.metrics.delivered(packet.payload.len());
+                if let Some(viewer_slot) = &self.viewer_slot {
+                    viewer_slot.delivered(packet.payload.len());
+                }
+                return Ok(packet);
+            }
+
+            self.counters.add_dropped(1);
+        }
+    }
+
+    fn should_deliver(&mut self, packet: &Packet) -> bool {
+        let content_type = packet.content_type;
+        match (content_type.container, content_type.kind()) {
+            (Container::Flv, MediaKind::Video) => self.should_deliver_video(packet),
+            // chunks interleave audio and video, hold all of them back until the next keyframe
+            (Container::MpegTs, _) if self.awaiting_keyframe => {
+                self.resume_if(mpegts::is_random_access(&packet.payload))
+            }
+            _ => true,
+        }
+    }
+
+    fn should_deliver_video(&mut self, packet: &Packet) -> bool {
+        let header = match VideoTagHeader::try_from(&*packet.payload) {
+            Ok(header) if !header.is_sequence_header() => header,
+            _ => return true,
+        };
+
+        if self.awaiting_keyframe {
+            return self.resume_if(header.is_keyframe());
+        }
+
+        !(header.is_disposable() && self.is_behind())
+    }
+
+    fn resume_if(&mut self, keyframe: bool) -> bool {
+        if keyframe {
+            debug!("Viewer resumed at keyframe");
+            self.awaiting_keyframe = false;
+        }
+        keyframe
+    }
+
+    /// Tracks for how long the viewer has been behind and gives up on it past the limit.
+    fn check_lag(&mut self, lagged: bool) -> Result<(), RecvError> {
+        if !lagged && !self.is_behind() {
+            self.behind_since = None;
+            return Ok(());
+        }
+
+        let behind_since = *self.behind_since.get_or_insert_with(Instant::now);
+        if behind_since.elapsed() > self.config.max_lag && !self.internal {
+            return Err(RecvError::TooSlow);
+        }
+
+        Ok(())
+    }
+
+    fn is_behind(&self) -> bool {
+        self.receiver.len() > self.config.buffer_size / 2
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        if let Some(event) = self.leave_event.take() {
+            // nobody listening for events is not an error
+            let _ = self.events.send(event);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::convert::TryFrom;
+
+use anyhow::Result;
+use javelin_codec::aac::{self, AacCoder};
+use javelin_codec::avc::{self, AvcCoder};
+use javelin_codec::flv::tag::{AudioData, VideoData};
+use javelin_codec::mpegts::TransportStream;
+use javelin_codec::{FormatReader, FormatWriter};
+use javelin_types::{packet, Packet, Timestamp};
+use tokio::sync::broadcast;
+use tracing::trace;
+
+use super::transport::OutgoingBroadcast;
+
+
+/// Longest stretch of the stream without PAT and PMT, in milliseconds.
+const TABLE_INTERVAL: u64 = 100;
+
+
+/// Remuxes the FLV packets of a session into one continuous transport stream.
+///
+/// Output starts at the first keyframe and is sent as packets of whole 188 byte TS packets,
+/// with the tables repeated at every keyframe and at least every [`TABLE_INTERVAL`].
+pub(super) struct TsMuxer {
+    outgoing: OutgoingBroadcast,
+    stream: TransportStream,
+    avc_coder: AvcCoder,
+    aac_coder: AacCoder,
+    started: bool,
+    last_tables: u64,
+}
+
+impl TsMuxer {
+    pub fn new(buffer_size: usize) -> Self {
+        let (outgoing, _) = broadcast::channel(buffer_size);
+
+        Self {
+            outgoing,
+            stream: TransportStream::new(),
+            avc_coder: AvcCoder::new(),
+            aac_coder: AacCoder::new(),
+            started: false,
+            last_tables: 0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Packet> {
+        self.outgoing.subscribe()
+    }
+
+    /// No viewer is left to receive the stream.
+    pub fn is_idle(&self) -> bool {
+        self.outgoing.receiver_count() == 0
+    }
+
+    pub fn push(&mut self, packet: &Packet) -> Result<()> {
+        let timestamp = packet
+            .timestamp
+            .map(Timestamp::as_millis)
+            .unwrap_or_default();
+
+        let keyframe = match packet.content_type {
+            packet::FLV_VIDEO_H264 => self.push_video(timestamp, &packet.payload)?,
+            packet::FLV_AUDIO_AAC => {
+                self.push_audio(timestamp, &packet.payload)?;
+                false
+            }
+            _ => return Ok(()),
+        };
+
+        if !self.started {
+            return Ok(());
+        }
+
+        let with_tables = keyframe || timestamp.saturating_sub(self.last_tables) >= TABLE_INTERVAL;
+        if with_tables {
+            self.last_tables = timestamp;
+        }
+
+        let bytes = self.stream.take_bytes(with_tables)?;
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        // nobody receiving is handled by the session dropping the muxer
+        let _ = self.outgoing.send(Packet::new(
+            packet::CONTAINER_MPEGTS,
+            Some(timestamp),
+            bytes,
+        ));
+
+        Ok(())
+    }
+
+    /// Returns whether the packet was a keyframe that made it into the stream.
+    fn push_video(&mut self, timestamp: u64, bytes: &[u8]) -> Result<bool> {
+        let flv_packet = VideoData::try_from(bytes)?;
+
+        if flv_packet.is_sequence_header() {
+            self.avc_coder.set_dcr(flv_packet.body.as_ref())?;
+            return Ok(false);
+        }
+
+        let keyframe = flv_packet.is_keyframe();
+
+        if !self.started {
+            if !keyframe {
+                return Ok(false);
+            }
+            trace!("Starting transport stream at keyframe");
+            self.started = true;
+        }
+
+        let video = match self.avc_coder.read_format(avc::Avcc, &flv_packet.body)? {
+            Some(avc) => self.avc_coder.write_format(avc::AnnexB, avc)?,
+            None => return Ok(false),
+        };
+
+        let comp_time = flv_packet.composition_time as u64;
+        self.stream
+            .push_video(timestamp, comp_time, keyframe, video)?;
+
+        Ok(keyframe)
+    }
+
+    fn push_audio(&mut self, timestamp: u64, bytes: &[u8]) -> Result<()> {
+        let flv_packet = AudioData::try_from(bytes)?;
+
+        if flv_packet.is_sequence_header() {
+            self.aac_coder.set_asc(flv_packet.body.as_ref())?;
+            return Ok(());
+        }
+
+        if !self.started {
+            return Ok(());
+        }
+
+        let audio = match self.aac_coder.read_format(aac::Raw, &flv_packet.body)? {
+            Some(raw_aac) => self
+                .aac_coder
+                .write_format(aac::AudioDataTransportStream, raw_aac)?,
+            None => return Ok(()),
+        };
+
+        self.stream.push_audio(timestamp, audio)?;
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: &[u8] = &[
+        0x67, 0x64, 0x00, 0x1F, 0xAC, 0xD9, 0x40, 0x50, 0x05, 0xBB, 0x01, 0x10, 0x00, 0x00, 0x03,
+        0x00, 0x10, 0x00, 0x00, 0x03, 0x03, 0xC0, 0xF1, 0x83, 0x19, 0x60,
+    ];
+    const PPS: &[u8] = &[0x68, 0xEB, 0xE3, 0xCB, 0x22, 0xC0];
+
+    fn video_sequence_header() -> Vec<u8> {
+        let mut bytes = vec![0x17, 0x00, 0x00, 0x00, 0x00];
+        bytes.extend([0x01, 0x64, 0x00, 0x1F, 0xFF, 0xE1, 0x00, SPS.len() as u8]);
+        bytes.extend(SPS);
+        bytes.extend([0x01, 0x00, PPS.len() as u8]);
+        bytes.extend(PPS);
+        bytes
+    }
+
+    fn keyframe() -> Vec<u8> {
+        vec![
+            0x17, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x65, 0x88, 0x84, 0x00,
+        ]
+    }
+
+    fn push(
+        muxer: &mut TsMuxer,
+        content_type: packet::ContentType,
+        timestamp: u64,
+        bytes: Vec<u8>,
+    ) {
+        muxer
+            .push(&Packet::new(content_type, Some(timestamp), bytes))
+            .unwrap();
+    }
+
+    #[test]
+    fn streams_past_clock_rollover() {
+        // the 90 kHz clock of PTS and DTS wraps around after 2^33 ticks, about 26.5 hours
+        let timestamp = (1 << 33) / 90 + 1000;
+
+        let mut muxer = TsMuxer::new(16);
+        let mut receiver = muxer.subscribe();
+        push(
+            &mut muxer,
+            packet::FLV_VIDEO_H264,
+            timestamp,
+            video_sequence_header(),
+        );
+        push(&mut muxer, packet::FLV_AUDIO_AAC, timestamp, vec![
+            0xAF, 0x00, 0x12, 0x10,
+        ]);
+        push(&mut muxer, packet::FLV_VIDEO_H264, timestamp, keyframe());
+        push(&mut muxer, packet::FLV_AUDIO_AAC, timestamp + 23, vec![
+            0xAF, 0x01, 0x21, 0x10,
+        ]);
+
+        for expected in [timestamp, timestamp + 23] {
+            let packet = receiver.try_recv().unwrap();
+            assert_eq!(packet.content_type, packet::CONTAINER_MPEGTS);
+            assert_eq!(packet.timestamp.map(Timestamp::as_millis), Some(expected));
+            assert_eq!(packet.payload.len() % 188, 0);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::fmt::Display;
+use std::net::SocketAddr;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::config::WebhookConfig;
+use super::{PeerInfo, Protocol, StreamPath};
+
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub(super) enum Action {
+    #[serde(rename = "on_publish")]
+    Publish,
+
+    #[serde(rename = "on_play")]
+    Play,
+
+    #[serde(rename = "on_publish_done")]
+    PublishDone,
+}
+
+
+#[derive(Debug, Serialize)]
+struct Request<'a> {
+    action: Action,
+    app_name: &'a str,
+    stream_name: &'a str,
+    stream_key: Option<&'a str>,
+    client_addr: Option<SocketAddr>,
+    protocol: Protocol,
+}
+
+
+/// Optional body of a successful response, an empty body allows the request.
+#[derive(Debug, Deserialize)]
+struct Response {
+    allow: bool,
+}
+
+
+/// Leaves publish and play decisions to an external HTTP service.
+///
+/// Client errors and `allow: false` deny a request, server errors and bodies that are neither
+/// empty nor a decision count as the webhook being unavailable.
+#[derive(Clone)]
+pub(super) struct Webhook {
+    client: reqwest::Client,
+    url: String,
+    fail_open: bool,
+}
+
+impl Webhook {
+    pub fn new(config: &WebhookConfig) -> Result<Option<Self>> {
+        let Some(url) = config.url.clone() else {
+            return Ok(None);
+        };
+
+        let client = reqwest::Client::builder().timeout(config.timeout).build()?;
+
+        Ok(Some(Self {
+            client,
+            url,
+            fail_open: config.fail_open,
+        }))
+    }
+
+    pub async fn authorize(
+        &self,
+        action: Action,
+        path: &StreamPath,
+        stream_key: Option<&str>,
+        peer: &PeerInfo,
+    ) -> Result<()> {
+        let request = Request {
+            action,
+            app_name: &path.app_name,
+            stream_name: &path.stream_name,
+            stream_key,
+            client_addr: peer.addr,
+            protocol: peer.protocol,
+        };
+
+        let response = match self.client.post(&self.url).json(&request).send().await {
+            Ok(response) => response,
+            Err(why) => return self.unavailable(action, path, why),
+        };
+
+        let status = response.status();
+        if status.is_client_error() {
+            bail!(
+                "Webhook denied {:?} for {} with status {}",
+                action,
+                path,
+                status
+            );
+        }
+
+        if !status.is_success() {
+            return self.unavailable(action, path, format!("status {}", status));
+        }
+
+        let body = match response.bytes().await {
+            Ok(body) => body,
+            Err(why) => return self.unavailable(action, path, why),
+        };
+
+        if body.is_empty() {
+            return Ok(());
+        }
+
+        match serde_json::from_slice::<Response>(&body) {
+            Ok(Response { allow: false }) => {
+                bail!("Webhook denied {:?} for {}", action, path)
+            }
+            Ok(_) => Ok(()),
+            Err(why) => self.unavailable(action, path, format!("invalid response: {}", why)),
+        }
+    }
+
+    fn unavailable(&self, action: Action, path: &StreamPath, why: impl Display) -> Result<()> {
+        if !self.fail_open {
+            bail!(
+                "Webhook unavailable, denying {:?} for {}: {}",
+                action,
+                path,
+                why
+            );
+        }
+
+        warn!(
+            "Webhook unavailable, allowing {:?} for {}: {}",
+            action, path, why
+        );
+        Ok(())
+    }
+
+    /// Informs the webhook without waiting for, or caring about, its answer.
+    pub fn notify(
+        &self,
+        action: Action,
+        path: &StreamPath,
+        stream_key: Option<&str>,
+        peer: &PeerInfo,
+    ) {
+        let request = self.client.post(&self.url).json(&Request {
+            action,
+            app_name: &path.app_name,
+            stream_name: &path.stream_name,
+            stream_key,
+            client_addr: peer.addr,
+            protocol: peer.protocol,
+        });
+
+        tokio::spawn(async move {
+            if let Err(why) = request.send().await {
+                warn!("Failed to notify webhook: {}", why);
+            }
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::Router;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+
+    fn respond(status: StatusCode, body: &'static str) -> Router {
+        Router::new().route("/auth", post(move || async move { (status, body) }))
+    }
+
+    fn stall() -> Router {
+        Router::new().route(
+            "/auth",
+            post(|| async {
+                tokio::time::sleep(TIMEOUT * 10).await;
+                StatusCode::OK
+            }),
+        )
+    }
+
+    async fn authorize(stand_in: Router, fail_open: bool) -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/auth", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, stand_in).await });
+
+        let config = WebhookConfig {
+            url: Some(url),
+            timeout: TIMEOUT,
+            fail_open,
+        };
+        let webhook = Webhook::new(&config).unwrap().unwrap();
+        let path = StreamPath::new("app", "stream");
+        let peer = PeerInfo::new(Protocol::Rtmp, "127.0.0.1:50000".parse().unwrap());
+
+        webhook
+            .authorize(Action::Publish, &path, Some("key"), &peer)
+            .await
+    }
+
+
+    #[tokio::test]
+    async fn allows() {
+        let stand_in = respond(StatusCode::OK, r#"{"allow": true}"#);
+        assert!(authorize(stand_in, false).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn allows_without_body() {
+        let stand_in = respond(StatusCode::NO_CONTENT, "");
+        assert!(authorize(stand_in, false).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn denies_by_status() {
+        let stand_in = respond(StatusCode::FORBIDDEN, "");
+        assert!(authorize(stand_in, true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn denies_by_body() {
+        let stand_in = respond(StatusCode::OK, r#"{"allow": false}"#);
+        assert!(authorize(stand_in, true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn malformed_body_fails_open() {
+        let stand_in = respond(StatusCode::OK, "<html>Service unavailable</html>");
+        assert!(authorize(stand_in, true).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn malformed_body_fails_closed() {
+        let stand_in = respond(StatusCode::OK, "<html>Service unavailable</html>");
+        assert!(authorize(stand_in, false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn server_error_fails_open() {
+        let stand_in = respond(StatusCode::SERVICE_UNAVAILABLE, "");
+        assert!(authorize(stand_in, true).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn server_error_fails_closed() {
+        let stand_in = respond(StatusCode::INTERNAL_SERVER_ERROR, "");
+        assert!(authorize(stand_in, false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn timeout_fails_open() {
+        assert!(authorize(stall(), true).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn timeout_fails_closed() {
+        assert!(authorize(stall(), false).await.is_err());
+    }
+}
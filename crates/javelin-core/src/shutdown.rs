@@ -0,0 +1,61 @@
+use tokio::sync::{mpsc, watch};
+
+
+/// Tells services and their connections that the server is going down.
+///
+/// Every clone holds off the end of the shutdown until it is dropped, so tasks should keep
+/// one for as long as they have work to finish.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    signal: watch::Receiver<bool>,
+    _guard: mpsc::Sender<()>,
+}
+
+impl Shutdown {
+    /// Resolves once the shutdown was triggered.
+    pub async fn triggered(&self) {
+        let mut signal = self.signal.clone();
+        // a dropped coordinator can not keep the server running either
+        let _ = signal.wait_for(|triggered| *triggered).await;
+    }
+}
+
+
+/// Triggers the shutdown and waits for everyone holding a [`Shutdown`] to finish.
+pub struct Coordinator {
+    signal: watch::Sender<bool>,
+    guard: Option<mpsc::Sender<()>>,
+    drained: mpsc::Receiver<()>,
+}
+
+impl Coordinator {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let (signal, _) = watch::channel(false);
+        let (guard, drained) = mpsc::channel(1);
+
+        Self {
+            signal,
+            guard: Some(guard),
+            drained,
+        }
+    }
+
+    pub fn subscribe(&self) -> Shutdown {
+        Shutdown {
+            signal: self.signal.subscribe(),
+            _guard: self.guard.clone().expect("Shutdown already triggered"),
+        }
+    }
+
+    pub fn trigger(&mut self) {
+        self.signal.send_replace(true);
+        self.guard = None;
+    }
+
+    /// Resolves once every [`Shutdown`] handed out was dropped.
+    pub async fn drained(&mut self) {
+        // nothing is ever sent, the channel only closes
+        let _ = self.drained.recv().await;
+    }
+}
@@ -0,0 +1,527 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use javelin_core::session::{
+    ManagerHandle, ManagerMessage, Recording, RecordingError, Stats, StreamPath, ViewerId,
+};
+use javelin_core::Config;
+use javelin_types::models::{self, Limits, SessionRecord, StreamKey, User, UserRepository};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, RwLock};
+use tracing::{error, info, warn};
+
+
+/// Number of past sessions returned when the request doesn't ask for a specific amount.
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+const MAX_HISTORY_LIMIT: usize = 1000;
+
+
+pub(crate) type SharedRepository = Arc<RwLock<dyn UserRepository + Send + Sync>>;
+
+
+#[derive(Clone)]
+struct AdminState {
+    session_manager: ManagerHandle,
+    user_repo: SharedRepository,
+    config: Config,
+}
+
+
+/// User as shown to operators, keys are never handed out.
+#[derive(Debug, Serialize)]
+struct UserInfo {
+    name: String,
+    /// Viewers have to present a play key.
+    private: bool,
+}
+
+impl From<User> for UserInfo {
+    fn from(user: User) -> Self {
+        Self {
+            name: user.name,
+            private: user.play_key.is_some(),
+        }
+    }
+}
+
+
+#[derive(Debug, Deserialize)]
+struct KeyParams {
+    key: Option<String>,
+}
+
+
+#[derive(Debug, Deserialize)]
+struct StreamKeyParams {
+    key: String,
+    stream: Option<String>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+
+#[derive(Debug, Deserialize)]
+struct HistoryParams {
+    app: Option<String>,
+
+    #[serde(default = "default_history_limit")]
+    limit: usize,
+}
+
+
+#[derive(Debug, Serialize)]
+struct ReloadResult {
+    restart_required: Vec<String>,
+}
+
+
+/// Management endpoints, every request has to carry the token as `Authorization: Bearer`.
+pub(crate) fn routes(
+    token: String,
+    session_manager: ManagerHandle,
+    user_repo: SharedRepository,
+    config: Config,
+) -> Router {
+    let state = AdminState {
+        session_manager,
+        user_repo,
+        config,
+    };
+
+    Router::new()
+        .route("/sessions", get(list_sessions))
+        .route(
+            "/sessions/:app_name/:stream_name",
+            get(session_stats).delete(terminate_session),
+        )
+        .route(
+            "/sessions/:app_name/:stream_name/viewers/:viewer_id",
+            delete(disconnect_viewer),
+        )
+        .route(
+            "/sessions/:app_name/:stream_name/recording",
+            put(start_recording).delete(stop_recording),
+        )
+        .route("/history", get(session_history))
+        .route("/users", get(list_users))
+        .route(
+            "/users/:name",
+            get(show_user).put(permit_stream).delete(remove_user),
+        )
+        .route("/users/:name/play_key", put(set_play_key))
+        .route("/users/:name/limits", get(show_limits).put(set_limits))
+        .route("/users/:name/keys", get(list_keys))
+        .route(
+            "/users/:name/keys/:key_name",
+            put(add_key).delete(revoke_key),
+        )
+        .route("/config/reload", post(reload_config))
+        .with_state(state)
+        .layer(middleware::from_fn_with_state(
+            Arc::<str>::from(token),
+            require_token,
+        ))
+}
+
+
+async fn require_token(
+    State(token): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match presented {
+        Some(presented) if constant_time_eq(presented.as_bytes(), token.as_bytes()) => {
+            Ok(next.run(request).await)
+        }
+        _ => {
+            warn!("Rejected admin request to {}", request.uri().path());
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+
+/// Compares without bailing out at the first mismatch, so the token can't be guessed by timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+
+async fn list_sessions(State(state): State<AdminState>) -> Result<Json<Vec<Stats>>, StatusCode> {
+    let stats = request(&state, |responder| {
+        ManagerMessage::QueryStats((None, responder))
+    })
+    .await?;
+
+    Ok(Json(stats))
+}
+
+async fn session_stats(
+    State(state): State<AdminState>,
+    Path((app_name, stream_name)): Path<(String, String)>,
+) -> Result<Json<Stats>, StatusCode> {
+    let path = StreamPath::new(app_name, stream_name);
+
+    request(&state, |responder| {
+        ManagerMessage::QuerySession((path, responder))
+    })
+    .await?
+    .map(Json)
+    .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn terminate_session(
+    State(state): State<AdminState>,
+    Path((app_name, stream_name)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let path = StreamPath::new(app_name, stream_name);
+
+    let terminated = request(&state, |responder| {
+        ManagerMessage::TerminateSession((path, responder))
+    })
+    .await?;
+
+    found_or_not(terminated)
+}
+
+async fn disconnect_viewer(
+    State(state): State<AdminState>,
+    Path((app_name, stream_name, viewer_id)): Path<(String, String, ViewerId)>,
+) -> Result<StatusCode, StatusCode> {
+    let path = StreamPath::new(app_name, stream_name);
+
+    let disconnected = request(&state, |responder| {
+        ManagerMessage::DisconnectViewer((path, viewer_id, responder))
+    })
+    .await?;
+
+    found_or_not(disconnected)
+}
+
+/// Records the stream unless recording is off for its application, 409 if it is and 404
+/// without a recorder.
+async fn start_recording(
+    State(state): State<AdminState>,
+    Path((app_name, stream_name)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let path = StreamPath::new(app_name, stream_name);
+
+    let recording = request(&state, |responder| {
+        ManagerMessage::RequestRecording((path, true, responder))
+    })
+    .await?;
+
+    recording_status(recording)
+}
+
+async fn stop_recording(
+    State(state): State<AdminState>,
+    Path((app_name, stream_name)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let path = StreamPath::new(app_name, stream_name);
+
+    let recording = request(&state, |responder| {
+        ManagerMessage::RequestRecording((path, false, responder))
+    })
+    .await?;
+
+    recording_status(recording)
+}
+
+
+/// Sessions that already ended, most recent first.
+async fn session_history(
+    State(state): State<AdminState>,
+    Query(params): Query<HistoryParams>,
+) -> Result<Json<Vec<SessionRecord>>, StatusCode> {
+    let history = state
+        .user_repo
+        .read()
+        .await
+        .session_history(params.app.as_deref(), params.limit.min(MAX_HISTORY_LIMIT))
+        .await
+        .map_err(repository_error)?;
+
+    Ok(Json(history))
+}
+
+
+async fn list_users(State(state): State<AdminState>) -> Result<Json<Vec<UserInfo>>, StatusCode> {
+    let users = state
+        .user_repo
+        .read()
+        .await
+        .list_users()
+        .await
+        .map_err(repository_error)?;
+
+    Ok(Json(users.into_iter().map(UserInfo::from).collect()))
+}
+
+async fn show_user(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+) -> Result<Json<UserInfo>, StatusCode> {
+    state
+        .user_repo
+        .read()
+        .await
+        .user_by_name(&name)
+        .await
+        .map_err(repository_error)?
+        .map(|user| Json(user.into()))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Creates the user, or replaces its default stream key.
+async fn permit_stream(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+    Json(params): Json<KeyParams>,
+) -> Result<StatusCode, StatusCode> {
+    let Some(key) = params.key.filter(|key| !key.is_empty()) else {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    };
+
+    state
+        .user_repo
+        .write()
+        .await
+        .add_user_with_key(&name, &key)
+        .await
+        .map_err(repository_error)?;
+
+    info!("Updated stream key of {}", name);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Requires viewers to present the key, leaving it out makes the streams public again.
+async fn set_play_key(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+    Json(params): Json<KeyParams>,
+) -> Result<StatusCode, StatusCode> {
+    state
+        .user_repo
+        .write()
+        .await
+        .set_play_key(&name, params.key.as_deref())
+        .await
+        .map_err(repository_error)?;
+
+    info!("Updated play key of {}", name);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn remove_user(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let removed = state
+        .user_repo
+        .write()
+        .await
+        .remove_user(&name)
+        .await
+        .map_err(repository_error)?;
+
+    if removed {
+        info!("Removed user {}", name);
+    }
+
+    found_or_not(removed)
+}
+
+
+async fn show_limits(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+) -> Result<Json<Limits>, StatusCode> {
+    let user_repo = state.user_repo.read().await;
+
+    if user_repo
+        .user_by_name(&name)
+        .await
+        .map_err(repository_error)?
+        .is_none()
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let limits = user_repo.limits_of(&name).await.map_err(repository_error)?;
+
+    Ok(Json(limits))
+}
+
+/// Replaces all limits, the ones left out are lifted. Running streams keep their limits.
+async fn set_limits(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+    Json(limits): Json<Limits>,
+) -> Result<StatusCode, StatusCode> {
+    state
+        .user_repo
+        .write()
+        .await
+        .set_limits(&name, limits)
+        .await
+        .map_err(repository_error)?;
+
+    info!("Updated limits of {}", name);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+async fn list_keys(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<StreamKey>>, StatusCode> {
+    let user_repo = state.user_repo.read().await;
+
+    if user_repo
+        .user_by_name(&name)
+        .await
+        .map_err(repository_error)?
+        .is_none()
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let keys = user_repo.keys_of(&name).await.map_err(repository_error)?;
+
+    Ok(Json(keys))
+}
+
+/// Adds a named key, replacing and reactivating any key of the same name.
+async fn add_key(
+    State(state): State<AdminState>,
+    Path((name, key_name)): Path<(String, String)>,
+    Json(params): Json<StreamKeyParams>,
+) -> Result<StatusCode, StatusCode> {
+    if params.key.is_empty() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    state
+        .user_repo
+        .write()
+        .await
+        .add_key(
+            &name,
+            &key_name,
+            &params.key,
+            false,
+            params.stream.as_deref(),
+            params.expires_at,
+        )
+        .await
+        .map_err(repository_error)?;
+
+    info!("Stored key {} of {}", key_name, name);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn revoke_key(
+    State(state): State<AdminState>,
+    Path((name, key_name)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let revoked = state
+        .user_repo
+        .write()
+        .await
+        .revoke_key(&name, &key_name)
+        .await
+        .map_err(repository_error)?;
+
+    if revoked {
+        info!("Revoked key {} of {}", key_name, name);
+    }
+
+    found_or_not(revoked)
+}
+
+
+async fn reload_config(
+    State(state): State<AdminState>,
+) -> Result<Json<ReloadResult>, (StatusCode, String)> {
+    info!("Reloading configuration on request");
+
+    match state.config.reload() {
+        Ok(restart_required) => {
+            for setting in &restart_required {
+                warn!("Changed setting {} requires a restart", setting);
+            }
+            Ok(Json(ReloadResult { restart_required }))
+        }
+        Err(why) => {
+            error!("Keeping current configuration: {:#}", why);
+            Err((StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", why)))
+        }
+    }
+}
+
+
+async fn request<T, F>(state: &AdminState, message: F) -> Result<T, StatusCode>
+where
+    F: FnOnce(oneshot::Sender<T>) -> ManagerMessage,
+{
+    let (responder, response) = oneshot::channel();
+
+    if state.session_manager.send(message(responder)).is_err() {
+        error!("Failed to reach session manager");
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    response.await.map_err(|_| StatusCode::SERVICE_UNAVAILABLE)
+}
+
+
+fn found_or_not(found: bool) -> Result<StatusCode, StatusCode> {
+    if found {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+
+fn recording_status(recording: Recording) -> Result<StatusCode, StatusCode> {
+    match recording {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(RecordingError::NotLive | RecordingError::Unavailable) => Err(StatusCode::NOT_FOUND),
+        Err(RecordingError::Off) => Err(StatusCode::CONFLICT),
+    }
+}
+
+
+fn repository_error(why: models::Error) -> StatusCode {
+    match why {
+        models::Error::NoSuchUser(_) => StatusCode::NOT_FOUND,
+        models::Error::ReadOnly => StatusCode::METHOD_NOT_ALLOWED,
+        why => {
+            error!("{}", why);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+
+fn default_history_limit() -> usize {
+    DEFAULT_HISTORY_LIMIT
+}
@@ -0,0 +1,67 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use javelin_core::session::{ManagerHandle, ManagerMessage, Stats};
+use tokio::sync::oneshot;
+use tracing::error;
+
+
+pub(crate) fn routes(session_manager: ManagerHandle) -> Router {
+    Router::new()
+        .route("/stats", get(all_stats))
+        .route("/stats/:app_name", get(app_stats))
+        .route("/stats/:app_name/:stream_name", get(stream_stats))
+        .with_state(session_manager)
+}
+
+
+async fn all_stats(
+    State(session_manager): State<ManagerHandle>,
+) -> Result<Json<Vec<Stats>>, StatusCode> {
+    query_stats(&session_manager, None).await.map(Json)
+}
+
+async fn app_stats(
+    State(session_manager): State<ManagerHandle>,
+    Path(app_name): Path<String>,
+) -> Result<Json<Vec<Stats>>, StatusCode> {
+    query_stats(&session_manager, Some(app_name))
+        .await
+        .map(Json)
+}
+
+async fn stream_stats(
+    State(session_manager): State<ManagerHandle>,
+    Path((app_name, stream_name)): Path<(String, String)>,
+) -> Result<Json<Stats>, StatusCode> {
+    let stats = query_stats(&session_manager, Some(app_name)).await?;
+    stats
+        .into_iter()
+        .find(|stats| stats.session.path.stream_name == stream_name)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+
+async fn query_stats(
+    session_manager: &ManagerHandle,
+    app_name: Option<String>,
+) -> Result<Vec<Stats>, StatusCode> {
+    let (request, response) = oneshot::channel();
+
+    if session_manager
+        .send(ManagerMessage::QueryStats((app_name, request)))
+        .is_err()
+    {
+        error!("Failed to query session statistics");
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    let stats = response
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+
+    // client addresses are only exposed through the admin API
+    Ok(stats.into_iter().map(Stats::without_addresses).collect())
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, Query, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use javelin_core::session::{
+    JoinError, ManagerHandle, ManagerMessage, PeerInfo, Protocol, StreamPath,
+};
+use serde::Deserialize;
+use tokio::sync::oneshot;
+
+use crate::metrics::HlsViewers;
+
+
+/// Players reload playlists every few seconds, decisions are reused for this long.
+const DECISION_TTL: Duration = Duration::from_secs(10);
+
+/// Segments are fetched after the playlist listing them, decisions stay valid for them a little
+/// longer so a playlist served at the end of [`DECISION_TTL`] can still be played.
+const SEGMENT_DECISION_TTL: Duration = Duration::from_secs(20);
+
+
+type DecisionKey = (IpAddr, StreamPath, Option<String>);
+
+
+/// Recent play decisions per client, stream and play key.
+#[derive(Debug, Clone)]
+pub(crate) struct PlayAuthorization {
+    session_manager: ManagerHandle,
+    viewers: HlsViewers,
+    decisions: Arc<Mutex<HashMap<DecisionKey, (bool, Instant)>>>,
+}
+
+impl PlayAuthorization {
+    pub fn new(session_manager: ManagerHandle, viewers: HlsViewers) -> Self {
+        Self {
+            session_manager,
+            viewers,
+            decisions: Arc::default(),
+        }
+    }
+
+    fn cached(&self, key: &DecisionKey) -> Option<bool> {
+        let decisions = self.decisions.lock().unwrap();
+        decisions
+            .get(key)
+            .filter(|(_, decided_at)| decided_at.elapsed() < DECISION_TTL)
+            .map(|(allowed, _)| *allowed)
+    }
+
+    /// Decision for a segment of the stream, made for a playlist requested with any key.
+    ///
+    /// Players do not pass the query of a playlist on to the segments it lists.
+    fn cached_for_segment(&self, addr: IpAddr, stream_path: &StreamPath) -> Option<bool> {
+        let decisions = self.decisions.lock().unwrap();
+        decisions
+            .iter()
+            .filter(|((ip, path, _), (_, decided_at))| {
+                *ip == addr && path == stream_path && decided_at.elapsed() < SEGMENT_DECISION_TTL
+            })
+            .map(|(_, (allowed, _))| *allowed)
+            .reduce(|any, allowed| any || allowed)
+    }
+
+    fn remember(&self, key: DecisionKey, allowed: bool) {
+        let mut decisions = self.decisions.lock().unwrap();
+        decisions.retain(|_, (_, decided_at)| decided_at.elapsed() < SEGMENT_DECISION_TTL);
+        decisions.insert(key, (allowed, Instant::now()));
+    }
+
+    /// Asks the session manager, taking up a viewer slot if the stream is live.
+    async fn decide(
+        &self,
+        stream_path: StreamPath,
+        key: Option<String>,
+        addr: SocketAddr,
+    ) -> Result<bool, StatusCode> {
+        self.viewers.release(&stream_path, addr.ip());
+
+        let (responder, response) = oneshot::channel();
+        let peer = PeerInfo::new(Protocol::Hls, addr);
+        let message =
+            ManagerMessage::AuthorizeViewer((stream_path.clone(), key.clone(), peer, responder));
+
+        if self.session_manager.send(message).is_err() {
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        let admitted = match response.await {
+            Ok(Err(JoinError::NotAuthorized)) => false,
+            Ok(Ok(Some(viewer_slot))) => {
+                self.viewers
+                    .admit(stream_path.clone(), addr.ip(), viewer_slot);
+                true
+            }
+            // not remembered, the client has to take up a slot once the stream is live
+            Ok(Ok(None)) => return Ok(true),
+            // full streams are not remembered, a slot may free up any moment
+            Ok(Err(_)) | Err(_) => return Err(StatusCode::SERVICE_UNAVAILABLE),
+        };
+        self.remember((addr.ip(), stream_path, key), admitted);
+
+        Ok(admitted)
+    }
+}
+
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PlayParams {
+    key: Option<String>,
+}
+
+
+/// Asks the session manager whether the client may watch before serving a playlist or segment.
+///
+/// The decision is reused for further requests of the client for a few seconds, segments
+/// follow the decision for the playlist of their stream. Clients of a live stream take up a
+/// viewer slot, once all are taken new ones get a 503.
+pub(crate) async fn authorize_viewer(
+    State(authorization): State<PlayAuthorization>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(params): Query<PlayParams>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let path = request.uri().path();
+
+    let Some(stream_path) = stream_path_of(path) else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let cached = if path.ends_with(".m3u8") {
+        authorization.cached(&(addr.ip(), stream_path.clone(), params.key.clone()))
+    } else {
+        authorization.cached_for_segment(addr.ip(), &stream_path)
+    };
+
+    let allowed = match cached {
+        Some(allowed) => allowed,
+        None => authorization.decide(stream_path, params.key, addr).await?,
+    };
+
+    if !allowed {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(next.run(request).await)
+}
+
+
+/// Stream a playlist or segment belongs to, they live at `app/stream/<file>`.
+pub(crate) fn stream_path_of(path: &str) -> Option<StreamPath> {
+    path.trim_start_matches('/')
+        .rsplit_once('/')
+        .and_then(|(dir, _)| dir.parse().ok())
+}
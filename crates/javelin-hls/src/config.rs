@@ -1,5 +1,6 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use serde::Deserialize;
 
@@ -12,6 +13,10 @@ pub struct Config {
     #[serde(default = "default_enabled")]
     pub enabled: bool,
 
+    /// Segments are cut at the first keyframe after this much time.
+    #[serde(default = "default_segment_duration")]
+    pub segment_duration: Duration,
+
     #[serde(default)]
     pub web: WebConfig,
 }
@@ -21,6 +26,7 @@ impl Default for Config {
         Self {
             root_dir: default_root_dir(),
             enabled: default_enabled(),
+            segment_duration: default_segment_duration(),
             web: WebConfig::default(),
         }
     }
@@ -34,6 +40,10 @@ pub struct WebConfig {
 
     #[serde(default = "default_enabled")]
     pub enabled: bool,
+
+    /// Bearer token for the admin API at `/api/admin`, which is disabled without one.
+    #[serde(default)]
+    pub admin_token: Option<String>,
 }
 
 impl Default for WebConfig {
@@ -41,6 +51,7 @@ impl Default for WebConfig {
         Self {
             addr: default_web_addr(),
             enabled: default_enabled(),
+            admin_token: None,
         }
     }
 }
@@ -50,6 +61,10 @@ fn default_root_dir() -> PathBuf {
     PathBuf::from("./data/hls")
 }
 
+fn default_segment_duration() -> Duration {
+    Duration::from_secs(2)
+}
+
 fn default_web_addr() -> SocketAddr {
     SocketAddr::from(([0, 0, 0, 0], 8080))
 }
@@ -4,6 +4,7 @@ use std::time::Duration;
 
 use futures_delay_queue::delay_queue;
 use futures_intrusive::buffer::GrowingHeapBuf;
+use javelin_core::metrics;
 use tokio::sync::mpsc;
 use tracing::{debug, error};
 
@@ -46,6 +47,7 @@ impl FileCleaner {
                 files.len(),
                 timestamp
             );
+            metrics::hls_cleanup_queued(files.len());
             self.queue.insert(files, timestamp);
 
             if let Some(expired) = self.queue_rx.receive().await {
@@ -66,6 +68,8 @@ fn remove_files(paths: &[PathBuf]) {
     for path in paths {
         remove_file(path);
     }
+
+    metrics::hls_segments_cleaned(paths.len());
 }
 
 fn remove_file(path: &PathBuf) {
@@ -1,6 +1,10 @@
+mod admin;
+mod api;
+mod auth;
 mod config;
 mod file_cleaner;
 mod m3u8;
+mod metrics;
 pub mod service;
 mod writer;
 
@@ -16,6 +16,7 @@ pub struct Playlist {
     file_path: PathBuf,
     current_duration: u64,
     cleanup_started: bool,
+    pending_discontinuity: bool,
     playlist: MediaPlaylist,
     file_cleaner: file_cleaner::Sender,
 }
@@ -39,6 +40,7 @@ impl Playlist {
             file_path: path.into(),
             current_duration: 0,
             cleanup_started: false,
+            pending_discontinuity: false,
             playlist,
             file_cleaner,
         }
@@ -48,6 +50,11 @@ impl Playlist {
         self.playlist.target_duration = (duration as f64 / 1000.0) as u64;
     }
 
+    /// Marks the next segment as starting a new timeline.
+    pub fn mark_discontinuity(&mut self) {
+        self.pending_discontinuity = true;
+    }
+
     fn schedule_for_deletion(&mut self, amount: usize, delete_after: u64) {
         let segments_to_delete: Vec<_> = self.playlist.segments.drain(..amount).collect();
         let paths: Vec<_> = segments_to_delete
@@ -59,6 +66,10 @@ impl Playlist {
             .collect();
 
         self.playlist.media_sequence += paths.len() as u64;
+        self.playlist.discontinuity_sequence += segments_to_delete
+            .iter()
+            .filter(|seg| seg.discontinuity)
+            .count() as u64;
         self.file_cleaner
             .send((Duration::from_millis(delete_after), paths))
             .unwrap();
@@ -72,6 +83,7 @@ impl Playlist {
         segment.duration = (duration as f64 / 1000.0) as f32;
         segment.title = Some("".into()); // adding empty title here, because implementation is broken
         segment.uri = uri.into();
+        segment.discontinuity = std::mem::take(&mut self.pending_discontinuity);
 
 
         if self.cleanup_started {
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::HttpBody;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use javelin_core::metrics;
+use javelin_core::session::{Protocol, StreamPath, ViewerSlot};
+
+use crate::auth;
+
+
+/// Clients that requested a playlist within this time count as viewers.
+const VIEWER_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// Serves all metrics in the Prometheus text format.
+pub(crate) async fn render() -> Result<impl IntoResponse, StatusCode> {
+    let body = metrics::render().ok_or(StatusCode::NOT_FOUND)?;
+    let content_type = [(header::CONTENT_TYPE, "text/plain; version=0.0.4")];
+    Ok((content_type, body))
+}
+
+
+/// HLS clients don't stay connected, the ones that keep reloading a playlist are its viewers.
+///
+/// Clients are told apart by their address only, players behind the same address count once.
+/// Each one holds a slot counted against the viewer limit of a live stream until it expires.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HlsViewers {
+    clients: Arc<Mutex<HashMap<StreamPath, HashMap<IpAddr, Client>>>>,
+}
+
+#[derive(Debug)]
+struct Client {
+    seen_at: Instant,
+    viewer_slot: Option<ViewerSlot>,
+}
+
+impl HlsViewers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gives up the slot of the client, so asking for a new one doesn't count it twice.
+    pub fn release(&self, path: &StreamPath, addr: IpAddr) {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients
+            .get_mut(path)
+            .and_then(|stream_clients| stream_clients.get_mut(&addr))
+        {
+            client.viewer_slot = None;
+        }
+    }
+
+    pub fn admit(&self, path: StreamPath, addr: IpAddr, viewer_slot: ViewerSlot) {
+        let mut clients = self.clients.lock().unwrap();
+        let client = Client {
+            seen_at: Instant::now(),
+            viewer_slot: Some(viewer_slot),
+        };
+        let known = clients
+            .entry(path)
+            .or_default()
+            .insert(addr, client)
+            .is_some();
+
+        if !known {
+            update_gauges(&mut clients);
+        }
+    }
+
+    /// Adds served bytes to the usage of the session the client holds a slot of.
+    fn delivered(&self, path: &StreamPath, addr: IpAddr, bytes: usize) {
+        let clients = self.clients.lock().unwrap();
+        if let Some(viewer_slot) = clients
+            .get(path)
+            .and_then(|stream_clients| stream_clients.get(&addr))
+            .and_then(|client| client.viewer_slot.as_ref())
+        {
+            viewer_slot.delivered(bytes);
+        }
+    }
+
+    fn seen(&self, path: StreamPath, addr: IpAddr) {
+        let mut clients = self.clients.lock().unwrap();
+        let stream_clients = clients.entry(path).or_default();
+        let known = match stream_clients.get_mut(&addr) {
+            Some(client) => {
+                client.seen_at = Instant::now();
+                true
+            }
+            None => {
+                let client = Client {
+                    seen_at: Instant::now(),
+                    viewer_slot: None,
+                };
+                stream_clients.insert(addr, client);
+                false
+            }
+        };
+
+        if !known {
+            update_gauges(&mut clients);
+        }
+    }
+
+    /// Forgets clients that stopped watching, meant to be called periodically.
+    pub fn expire(&self) {
+        update_gauges(&mut self.clients.lock().unwrap());
+    }
+}
+
+
+fn update_gauges(clients: &mut HashMap<StreamPath, HashMap<IpAddr, Client>>) {
+    let mut per_app = HashMap::new();
+
+    for (path, stream_clients) in clients.iter_mut() {
+        stream_clients.retain(|_, client| client.seen_at.elapsed() < VIEWER_TIMEOUT);
+        *per_app.entry(path.app_name.clone()).or_insert(0) += stream_clients.len();
+    }
+
+    metrics::set_hls_viewers(
+        per_app
+            .iter()
+            .map(|(app_name, count)| (app_name.as_str(), *count)),
+    );
+
+    // streams without viewers were reported as zero above, no need to do so again
+    clients.retain(|_, stream_clients| !stream_clients.is_empty());
+}
+
+
+/// Counts served bytes as egress of the session and playlist requests as viewers.
+pub(crate) async fn track_requests(
+    State(viewers): State<HlsViewers>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    let response = next.run(request).await;
+
+    if !response.status().is_success() {
+        return response;
+    }
+
+    let Some(stream_path) = auth::stream_path_of(&path) else {
+        return response;
+    };
+
+    if let Some(bytes) = response.body().size_hint().exact() {
+        metrics::bytes_sent(Protocol::Hls, &stream_path.app_name, bytes as usize);
+        viewers.delivered(&stream_path, addr.ip(), bytes as usize);
+    }
+
+    if path.ends_with(".m3u8") {
+        viewers.seen(stream_path, addr.ip());
+    }
+
+    response
+}
@@ -1,35 +1,53 @@
 use std::fs;
+use std::net::SocketAddr;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{bail, Result};
-use axum::Router;
-use javelin_core::session::{self, ManagerMessage};
-use javelin_core::Config;
+use axum::routing::get;
+use axum::{middleware, Router};
+use javelin_core::session::{self, Event, EventKind, ManagerMessage, PeerInfo, Protocol};
+use javelin_core::{Config, Shutdown};
+use javelin_types::models::UserRepository;
 use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{oneshot, RwLock};
 use tower_http::services::ServeDir;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
+use crate::admin::SharedRepository;
+use crate::auth::PlayAuthorization;
 use crate::config::Config as HlsConfig;
-use crate::file_cleaner;
+use crate::metrics::HlsViewers;
 use crate::writer::Writer;
+use crate::{admin, api, auth, file_cleaner, metrics};
 
 
 pub struct Service {
     config: HlsConfig,
+    source: Config,
     session_manager: session::ManagerHandle,
+    user_repo: SharedRepository,
 }
 
 
 impl Service {
-    pub fn new(session_manager: session::ManagerHandle, config: &Config) -> Self {
-        let config = config.get("hls").unwrap_or_default();
+    pub fn new<D>(session_manager: session::ManagerHandle, user_repo: D, config: &Config) -> Self
+    where
+        D: UserRepository + Send + Sync + 'static,
+    {
+        config.register_section::<HlsConfig>("hls", &["root_dir", "web"]);
+
         Self {
-            config,
+            config: config.get("hls").unwrap_or_default(),
+            source: config.clone(),
             session_manager,
+            user_repo: Arc::new(RwLock::new(user_repo)),
         }
     }
 
-    pub async fn run(self) {
+    pub async fn run(self, shutdown: Shutdown) {
         let hls_root = self.config.root_dir.clone();
         info!("HLS directory located at '{}'", hls_root.display());
 
@@ -45,30 +63,97 @@ impl Service {
         if self.config.web.enabled {
             let addr = self.config.web.addr;
 
+            let hls_viewers = HlsViewers::new();
+            tokio::spawn(expire_hls_viewers(hls_viewers.clone(), shutdown.clone()));
+
             let serve_dir = ServeDir::new(hls_root);
-            let routes = Router::new().nest_service("/hls", serve_dir);
+            let hls = Router::new()
+                .fallback_service(serve_dir)
+                .layer(middleware::from_fn_with_state(
+                    PlayAuthorization::new(self.session_manager.clone(), hls_viewers.clone()),
+                    auth::authorize_viewer,
+                ))
+                .layer(middleware::from_fn_with_state(
+                    hls_viewers,
+                    metrics::track_requests,
+                ));
+            let mut routes = Router::new()
+                .nest("/hls", hls)
+                .nest("/api", api::routes(self.session_manager.clone()))
+                .route("/metrics", get(metrics::render));
+
+            if let Some(token) = self.config.web.admin_token.clone() {
+                info!("Admin API enabled");
+                let admin = admin::routes(
+                    token,
+                    self.session_manager.clone(),
+                    self.user_repo.clone(),
+                    self.source.clone(),
+                );
+                routes = routes.nest("/api/admin", admin);
+            }
 
+            let shutdown = shutdown.clone();
             tokio::spawn(async move {
                 let listener = TcpListener::bind(addr).await.unwrap();
-                axum::serve(listener, routes).await.unwrap();
+                let service = routes.into_make_service_with_connect_info::<SocketAddr>();
+                axum::serve(listener, service)
+                    .with_graceful_shutdown(async move { shutdown.triggered().await })
+                    .await
+                    .unwrap();
             });
         }
 
-        let (trigger, mut trigger_handle) = session::trigger_channel();
+        let mut events = self.session_manager.subscribe();
+
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                _ = shutdown.triggered() => break,
+            };
+
+            let path = match event {
+                Ok(Event {
+                    kind: EventKind::PublishStarted,
+                    path,
+                    ..
+                }) => path,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(%skipped, "HLS service missed session events");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let (request, response) = oneshot::channel();
+            let peer = PeerInfo::internal(Protocol::Hls);
+            let message = ManagerMessage::JoinSession((path.clone(), None, peer, request));
 
-        if self
-            .session_manager
-            .send(ManagerMessage::RegisterTrigger("create_session", trigger))
-            .is_err()
-        {
-            error!("Failed to register session trigger");
-            return;
-        }
+            if self.session_manager.send(message).is_err() {
+                error!("Failed to join session {}", path);
+                continue;
+            }
 
-        while let Some((app_name, watcher)) = trigger_handle.recv().await {
-            match Writer::create(app_name, watcher, fcleaner_sender.clone(), &self.config) {
+            let Ok(Ok((init_data, watcher))) = response.await else {
+                error!("Session {} closed before HLS writer could join", path);
+                continue;
+            };
+
+            // the directory and web server stay as they are until restarted
+            let config = HlsConfig {
+                root_dir: self.config.root_dir.clone(),
+                ..self.source.get("hls").unwrap_or_default()
+            };
+
+            match Writer::create(&path, watcher, fcleaner_sender.clone(), &config) {
                 Ok(writer) => {
-                    tokio::spawn(async move { writer.run().await.unwrap() });
+                    let shutdown = shutdown.clone();
+                    tokio::spawn(async move {
+                        writer.run(init_data).await.unwrap();
+                        // keeps the server around until the playlist is closed
+                        drop(shutdown);
+                    });
                 }
                 Err(why) => error!("Failed to create writer: {:?}", why),
             }
@@ -77,6 +162,18 @@ impl Service {
 }
 
 
+async fn expire_hls_viewers(hls_viewers: HlsViewers, shutdown: Shutdown) {
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => hls_viewers.expire(),
+            _ = shutdown.triggered() => break,
+        }
+    }
+}
+
+
 fn directory_cleanup<P: AsRef<Path>>(path: P) -> Result<()> {
     let path = path.as_ref();
 
@@ -8,7 +8,8 @@ use javelin_codec::aac::{self, AacCoder};
 use javelin_codec::avc::{self, AvcCoder};
 use javelin_codec::mpegts::TransportStream;
 use javelin_codec::{flv, FormatReader, FormatWriter};
-use javelin_core::session;
+use javelin_core::metrics;
+use javelin_core::session::{self, InitData, StreamPath, ViewerTimeline};
 use javelin_types::{packet, Packet};
 use tracing::{debug, error, info, warn};
 
@@ -22,26 +23,30 @@ pub struct Writer {
     write_interval: u64,
     next_write: u64,
     last_keyframe: u64,
+    last_timestamp: u64,
+    timeline: ViewerTimeline,
     keyframe_counter: usize,
+    discontinuity: bool,
     buffer: TransportStream,
     playlist: Playlist,
     stream_path: PathBuf,
+    app_name: String,
     avc_coder: AvcCoder,
     aac_coder: AacCoder,
 }
 
 impl Writer {
     pub fn create(
-        app_name: String,
+        path: &StreamPath,
         watcher: session::Watcher,
         fcleaner_sender: file_cleaner::Sender,
         config: &Config,
     ) -> Result<Self> {
-        let write_interval = 2000; // milliseconds
-        let next_write = write_interval; // milliseconds
+        let write_interval = config.segment_duration.as_millis() as u64;
+        let next_write = write_interval;
 
         let hls_root = config.root_dir.clone();
-        let stream_path = hls_root.join(app_name);
+        let stream_path = hls_root.join(&path.app_name).join(&path.stream_name);
         let playlist_path = stream_path.join("playlist.m3u8");
 
         prepare_stream_directory(&stream_path)?;
@@ -51,22 +56,46 @@ impl Writer {
             write_interval,
             next_write,
             last_keyframe: 0,
+            last_timestamp: 0,
+            timeline: ViewerTimeline::new(),
             keyframe_counter: 0,
+            discontinuity: false,
             buffer: TransportStream::new(),
             playlist: Playlist::new(playlist_path, fcleaner_sender),
             avc_coder: AvcCoder::new(),
             aac_coder: AacCoder::new(),
             stream_path,
+            app_name: path.app_name.clone(),
         })
     }
 
-    pub async fn run(mut self) -> Result<()> {
-        while let Ok(packet) = self.watcher.recv().await {
+    pub async fn run(mut self, init_data: InitData) -> Result<()> {
+        for packet in init_data.into_packets() {
             if let Err(why) = self.handle_packet(packet) {
                 error!("{:?}", why);
             }
         }
 
+        loop {
+            match self.watcher.recv().await {
+                Ok(packet) => {
+                    if let Err(why) = self.handle_packet(packet) {
+                        error!("{:?}", why);
+                    }
+                }
+                Err(session::RecvError::Closed) => break,
+                Err(why) => {
+                    warn!("HLS writer stopped: {}", why);
+                    break;
+                }
+            }
+        }
+
+        // the playlist is ended once the writer is dropped
+        if let Err(why) = self.flush_segment() {
+            error!("Failed to write final segment: {:?}", why);
+        }
+
         Ok(())
     }
 
@@ -86,22 +115,25 @@ impl Writer {
 
         let keyframe = flv_packet.is_keyframe();
 
+        if self.discontinuity {
+            if !keyframe {
+                return Ok(());
+            }
+
+            self.last_keyframe = timestamp;
+            self.next_write = timestamp + self.write_interval;
+            self.discontinuity = false;
+        }
+
         if keyframe {
-            let keyframe_duration = timestamp - self.last_keyframe;
+            let keyframe_duration = timestamp.saturating_sub(self.last_keyframe);
 
             if self.keyframe_counter == 1 {
                 self.playlist.set_target_duration(keyframe_duration * 3);
             }
 
             if timestamp >= self.next_write {
-                let filename = format!(
-                    "{}-{}.mpegts",
-                    Utc::now().timestamp(),
-                    self.keyframe_counter
-                );
-                let path = self.stream_path.join(&filename);
-                self.buffer.write_to_file(&path)?;
-                self.playlist.add_media_segment(filename, keyframe_duration);
+                self.write_segment(keyframe_duration)?;
                 self.next_write += self.write_interval;
                 self.last_keyframe = timestamp;
             }
@@ -139,7 +171,7 @@ impl Writer {
             return Ok(());
         }
 
-        if self.keyframe_counter == 0 {
+        if self.keyframe_counter == 0 || self.discontinuity {
             return Ok(());
         }
 
@@ -157,7 +189,53 @@ impl Writer {
         Ok(())
     }
 
+    fn write_segment(&mut self, duration: u64) -> Result<()> {
+        let filename = format!(
+            "{}-{}.mpegts",
+            Utc::now().timestamp(),
+            self.keyframe_counter
+        );
+        let path = self.stream_path.join(&filename);
+        self.buffer.write_to_file(&path)?;
+        self.playlist.add_media_segment(filename, duration);
+        metrics::hls_segment_written(&self.app_name);
+        Ok(())
+    }
+
+    /// Writes out whatever was buffered since the last keyframe as a shorter segment.
+    fn flush_segment(&mut self) -> Result<()> {
+        if self.keyframe_counter > 0 && !self.discontinuity {
+            let duration = self.last_timestamp.saturating_sub(self.last_keyframe);
+            self.write_segment(duration)?;
+        }
+
+        Ok(())
+    }
+
+    /// Closes the current segment when the publisher restarts its timeline, e.g. after
+    /// reconnecting, and waits for the next keyframe to continue.
+    fn begin_discontinuity(&mut self) -> Result<()> {
+        info!(
+            "Timeline of {} restarted, inserting discontinuity",
+            self.stream_path.display()
+        );
+
+        self.flush_segment()?;
+        self.playlist.mark_discontinuity();
+        self.discontinuity = true;
+
+        Ok(())
+    }
+
     fn handle_packet(&mut self, packet: Packet) -> Result<()> {
+        if let Some(timestamp) = packet.timestamp.map(u64::from) {
+            // the segment cut short by a restart ends at the latest timestamp before it
+            if self.timeline.map(timestamp).restarted && !self.discontinuity {
+                self.begin_discontinuity()?;
+            }
+            self.last_timestamp = self.timeline.latest();
+        }
+
         match packet {
             Packet {
                 content_type: packet::FLV_VIDEO_H264,
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+
+/// When sessions of an application are recorded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordMode {
+    #[default]
+    Off,
+
+    /// Every session, from the moment it starts.
+    Always,
+
+    /// Only once requested through the admin API, until asked to stop.
+    OnDemand,
+}
+
+
+/// Limits after which a recording continues in a new file, at the next keyframe.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct RotationConfig {
+    #[serde(default)]
+    pub max_size: Option<u64>,
+
+    #[serde(default)]
+    pub max_duration: Option<Duration>,
+}
+
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default = "default_root_dir")]
+    pub root_dir: PathBuf,
+
+    #[serde(default)]
+    pub mode: RecordMode,
+
+    /// Path of each file below the root directory, with `{app}`, `{stream}`, `{session}`,
+    /// `{date}`, `{timestamp}` and `{index}` filled in when the file is created.
+    #[serde(default = "default_file_name")]
+    pub file_name: String,
+
+    #[serde(default)]
+    pub rotation: RotationConfig,
+
+    #[serde(default)]
+    pub apps: HashMap<String, AppConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            root_dir: default_root_dir(),
+            mode: RecordMode::default(),
+            file_name: default_file_name(),
+            rotation: RotationConfig::default(),
+            apps: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn mode(&self, app_name: &str) -> RecordMode {
+        self.apps
+            .get(app_name)
+            .and_then(|app| app.mode)
+            .unwrap_or(self.mode)
+    }
+
+    pub fn file_name(&self, app_name: &str) -> &str {
+        self.apps
+            .get(app_name)
+            .and_then(|app| app.file_name.as_deref())
+            .unwrap_or(&self.file_name)
+    }
+
+    pub fn rotation(&self, app_name: &str) -> RotationConfig {
+        self.apps
+            .get(app_name)
+            .and_then(|app| app.rotation)
+            .unwrap_or(self.rotation)
+    }
+}
+
+
+/// Per application overrides of the global recording settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AppConfig {
+    #[serde(default)]
+    pub mode: Option<RecordMode>,
+
+    #[serde(default)]
+    pub file_name: Option<String>,
+
+    #[serde(default)]
+    pub rotation: Option<RotationConfig>,
+}
+
+
+fn default_root_dir() -> PathBuf {
+    PathBuf::from("./data/recordings")
+}
+
+fn default_file_name() -> String {
+    "{app}/{stream}-{date}.flv".to_string()
+}
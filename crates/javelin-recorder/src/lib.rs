@@ -0,0 +1,6 @@
+mod config;
+mod recorder;
+pub mod service;
+
+
+pub use self::service::Service;
@@ -0,0 +1,604 @@
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use javelin_codec::flv::tag::{AudioTagHeader, VideoTagHeader};
+use javelin_codec::flv::{FlvWriter, ScriptValue, TagType};
+use javelin_core::session::{self, InitData, SessionId, StreamPath, ViewerTimeline};
+use javelin_types::packet::{Container, MediaKind};
+use javelin_types::{packet, Metadata, Packet};
+use tokio::sync::oneshot;
+use tracing::{debug, error, info, warn};
+
+use crate::config::{Config, RotationConfig};
+
+
+/// Placeholders available in file name templates.
+const PLACEHOLDERS: &[&str] = &["app", "stream", "session", "date", "timestamp", "index"];
+
+/// Stream metadata written to `onMetaData` as numbers, by their name in the session metadata.
+const NUMBER_PROPERTIES: &[(&str, &str)] = &[
+    ("video.width", "width"),
+    ("video.height", "height"),
+    ("video.frame_rate", "framerate"),
+    ("video.codec_id", "videocodecid"),
+    ("video.bitrate", "videodatarate"),
+    ("audio.codec_id", "audiocodecid"),
+    ("audio.bitrate", "audiodatarate"),
+    ("audio.sampling_rate", "audiosamplerate"),
+    ("audio.channels", "audiochannels"),
+];
+
+
+/// Writes the packets of a session to FLV files, starting a new file whenever
+/// the rotation limits are reached.
+pub struct Recorder {
+    watcher: session::Watcher,
+    recording: Recording,
+}
+
+
+/// Files of a recording, fed one packet at a time.
+struct Recording {
+    path: StreamPath,
+    session_id: Option<SessionId>,
+    root_dir: PathBuf,
+    file_name: String,
+    rotation: RotationConfig,
+    metadata: Option<Packet>,
+    video_seq_header: Option<Packet>,
+    audio_seq_header: Option<Packet>,
+    timeline: ViewerTimeline,
+    file: Option<OpenFile>,
+    file_count: usize,
+}
+
+
+struct OpenFile {
+    writer: FlvWriter<BufWriter<File>>,
+    path: PathBuf,
+    /// Recording time of the first tag, the file's own timeline starts there.
+    started_at: u64,
+}
+
+
+impl Recorder {
+    pub fn new(
+        path: &StreamPath,
+        session_id: Option<SessionId>,
+        watcher: session::Watcher,
+        config: &Config,
+    ) -> Result<Self> {
+        Ok(Self {
+            watcher,
+            recording: Recording::new(path, session_id, config)?,
+        })
+    }
+
+    /// Records until the session closes or a stop is sent.
+    pub async fn run(mut self, init_data: InitData, stop: oneshot::Receiver<()>) -> Result<()> {
+        let path = self.recording.path.clone();
+        info!("Recording {}", path);
+
+        let stop = async move {
+            // a dropped sender only means the service is gone, e.g. during shutdown
+            if stop.await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        };
+        tokio::pin!(stop);
+
+        for packet in init_data.into_packets() {
+            self.recording.handle_packet(packet)?;
+        }
+
+        loop {
+            let result = tokio::select! {
+                result = self.watcher.recv() => result,
+                _ = &mut stop => {
+                    info!("Stopped recording {} on request", path);
+                    break;
+                }
+            };
+
+            match result {
+                Ok(packet) => self.recording.handle_packet(packet)?,
+                Err(session::RecvError::Closed) => break,
+                Err(why) => {
+                    warn!("Recorder of {} stopped: {}", path, why);
+                    break;
+                }
+            }
+        }
+
+        self.recording.close_file()
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        debug!("Closing recorder for {}", self.recording.path);
+    }
+}
+
+
+impl Recording {
+    fn new(path: &StreamPath, session_id: Option<SessionId>, config: &Config) -> Result<Self> {
+        let file_name = config.file_name(&path.app_name).to_string();
+        // catches mistakes in the template before the first file is due
+        let examples: Vec<_> = PLACEHOLDERS
+            .iter()
+            .map(|name| (*name, name.to_string()))
+            .collect();
+        expand_file_name(&file_name, &examples)?;
+
+        Ok(Self {
+            path: path.clone(),
+            session_id,
+            root_dir: config.root_dir.clone(),
+            file_name,
+            rotation: config.rotation(&path.app_name),
+            metadata: None,
+            video_seq_header: None,
+            audio_seq_header: None,
+            timeline: ViewerTimeline::new(),
+            file: None,
+            file_count: 0,
+        })
+    }
+
+    fn handle_packet(&mut self, packet: Packet) -> Result<()> {
+        let content_type = packet.content_type;
+        match (content_type.container, content_type.kind()) {
+            _ if content_type == packet::METADATA => {
+                // only takes effect in the next file, players read it from the start
+                self.metadata = Some(packet);
+                Ok(())
+            }
+            (Container::Flv, MediaKind::Video) => self.handle_video(packet),
+            (Container::Flv, MediaKind::Audio) => self.handle_audio(packet),
+            _ => Ok(()),
+        }
+    }
+
+    fn handle_video(&mut self, packet: Packet) -> Result<()> {
+        let header = match VideoTagHeader::try_from(&*packet.payload) {
+            Ok(header) => header,
+            Err(why) => {
+                debug!("Skipping video packet: {}", why);
+                return Ok(());
+            }
+        };
+
+        if header.is_sequence_header() {
+            self.write_seq_header(TagType::Video, &packet)?;
+            self.video_seq_header = Some(packet);
+            return Ok(());
+        }
+
+        let Some(timestamp) = packet.timestamp else {
+            return Ok(());
+        };
+        let timestamp = self.timeline.map(timestamp.into()).timestamp;
+
+        if header.is_keyframe() && self.rotation_due(timestamp) {
+            self.close_file()?;
+        }
+
+        if self.file.is_none() {
+            // players can only start decoding at a keyframe
+            if !header.is_keyframe() {
+                return Ok(());
+            }
+            self.open_file(timestamp)?;
+        }
+
+        self.write_tag(TagType::Video, timestamp, &packet)
+    }
+
+    fn handle_audio(&mut self, packet: Packet) -> Result<()> {
+        let header = match AudioTagHeader::try_from(&*packet.payload) {
+            Ok(header) => header,
+            Err(why) => {
+                debug!("Skipping audio packet: {}", why);
+                return Ok(());
+            }
+        };
+
+        if header.is_sequence_header() {
+            self.write_seq_header(TagType::Audio, &packet)?;
+            self.audio_seq_header = Some(packet);
+            return Ok(());
+        }
+
+        let Some(timestamp) = packet.timestamp else {
+            return Ok(());
+        };
+        let timestamp = self.timeline.map(timestamp.into()).timestamp;
+
+        // files of streams with video start and end at keyframes
+        let audio_only = self.video_seq_header.is_none();
+
+        if audio_only && self.rotation_due(timestamp) {
+            self.close_file()?;
+        }
+
+        if self.file.is_none() {
+            if !audio_only {
+                return Ok(());
+            }
+            self.open_file(timestamp)?;
+        }
+
+        self.write_tag(TagType::Audio, timestamp, &packet)
+    }
+
+    fn rotation_due(&self, timestamp: u64) -> bool {
+        let Some(file) = &self.file else {
+            return false;
+        };
+
+        let size_reached = self
+            .rotation
+            .max_size
+            .is_some_and(|max_size| file.writer.bytes_written() >= max_size);
+        let duration_reached = self.rotation.max_duration.is_some_and(|max_duration| {
+            timestamp.saturating_sub(file.started_at) >= max_duration.as_millis() as u64
+        });
+
+        size_reached || duration_reached
+    }
+
+    fn open_file(&mut self, started_at: u64) -> Result<()> {
+        let now = Utc::now();
+        self.file_count += 1;
+
+        let session_id = self.session_id.map(|id| id.to_string()).unwrap_or_default();
+        let vars = [
+            ("app", self.path.app_name.clone()),
+            ("stream", self.path.stream_name.clone()),
+            ("session", session_id),
+            ("date", now.format("%Y%m%d-%H%M%S").to_string()),
+            ("timestamp", now.timestamp().to_string()),
+            ("index", self.file_count.to_string()),
+        ];
+        let file_name = expand_file_name(&self.file_name, &vars)?;
+        let (file, path) = create_file(&self.root_dir.join(file_name))?;
+
+        let has_video = self.video_seq_header.is_some();
+        let has_audio = self.audio_seq_header.is_some() || !has_video;
+        let mut writer = FlvWriter::new(BufWriter::new(file), has_audio, has_video)?;
+
+        if let Some(metadata) = self.metadata.clone() {
+            match Metadata::try_from(metadata) {
+                Ok(metadata) => writer.write_metadata(&metadata_properties(&metadata))?,
+                Err(why) => error!("Failed to read metadata: {}", why),
+            }
+        }
+
+        if let Some(packet) = &self.video_seq_header {
+            writer.write_tag(TagType::Video, 0, &packet.payload)?;
+        }
+
+        if let Some(packet) = &self.audio_seq_header {
+            writer.write_tag(TagType::Audio, 0, &packet.payload)?;
+        }
+
+        debug!("Recording {} to '{}'", self.path, path.display());
+
+        self.file = Some(OpenFile {
+            writer,
+            path,
+            started_at,
+        });
+
+        Ok(())
+    }
+
+    fn close_file(&mut self) -> Result<()> {
+        let Some(mut file) = self.file.take() else {
+            return Ok(());
+        };
+
+        file.writer.flush()?;
+        info!(
+            "Finished recording of {} in '{}'",
+            self.path,
+            file.path.display()
+        );
+
+        Ok(())
+    }
+
+    /// Passes on codec changes in the middle of a file, the next file starts with them anyway.
+    fn write_seq_header(&mut self, tag_type: TagType, packet: &Packet) -> Result<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+
+        let timestamp = self.timeline.position().saturating_sub(file.started_at);
+        file.writer
+            .write_tag(tag_type, timestamp as u32, &packet.payload)?;
+
+        Ok(())
+    }
+
+    fn write_tag(&mut self, tag_type: TagType, timestamp: u64, packet: &Packet) -> Result<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+
+        let timestamp = timestamp.saturating_sub(file.started_at);
+        file.writer
+            .write_tag(tag_type, timestamp as u32, &packet.payload)?;
+
+        Ok(())
+    }
+}
+
+/// Fills in the placeholders of a file name template, which has to stay inside the
+/// recordings directory.
+fn expand_file_name(template: &str, vars: &[(&str, String)]) -> Result<PathBuf> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+
+        let Some(len) = rest[start..].find('}') else {
+            bail!("Unclosed placeholder in recorder file name {}", template);
+        };
+        let name = &rest[start + 1..start + len];
+
+        let Some((_, value)) = vars.iter().find(|(var, _)| *var == name) else {
+            bail!(
+                "Unknown placeholder {{{}}} in recorder file name {}",
+                name,
+                template
+            );
+        };
+        expanded.push_str(value);
+
+        rest = &rest[start + len + 1..];
+    }
+    expanded.push_str(rest);
+
+    let path = PathBuf::from(expanded);
+    let is_relative = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+
+    if !is_relative || path.as_os_str().is_empty() {
+        bail!("Recorder file name {} has to be a relative path", template);
+    }
+
+    Ok(path)
+}
+
+
+/// Never overwrites earlier recordings, a number is added to names that are taken.
+fn create_file(path: &Path) -> Result<(File, PathBuf)> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    for attempt in 0..100 {
+        let candidate = if attempt == 0 {
+            path.to_path_buf()
+        } else {
+            path.with_file_name(format!("{}-{}{}", stem, attempt, extension))
+        };
+
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(file) => return Ok((file, candidate)),
+            Err(why) if why.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(why) => return Err(why.into()),
+        }
+    }
+
+    Err(anyhow!("Too many recordings named '{}'", path.display()))
+}
+
+
+fn metadata_properties(metadata: &Metadata) -> Vec<(&'static str, ScriptValue)> {
+    let mut properties: Vec<_> = NUMBER_PROPERTIES
+        .iter()
+        .filter_map(|(key, name)| Some((*name, ScriptValue::Number(metadata.get(key)?))))
+        .collect();
+
+    if let Some(stereo) = metadata.get("audio.stereo") {
+        properties.push(("stereo", ScriptValue::Boolean(stereo)));
+    }
+
+    if let Some(encoder) = metadata.get("encoder") {
+        properties.push(("encoder", ScriptValue::String(encoder)));
+    }
+
+    properties
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+
+    const VIDEO_SEQ_HEADER: &[u8] = &[0x17, 0x00, 0x00, 0x00, 0x00, 0x01, 0x64, 0x00, 0x1F];
+    const KEYFRAME: &[u8] = &[0x17, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x65];
+    const INTER_FRAME: &[u8] = &[0x27, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x41];
+    const AUDIO_SEQ_HEADER: &[u8] = &[0xAF, 0x00, 0x12, 0x10];
+    const AUDIO_FRAME: &[u8] = &[0xAF, 0x01, 0x21, 0x10];
+
+
+    fn vars() -> Vec<(&'static str, String)> {
+        vec![
+            ("app", "live".to_string()),
+            ("stream", "main".to_string()),
+            ("index", "1".to_string()),
+        ]
+    }
+
+    fn recording(root_dir: &Path, rotation: RotationConfig) -> Recording {
+        let config = Config {
+            root_dir: root_dir.to_path_buf(),
+            file_name: "{stream}-{index}.flv".to_string(),
+            rotation,
+            ..Config::default()
+        };
+        let path = StreamPath::new("live", "main");
+
+        Recording::new(&path, None, &config).unwrap()
+    }
+
+    fn feed(recording: &mut Recording, timestamp: u32, payload: &'static [u8]) {
+        let content_type = match payload[0] {
+            0xAF => packet::FLV_AUDIO_AAC,
+            _ => packet::FLV_VIDEO_H264,
+        };
+        let packet = Packet::new(content_type, Some(timestamp), payload);
+        recording.handle_packet(packet).unwrap();
+    }
+
+    fn files(root_dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(root_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+
+    #[test]
+    fn expands_placeholders() {
+        let path = expand_file_name("{app}/{stream}-{index}.flv", &vars()).unwrap();
+        assert_eq!(path, PathBuf::from("live/main-1.flv"));
+    }
+
+    #[test]
+    fn rejects_paths_outside_root_dir() {
+        for template in [
+            "../{stream}.flv",
+            "{app}/../../x.flv",
+            "/tmp/{stream}.flv",
+            "",
+        ] {
+            assert!(expand_file_name(template, &vars()).is_err(), "{}", template);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        assert!(expand_file_name("{app}/{user}.flv", &vars()).is_err());
+    }
+
+    #[test]
+    fn rejects_unclosed_placeholders() {
+        assert!(expand_file_name("{app}/{stream.flv", &vars()).is_err());
+    }
+
+    #[test]
+    fn never_overwrites_files() {
+        let root_dir = TempDir::new().unwrap();
+        let path = root_dir.path().join("live").join("main.flv");
+
+        let (_, first) = create_file(&path).unwrap();
+        fs::write(&first, b"earlier").unwrap();
+        let (_, second) = create_file(&path).unwrap();
+        let (_, third) = create_file(&path).unwrap();
+
+        assert_eq!(first, path);
+        assert_eq!(second, root_dir.path().join("live").join("main-1.flv"));
+        assert_eq!(third, root_dir.path().join("live").join("main-2.flv"));
+        assert_eq!(fs::read(&first).unwrap(), b"earlier");
+    }
+
+    #[test]
+    fn starts_at_keyframe() {
+        let root_dir = TempDir::new().unwrap();
+        let mut recording = recording(root_dir.path(), RotationConfig::default());
+
+        feed(&mut recording, 0, VIDEO_SEQ_HEADER);
+        feed(&mut recording, 0, INTER_FRAME);
+        assert!(files(root_dir.path()).is_empty());
+
+        feed(&mut recording, 40, KEYFRAME);
+        recording.close_file().unwrap();
+        assert_eq!(files(root_dir.path()), ["main-1.flv"]);
+    }
+
+    #[test]
+    fn rotates_at_keyframe_after_max_duration() {
+        let root_dir = TempDir::new().unwrap();
+        let rotation = RotationConfig {
+            max_size: None,
+            max_duration: Some(Duration::from_secs(1)),
+        };
+        let mut recording = recording(root_dir.path(), rotation);
+
+        feed(&mut recording, 0, VIDEO_SEQ_HEADER);
+        feed(&mut recording, 0, KEYFRAME);
+        feed(&mut recording, 1500, INTER_FRAME);
+        assert_eq!(files(root_dir.path()), ["main-1.flv"]);
+
+        feed(&mut recording, 2000, KEYFRAME);
+        recording.close_file().unwrap();
+        assert_eq!(files(root_dir.path()), ["main-1.flv", "main-2.flv"]);
+    }
+
+    #[test]
+    fn rotates_at_keyframe_after_max_size() {
+        let root_dir = TempDir::new().unwrap();
+        let rotation = RotationConfig {
+            max_size: Some(64),
+            max_duration: None,
+        };
+        let mut recording = recording(root_dir.path(), rotation);
+
+        feed(&mut recording, 0, VIDEO_SEQ_HEADER);
+        feed(&mut recording, 0, KEYFRAME);
+        for timestamp in [40, 80, 120] {
+            feed(&mut recording, timestamp, INTER_FRAME);
+        }
+        assert_eq!(files(root_dir.path()), ["main-1.flv"]);
+
+        feed(&mut recording, 160, KEYFRAME);
+        recording.close_file().unwrap();
+        assert_eq!(files(root_dir.path()), ["main-1.flv", "main-2.flv"]);
+    }
+
+    #[test]
+    fn rotates_audio_only_streams_at_any_frame() {
+        let root_dir = TempDir::new().unwrap();
+        let rotation = RotationConfig {
+            max_size: None,
+            max_duration: Some(Duration::from_secs(1)),
+        };
+        let mut recording = recording(root_dir.path(), rotation);
+
+        feed(&mut recording, 0, AUDIO_SEQ_HEADER);
+        feed(&mut recording, 0, AUDIO_FRAME);
+        feed(&mut recording, 500, AUDIO_FRAME);
+        feed(&mut recording, 1000, AUDIO_FRAME);
+        recording.close_file().unwrap();
+
+        assert_eq!(files(root_dir.path()), ["main-1.flv", "main-2.flv"]);
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use javelin_core::session::{
+    self, Event, EventKind, ManagerMessage, PeerInfo, Protocol, RecordingError, RecordingRequest,
+    SessionId, StreamPath,
+};
+use javelin_core::{Config, Shutdown};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error, info, warn};
+
+use crate::config::{Config as RecorderConfig, RecordMode};
+use crate::recorder::Recorder;
+
+
+pub struct Service {
+    config: RecorderConfig,
+    source: Config,
+    session_manager: session::ManagerHandle,
+}
+
+
+impl Service {
+    pub fn new(session_manager: session::ManagerHandle, config: &Config) -> Self {
+        config.register_section::<RecorderConfig>("recorder", &["root_dir"]);
+
+        Self {
+            config: config.get("recorder").unwrap_or_default(),
+            source: config.clone(),
+            session_manager,
+        }
+    }
+
+    pub async fn run(self, shutdown: Shutdown) {
+        info!("Recordings located at '{}'", self.config.root_dir.display());
+
+        let mut events = self.session_manager.subscribe();
+        let (recorder, mut requests) = mpsc::unbounded_channel();
+        if self
+            .session_manager
+            .send(ManagerMessage::RegisterRecorder(recorder))
+            .is_err()
+        {
+            error!("Failed to register recorder with session manager");
+            return;
+        }
+
+        // closed once the recorder is done
+        let mut recordings: HashMap<StreamPath, oneshot::Sender<()>> = HashMap::new();
+
+        loop {
+            let (path, session_id, config) = tokio::select! {
+                event = events.recv() => {
+                    let Event {
+                        kind,
+                        path,
+                        session_id,
+                        ..
+                    } = match event {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!(%skipped, "Recorder service missed session events");
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+
+                    let config = self.current_config();
+                    match kind {
+                        EventKind::PublishStarted
+                            if config.mode(&path.app_name) == RecordMode::Always => {}
+                        _ => continue,
+                    }
+
+                    recordings.retain(|_, stop| !stop.is_closed());
+                    (path, session_id, config)
+                }
+                Some(request) = requests.recv() => {
+                    let RecordingRequest {
+                        path,
+                        session_id,
+                        record,
+                        responder,
+                    } = request;
+
+                    let config = self.current_config();
+                    recordings.retain(|_, stop| !stop.is_closed());
+
+                    let recording = if !record {
+                        if let Some(stop) = recordings.remove(&path) {
+                            // recorder already finished on its own
+                            let _ = stop.send(());
+                        }
+                        Ok(())
+                    } else if config.mode(&path.app_name) == RecordMode::Off {
+                        Err(RecordingError::Off)
+                    } else {
+                        Ok(())
+                    };
+                    let start = record && recording.is_ok() && !recordings.contains_key(&path);
+
+                    if responder.send(recording).is_err() {
+                        debug!("Recording requester for {} went away", path);
+                    }
+
+                    if !start {
+                        continue;
+                    }
+
+                    (path, Some(session_id), config)
+                }
+                _ = shutdown.triggered() => break,
+            };
+
+            let (stop, stopped) = oneshot::channel();
+            recordings.insert(path.clone(), stop);
+
+            // joining waits for the session manager, which must not hold up other sessions
+            let session_manager = self.session_manager.clone();
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                if let Err(why) = record(session_manager, path, session_id, config, stopped).await {
+                    error!("Recording failed: {:#}", why);
+                }
+                // keeps the server around until the file is complete
+                drop(shutdown);
+            });
+        }
+    }
+
+    /// Recording settings as currently configured, the directory stays as it is until restarted.
+    fn current_config(&self) -> RecorderConfig {
+        RecorderConfig {
+            root_dir: self.config.root_dir.clone(),
+            ..self.source.get("recorder").unwrap_or_default()
+        }
+    }
+}
+
+
+/// Joins the session and records it until it closes or a stop is sent.
+async fn record(
+    session_manager: session::ManagerHandle,
+    path: StreamPath,
+    session_id: Option<SessionId>,
+    config: RecorderConfig,
+    stopped: oneshot::Receiver<()>,
+) -> Result<()> {
+    let (request, response) = oneshot::channel();
+    let peer = PeerInfo::internal(Protocol::Flv);
+    let message = ManagerMessage::JoinSession((path.clone(), None, peer, request));
+
+    if session_manager.send(message).is_err() {
+        bail!("Failed to join session {}", path);
+    }
+
+    let Ok(Ok((init_data, watcher))) = response.await else {
+        bail!("Session {} closed before recorder could join", path);
+    };
+
+    let recorder = Recorder::new(&path, session_id, watcher, &config)
+        .with_context(|| format!("Failed to start recording {}", path))?;
+
+    recorder.run(init_data, stopped).await
+}
@@ -1,4 +1,3 @@
-use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::time::Duration;
 
@@ -10,9 +9,6 @@ pub struct Config {
     #[serde(default = "default_addr")]
     pub addr: SocketAddr,
 
-    #[serde(default)]
-    pub stream_keys: HashMap<String, String>, // TODO: move to database
-
     #[serde(default = "default_conn_timeout")]
     pub connection_timeout: Duration,
 
@@ -25,7 +21,6 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             addr: default_addr(),
-            stream_keys: HashMap::new(),
             connection_timeout: default_conn_timeout(),
             #[cfg(feature = "rtmps")]
             tls: Default::default(),
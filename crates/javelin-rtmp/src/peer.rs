@@ -1,16 +1,20 @@
 use bytes::Bytes;
 use futures::{SinkExt, TryStreamExt};
-use javelin_core::session::{self, ManagerMessage, Message};
-use javelin_types::{packet, Packet};
+use javelin_core::session::{
+    self, InitData, JoinError, ManagerMessage, Message, PeerInfo, RecvError, SessionId, StreamPath,
+};
+use javelin_core::{metrics, Shutdown};
+use javelin_types::packet::{Container, MediaKind};
+use javelin_types::Packet;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::sync::{self, mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot};
 use tokio::time::timeout;
 use tokio_util::codec::{BytesCodec, Framed};
-use tracing::{debug, info, trace};
+use tracing::{debug, info, trace, warn};
 
 use crate::config::Config;
 use crate::error::Error;
-use crate::proto::{Event, Protocol};
+use crate::proto::{Error as ProtocolError, Event, Protocol};
 
 
 type ReturnQueue<P> = (mpsc::Sender<P>, mpsc::Receiver<P>);
@@ -18,8 +22,8 @@ type ReturnQueue<P> = (mpsc::Sender<P>, mpsc::Receiver<P>);
 
 enum State {
     Initializing,
-    Publishing(session::Handle),
-    Playing(session::Handle, session::Watcher),
+    Publishing(SessionId, session::Handle),
+    Playing(Box<session::Watcher>),
     Disconnecting,
 }
 
@@ -30,13 +34,15 @@ where
     S: AsyncRead + AsyncWrite + Unpin,
 {
     id: u64,
+    info: PeerInfo,
     bytes_stream: Framed<S, BytesCodec>,
     session_manager: session::ManagerHandle,
     return_queue: ReturnQueue<Packet>,
     proto: Protocol,
     config: Config,
-    app_name: Option<String>,
+    path: Option<StreamPath>,
     state: State,
+    shutdown: Shutdown,
 }
 
 impl<S> Peer<S>
@@ -46,22 +52,37 @@ where
     pub fn new(
         id: u64,
         stream: S,
+        info: PeerInfo,
         session_manager: session::ManagerHandle,
         config: Config,
+        shutdown: Shutdown,
     ) -> Self {
         Self {
             id,
+            info,
             bytes_stream: Framed::new(stream, BytesCodec::new()),
             session_manager,
             return_queue: mpsc::channel(64),
             proto: Protocol::new(),
             config,
-            app_name: None,
+            path: None,
             state: State::Initializing,
+            shutdown,
         }
     }
 
     pub async fn run(mut self) -> Result<(), Error> {
+        let result = self.process().await;
+
+        if result.is_err() {
+            // make sure the session learns about the publisher going away
+            self.disconnect()?;
+        }
+
+        result
+    }
+
+    async fn process(&mut self) -> Result<(), Error> {
         loop {
             while let Ok(packet) = self.return_queue.1.try_recv() {
                 if self.handle_return_packet(packet).await.is_err() {
@@ -70,25 +91,46 @@ where
             }
 
             match &mut self.state {
-                State::Initializing | State::Publishing(_) => {
+                State::Initializing | State::Publishing(..) => {
                     let val = self.bytes_stream.try_next();
-                    match timeout(self.config.connection_timeout, val).await? {
+                    let received = tokio::select! {
+                        received = timeout(self.config.connection_timeout, val) => Some(received?),
+                        _ = self.shutdown.triggered() => None,
+                    };
+
+                    // viewers stay until their session closed, publishers end it right away
+                    let Some(received) = received else {
+                        info!(id = self.id, "Closing connection for shutdown");
+                        self.release()?;
+                        continue;
+                    };
+
+                    match received {
                         Ok(Some(data)) => {
-                            for event in self.proto.handle_bytes(&data).unwrap() {
+                            let events = self.proto.handle_bytes(&data).inspect_err(|why| {
+                                if matches!(why, ProtocolError::HandshakeFailed) {
+                                    metrics::handshake_failed(self.info.protocol);
+                                }
+                            })?;
+                            for event in events {
                                 self.handle_event(event).await?;
                             }
                         }
                         _ => self.disconnect()?,
                     }
                 }
-                State::Playing(_, watcher) => {
-                    use sync::broadcast::error::RecvError;
-                    match watcher.recv().await {
-                        Ok(packet) => self.send_back(packet).await?,
-                        Err(RecvError::Closed) => self.disconnect()?,
-                        Err(_) => (),
+                State::Playing(watcher) => match watcher.recv().await {
+                    Ok(packet) => self.send_back(packet).await?,
+                    Err(RecvError::Closed) => self.disconnect()?,
+                    Err(RecvError::TooSlow) => {
+                        warn!(id = self.id, "Viewer stayed behind for too long");
+                        self.disconnect()?
                     }
-                }
+                    Err(RecvError::Disconnected) => {
+                        info!(id = self.id, "Viewer was disconnected");
+                        self.disconnect()?
+                    }
+                },
                 State::Disconnecting => {
                     debug!("Disconnecting...");
                     return Ok(());
@@ -98,13 +140,15 @@ where
     }
 
     async fn handle_return_packet(&mut self, packet: Packet) -> Result<(), Error> {
-        let bytes = match packet.content_type {
-            packet::METADATA => self.proto.pack_metadata(packet)?,
-            packet::FLV_VIDEO_H264 => self.proto.pack_video(packet)?,
-            packet::FLV_AUDIO_AAC => self.proto.pack_audio(packet)?,
+        let content_type = packet.content_type;
+        let bytes = match (content_type.container, content_type.kind()) {
+            (_, MediaKind::Data) => self.proto.pack_metadata(packet)?,
+            (Container::Flv, MediaKind::Video) => self.proto.pack_video(packet)?,
+            (Container::Flv, MediaKind::Audio) => self.proto.pack_audio(packet)?,
             _ => {
-                trace!(content_type = ?packet.content_type, "Cannot handle content type");
-                return Err(Error::ReturnPacketFailed(self.id));
+                // other protocols might publish content types that don't fit into RTMP
+                trace!(content_type = ?packet.content_type, "Skipping content type");
+                return Ok(());
             }
         };
         let duration = self.config.connection_timeout;
@@ -122,7 +166,7 @@ where
                     .expect("Failed to return data");
             }
             Event::SendPacket(packet) => {
-                if let State::Publishing(session) = &mut self.state {
+                if let State::Publishing(_, session) = &mut self.state {
                     session
                         .send(Message::Packet(packet))
                         .map_err(|_| Error::SessionSendFailed)?;
@@ -130,47 +174,82 @@ where
             }
             Event::AcquireSession {
                 app_name,
+                stream_name,
                 stream_key,
             } => {
-                self.app_name = Some(app_name.clone());
+                let path = StreamPath::new(app_name, stream_name);
+                self.path = Some(path.clone());
                 let (request, response) = oneshot::channel();
                 self.session_manager
                     .send(ManagerMessage::CreateSession((
-                        app_name, stream_key, request,
+                        path,
+                        stream_key,
+                        self.info.clone(),
+                        request,
                     )))
                     .map_err(|_| Error::SessionCreationFailed)?;
-                let session_sender = response.await.map_err(|_| Error::SessionCreationFailed)?;
-                self.state = State::Publishing(session_sender);
+                let (session_id, session_sender) =
+                    response.await.map_err(|_| Error::SessionCreationFailed)?;
+                self.state = State::Publishing(session_id, session_sender);
             }
-            Event::JoinSession { app_name, .. } => {
+            Event::JoinSession {
+                app_name,
+                stream_name,
+                stream_key,
+                request_id,
+                stream_id,
+            } => {
                 let (request, response) = oneshot::channel();
                 self.session_manager
-                    .send(ManagerMessage::JoinSession((app_name, request)))
+                    .send(ManagerMessage::JoinSession((
+                        StreamPath::new(app_name, stream_name),
+                        stream_key,
+                        self.info.clone(),
+                        request,
+                    )))
                     .map_err(|_| Error::SessionJoinFailed)?;
 
-                match response.await {
-                    Ok((session_sender, session_receiver)) => {
-                        self.state = State::Playing(session_sender, session_receiver);
+                // the session closing while the viewer joins looks the same as it being offline
+                match response.await.unwrap_or(Err(JoinError::NotLive)) {
+                    Ok((init_data, watcher)) => {
+                        let events = self.proto.accept_play(request_id, stream_id)?;
+                        self.return_data(events).await?;
+                        self.send_init_data(init_data).await?;
+                        self.state = State::Playing(Box::new(watcher));
+                    }
+                    Err(why) => {
+                        info!(id = self.id, "Viewer can not play stream: {}", why);
+                        let code = match why {
+                            JoinError::NotLive => "NetStream.Play.StreamNotFound",
+                            _ => "NetStream.Play.Failed",
+                        };
+                        let events = self.proto.reject_play(request_id, code, &why.to_string())?;
+                        self.return_data(events).await?;
+                        self.disconnect()?
                     }
-                    Err(_) => self.disconnect()?,
                 }
             }
-            Event::SendInitData { .. } => {
-                // TODO: better initialization handling
-                if let State::Playing(session, _) = &mut self.state {
-                    let (request, response) = oneshot::channel();
-                    session
-                        .send(Message::GetInitData(request))
-                        .map_err(|_| Error::SessionSendFailed)?;
+            Event::ReleaseSession => self.release()?,
+            Event::LeaveSession => self.disconnect()?,
+        }
 
-                    if let Ok((Some(meta), Some(video), Some(audio))) = response.await {
-                        self.send_back(meta).await?;
-                        self.send_back(video).await?;
-                        self.send_back(audio).await?;
-                    }
-                }
+        Ok(())
+    }
+
+    async fn return_data(&mut self, events: Vec<Event>) -> Result<(), Error> {
+        for event in events {
+            if let Event::ReturnData(data) = event {
+                self.bytes_stream.send(data).await?;
             }
-            Event::ReleaseSession | Event::LeaveSession => self.disconnect()?,
+        }
+
+        Ok(())
+    }
+
+    async fn send_init_data(&mut self, init_data: InitData) -> Result<(), Error> {
+        // Bypasses the return queue, the cached GOP might not fit into it
+        for packet in init_data.into_packets() {
+            self.handle_return_packet(packet).await?;
         }
 
         Ok(())
@@ -184,15 +263,25 @@ where
             .map_err(|_| Error::ReturnPacketFailed(self.id))
     }
 
-    fn disconnect(&mut self) -> Result<(), Error> {
-        if let State::Publishing(session) = &mut self.state {
-            let app_name = self.app_name.clone().unwrap();
-            session
-                .send(Message::Disconnect)
-                .map_err(|_| Error::SessionSendFailed)?;
+    /// Publisher finished its stream on purpose.
+    fn release(&mut self) -> Result<(), Error> {
+        if let State::Publishing(session_id, _) = &self.state {
+            let path = self.path.clone().unwrap();
+            self.session_manager
+                .send(ManagerMessage::ReleaseSession((path, *session_id)))
+                .map_err(|_| Error::SessionReleaseFailed)?;
+        }
+
+        self.state = State::Disconnecting;
 
+        Ok(())
+    }
+
+    fn disconnect(&mut self) -> Result<(), Error> {
+        if let State::Publishing(session_id, _) = &self.state {
+            let path = self.path.clone().unwrap();
             self.session_manager
-                .send(ManagerMessage::ReleaseSession(app_name))
+                .send(ManagerMessage::PublisherLost((path, *session_id)))
                 .map_err(|_| Error::SessionReleaseFailed)?;
         }
 
@@ -1,7 +1,8 @@
 use std::convert::{TryFrom, TryInto};
 
 use bytes::Bytes;
-use javelin_types::{packet, Packet};
+use javelin_types::packet::{self, Codec, Container, ContentType};
+use javelin_types::Packet;
 use rml_rtmp::handshake::{Handshake, HandshakeProcessResult, PeerType};
 use rml_rtmp::sessions::{
     ServerSession, ServerSessionConfig, ServerSessionEvent, ServerSessionResult,
@@ -43,14 +44,15 @@ pub enum Event {
     SendPacket(Packet),
     AcquireSession {
         app_name: String,
+        stream_name: String,
         stream_key: String,
     },
     JoinSession {
         app_name: String,
-        stream_key: String,
-    },
-    SendInitData {
-        app_name: String,
+        stream_name: String,
+        stream_key: Option<String>,
+        request_id: u32,
+        stream_id: u32,
     },
     ReleaseSession,
     LeaveSession,
@@ -150,6 +152,28 @@ impl Protocol {
         self.handle_results(results)
     }
 
+    /// Starts playback once the session manager admitted the viewer.
+    pub fn accept_play(&mut self, request_id: u32, stream_id: u32) -> Result<Vec<Event>, Error> {
+        self.accept_request(request_id)?;
+        self.state = State::Playing { stream_id };
+        Ok(self.return_queue.drain(..).collect())
+    }
+
+    pub fn reject_play(
+        &mut self,
+        request_id: u32,
+        code: &str,
+        description: &str,
+    ) -> Result<Vec<Event>, Error> {
+        let results = self
+            .session()?
+            .reject_request(request_id, code, description)
+            .map_err(|_| Error::RequestRejected)?;
+        self.handle_results(results)?;
+        self.state = State::Finished;
+        Ok(self.return_queue.drain(..).collect())
+    }
+
     pub fn pack_metadata(&mut self, packet: Packet) -> Result<Vec<u8>, Error> {
         let stream_id = self.stream_id()?;
         let metadata = convert::into_metadata(packet.try_into().unwrap());
@@ -224,9 +248,11 @@ impl Protocol {
                 stream_key,
                 ..
             } => {
+                let (stream_name, stream_key) = parse_stream_name(&stream_key);
                 self.emit(Event::AcquireSession {
                     app_name,
-                    stream_key,
+                    stream_name,
+                    stream_key: stream_key.unwrap_or_default(),
                 });
                 self.accept_request(request_id)?;
                 self.state = State::Publishing;
@@ -242,13 +268,15 @@ impl Protocol {
                 stream_id,
                 ..
             } => {
+                // accepted or rejected once the session manager decided
+                let (stream_name, stream_key) = parse_stream_name(&stream_key);
                 self.emit(Event::JoinSession {
-                    app_name: app_name.clone(),
+                    app_name,
+                    stream_name,
                     stream_key,
+                    request_id,
+                    stream_id,
                 });
-                self.accept_request(request_id)?;
-                self.emit(Event::SendInitData { app_name });
-                self.state = State::Playing { stream_id };
             }
             PlayStreamFinished { .. } => {
                 self.emit(Event::LeaveSession);
@@ -257,13 +285,15 @@ impl Protocol {
             AudioDataReceived {
                 data, timestamp, ..
             } => {
-                let packet = Packet::new(packet::FLV_AUDIO_AAC, Some(timestamp.value), data);
+                let content_type = ContentType::new(Container::Flv, audio_codec(&data));
+                let packet = Packet::new(content_type, Some(timestamp.value), data);
                 self.emit(Event::SendPacket(packet));
             }
             VideoDataReceived {
                 data, timestamp, ..
             } => {
-                let packet = Packet::new(packet::FLV_VIDEO_H264, Some(timestamp.value), data);
+                let content_type = ContentType::new(Container::Flv, video_codec(&data));
+                let packet = Packet::new(content_type, Some(timestamp.value), data);
                 self.emit(Event::SendPacket(packet));
             }
             StreamMetadataChanged { metadata, .. } => {
@@ -304,3 +334,58 @@ impl Default for Protocol {
         }
     }
 }
+
+
+/// Splits a publish or play name like `stream?key=secret` into the stream name and its key.
+fn parse_stream_name(name: &str) -> (String, Option<String>) {
+    let (stream_name, query) = name.split_once('?').unwrap_or((name, ""));
+    let stream_key = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("key="))
+        .map(str::to_string);
+
+    (stream_name.to_string(), stream_key)
+}
+
+
+/// Reads the codec from an FLV video tag, either a legacy codec ID or an enhanced RTMP FourCC.
+fn video_codec(tag: &[u8]) -> Codec {
+    let Some(&header) = tag.first() else {
+        return Codec::Unknown;
+    };
+
+    if header & 0x80 != 0 {
+        return match tag.get(1..5) {
+            Some(b"avc1") => Codec::H264,
+            Some(b"hvc1") => Codec::Hevc,
+            Some(b"av01") => Codec::Av1,
+            _ => Codec::Unknown,
+        };
+    }
+
+    match header & 0x0F {
+        7 => Codec::H264,
+        12 => Codec::Hevc,
+        _ => Codec::Unknown,
+    }
+}
+
+
+/// Reads the codec from an FLV audio tag, either a legacy sound format or an enhanced RTMP FourCC.
+fn audio_codec(tag: &[u8]) -> Codec {
+    let Some(&header) = tag.first() else {
+        return Codec::Unknown;
+    };
+
+    match header >> 4 {
+        2 | 14 => Codec::Mp3,
+        10 => Codec::Aac,
+        9 => match tag.get(1..5) {
+            Some(b"mp4a") => Codec::Aac,
+            Some(b".mp3") => Codec::Mp3,
+            Some(b"Opus") => Codec::Opus,
+            _ => Codec::Unknown,
+        },
+        _ => Codec::Unknown,
+    }
+}
@@ -3,7 +3,8 @@ use std::io::ErrorKind as IoErrorKind;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use anyhow::Result;
-use javelin_core::{session, Config};
+use javelin_core::session::{self, PeerInfo, Protocol};
+use javelin_core::{Config, Shutdown};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
 use tracing::{error, info};
@@ -41,44 +42,56 @@ impl From<&ClientId> for u64 {
 
 pub struct Service {
     config: RtmpConfig,
+    source: Config,
     session_manager: session::ManagerHandle,
     client_id: ClientId,
 }
 
 impl Service {
     pub fn new(session_manager: session::ManagerHandle, config: &Config) -> Self {
+        config.register_section::<RtmpConfig>("rtmp", &["addr", "tls"]);
+
         Self {
             session_manager,
             config: config.get("rtmp").unwrap_or_default(),
+            source: config.clone(),
             client_id: ClientId::default(),
         }
     }
 
-    pub async fn run(self) {
+    pub async fn run(self, shutdown: Shutdown) {
         #[cfg(not(feature = "rtmps"))]
-        let res = self.handle_rtmp().await;
+        let res = self.handle_rtmp(&shutdown).await;
         #[cfg(feature = "rtmps")]
-        let res = tokio::try_join!(self.handle_rtmp(), self.handle_rtmps());
+        let res = tokio::try_join!(self.handle_rtmp(&shutdown), self.handle_rtmps(&shutdown));
 
         if let Err(err) = res {
             error!("{}", err);
         }
     }
 
-    async fn handle_rtmp(&self) -> Result<()> {
+    async fn handle_rtmp(&self, shutdown: &Shutdown) -> Result<()> {
         let addr = &self.config.addr;
         let listener = TcpListener::bind(addr).await?;
         info!("Listening for RTMP connections on {}", addr);
 
         loop {
-            let (tcp_stream, _addr) = listener.accept().await?;
-            self.process(tcp_stream);
+            let (tcp_stream, addr) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = shutdown.triggered() => break,
+            };
+            let info = PeerInfo::new(Protocol::Rtmp, addr);
+            self.process(tcp_stream, info, shutdown.clone());
             self.client_id.increment();
         }
+
+        info!("Stopped accepting RTMP connections");
+
+        Ok(())
     }
 
     #[cfg(feature = "rtmps")]
-    async fn handle_rtmps(&self) -> Result<()> {
+    async fn handle_rtmps(&self, shutdown: &Shutdown) -> Result<()> {
         use tracing::info;
 
         if !self.config.tls.enabled {
@@ -97,24 +110,36 @@ impl Service {
         };
 
         loop {
-            let (tcp_stream, _addr) = listener.accept().await?;
+            let (tcp_stream, addr) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = shutdown.triggered() => break,
+            };
             tcp_stream.set_keepalive(Some(Duration::from_secs(30)))?;
             let tls_stream = tls_acceptor.accept(tcp_stream).await?;
-            self.process(tls_stream);
+            let info = PeerInfo::new(Protocol::Rtmps, addr);
+            self.process(tls_stream, info, shutdown.clone());
         }
+
+        info!("Stopped accepting RTMPS connections");
+
+        Ok(())
     }
 
-    fn process<S>(&self, stream: S)
+    fn process<S>(&self, stream: S, info: PeerInfo, shutdown: Shutdown)
     where
         S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
     {
-        info!("New client connection: {}", &self.client_id);
+        info!("New client connection: {} ({})", &self.client_id, info);
         let id = (&self.client_id).into();
+        // listeners stay as they are, everything else follows reloads
+        let config = self.source.get("rtmp").unwrap_or_default();
         let peer = Peer::new(
             id,
             stream,
+            info,
             self.session_manager.clone(),
-            self.config.clone(),
+            config,
+            shutdown,
         );
 
         tokio::spawn(async move {
@@ -1,6 +1,7 @@
 mod config;
 mod peer;
 mod service;
+mod transmux;
 
 
 use std::io;
@@ -25,6 +26,12 @@ pub enum Error {
     #[error("Client is not authorized to access the resource")]
     Unauthorized,
 
+    #[error("Requested stream is not live")]
+    NotLive,
+
+    #[error("Requested stream is not accepting viewers")]
+    Unavailable,
+
     #[error("Failed to parse the given stream id")]
     StreamIdDecodeFailed { stream_id: String },
 
@@ -2,56 +2,85 @@ use std::io;
 use std::time::Instant;
 
 use futures::{SinkExt, StreamExt};
-use javelin_core::session::Message;
+use javelin_core::session::{
+    self, ManagerMessage, Message, RecvError, SessionId, StreamPath, Watcher,
+};
+use javelin_core::Shutdown;
 use javelin_types::{packet, Packet};
 use srt_tokio::SrtSocket;
-use tokio::sync::broadcast::error::RecvError;
-use tokio::sync::broadcast::Receiver;
 use tokio::sync::mpsc::UnboundedSender;
-use tracing::{error, trace, warn};
+use tracing::{error, info, trace, warn};
 
+use crate::transmux::Transmuxer;
 use crate::Error;
 
 
 type SessionSender = UnboundedSender<Message>;
-type SessionReceiver = Receiver<Packet>;
+
+
+/// Largest payload of a live mode SRT packet, seven TS packets.
+const MAX_PAYLOAD_SIZE: usize = 7 * 188;
 
 
 enum State {
-    Publish,
-    Receive(SessionReceiver),
+    Publish(StreamPath, SessionId, SessionSender),
+    Receive(Box<Watcher>),
 }
 
 
 pub struct Peer {
-    session_sender: SessionSender,
     state: State,
 }
 
 impl Peer {
-    pub fn new_publishing(session_sender: SessionSender) -> Self {
+    pub fn new_publishing(
+        path: StreamPath,
+        session_id: SessionId,
+        session_sender: SessionSender,
+    ) -> Self {
         Self {
-            session_sender,
-            state: State::Publish,
+            state: State::Publish(path, session_id, session_sender),
         }
     }
 
-    pub fn new_receiving(session_sender: SessionSender, session_receiver: SessionReceiver) -> Self {
+    pub fn new_receiving(session_receiver: Watcher) -> Self {
         Self {
-            session_sender,
-            state: State::Receive(session_receiver),
+            state: State::Receive(Box::new(session_receiver)),
+        }
+    }
+
+    /// Gives up the session of a publisher whose connection could not be established.
+    pub fn release(self, session_manager: &session::ManagerHandle) {
+        if let State::Publish(path, session_id, _) = self.state {
+            let message = ManagerMessage::ReleaseSession((path, session_id));
+            if session_manager.send(message).is_err() {
+                error!("Failed to release session");
+            }
         }
     }
 }
 
 
 #[tracing::instrument(skip_all)]
-pub(crate) async fn handle_peer(peer: Peer, sock: SrtSocket) {
+pub(crate) async fn handle_peer(
+    peer: Peer,
+    sock: SrtSocket,
+    session_manager: session::ManagerHandle,
+    shutdown: Shutdown,
+) {
     let result = match peer.state {
-        State::Publish => handle_publishing_peer(sock, peer.session_sender).await,
-        State::Receive(session_receiver) => {
-            handle_receiving_peer(sock, peer.session_sender, session_receiver).await
+        State::Publish(path, session_id, session_sender) => {
+            let result = handle_publishing_peer(sock, session_sender, &shutdown).await;
+            let message = match result {
+                Ok(_) => ManagerMessage::ReleaseSession((path, session_id)),
+                Err(_) => ManagerMessage::PublisherLost((path, session_id)),
+            };
+            if session_manager.send(message).is_err() {
+                error!("Failed to release session");
+            }
+            result
         }
+        State::Receive(session_receiver) => handle_receiving_peer(sock, *session_receiver).await,
     };
 
     if let Err(err) = result {
@@ -65,13 +94,12 @@ pub(crate) async fn handle_peer(peer: Peer, sock: SrtSocket) {
 #[tracing::instrument(skip_all)]
 async fn handle_receiving_peer(
     mut sock: SrtSocket,
-    _session_sender: UnboundedSender<Message>,
-    mut session_receiver: Receiver<Packet>,
+    mut session_receiver: Watcher,
 ) -> Result<(), Error> {
     let socket_id = sock.settings().remote_sockid;
     trace!(?socket_id);
 
-    loop {
+    'receive: loop {
         match session_receiver.recv().await {
             Ok(Packet {
                 content_type: packet::CONTAINER_MPEGTS,
@@ -79,21 +107,28 @@ async fn handle_receiving_peer(
                 ..
             }) => {
                 let timestamp = Instant::now();
-                match sock.send((timestamp, payload)).await {
-                    Ok(_) => (),
-                    Err(err) if err.kind() == io::ErrorKind::NotConnected => break,
-                    Err(err) => return Err(err.into()),
+                for offset in (0..payload.len()).step_by(MAX_PAYLOAD_SIZE) {
+                    let end = payload.len().min(offset + MAX_PAYLOAD_SIZE);
+                    match sock.send((timestamp, payload.slice(offset..end))).await {
+                        Ok(_) => (),
+                        Err(err) if err.kind() == io::ErrorKind::NotConnected => break 'receive,
+                        Err(err) => return Err(err.into()),
+                    }
                 }
             }
             Ok(Packet { content_type, .. }) => {
-                trace!(?content_type, "Cannot handle packet at this point");
-                break;
+                trace!(?content_type, "Skipping packet not meant for SRT viewers");
             }
             Err(RecvError::Closed) => {
                 break;
             }
-            Err(RecvError::Lagged(skipped_amount)) => {
-                warn!(%skipped_amount ,"Client receiver lagged behind");
+            Err(RecvError::TooSlow) => {
+                warn!("Client receiver stayed behind for too long, disconnecting");
+                break;
+            }
+            Err(RecvError::Disconnected) => {
+                info!("Client receiver was disconnected");
+                break;
             }
         }
     }
@@ -108,16 +143,31 @@ async fn handle_receiving_peer(
 async fn handle_publishing_peer(
     mut sock: SrtSocket,
     session_sender: UnboundedSender<Message>,
+    shutdown: &Shutdown,
 ) -> Result<(), Error> {
     let socket_id = sock.settings().remote_sockid;
     trace!(?socket_id);
 
-    while let Some(data) = sock.next().await {
+    let mut transmuxer = Transmuxer::new();
+
+    // SRT viewers get the stream muxed again by the session, like any other
+    'receive: loop {
+        let data = tokio::select! {
+            data = sock.next() => match data {
+                Some(data) => data,
+                None => break,
+            },
+            _ = shutdown.triggered() => {
+                info!("Closing connection for shutdown");
+                break;
+            }
+        };
         let (_, bytes) = data?;
-        let timestamp = chrono::Utc::now().timestamp();
-        let packet = Packet::new(packet::CONTAINER_MPEGTS, Some(timestamp), bytes);
-        if session_sender.send(Message::Packet(packet)).is_err() {
-            break;
+
+        for packet in transmuxer.push(&bytes) {
+            if session_sender.send(Message::Packet(packet)).is_err() {
+                break 'receive;
+            }
         }
     }
 
@@ -3,8 +3,8 @@ use std::borrow::Cow;
 use base64::engine::general_purpose::URL_SAFE as BASE64_URL_SAFE;
 use base64::Engine;
 use futures::StreamExt;
-use javelin_core::session::ManagerMessage;
-use javelin_core::{session, Config};
+use javelin_core::session::{self, JoinError, ManagerMessage, PeerInfo, Protocol, StreamPath};
+use javelin_core::{Config, Shutdown};
 use srt_tokio::access::{
     AccessControlList, ConnectionMode, ServerRejectReason, StandardAccessControlEntry,
 };
@@ -26,28 +26,43 @@ pub struct Service {
 
 impl Service {
     pub fn new(session_manager: session::ManagerHandle, config: &Config) -> Self {
+        config.register_section::<SrtConfig>("srt", &["addr"]);
+
         Service {
             session_manager,
             config: config.get("srt").unwrap_or_default(),
         }
     }
 
-    pub async fn run(self) {
+    pub async fn run(self, shutdown: Shutdown) {
         let addr = self.config.addr;
 
         let (_listener, mut conn) = SrtListener::builder().bind(addr).await.unwrap();
 
         info!("Listening for SRT connections on {}", &addr);
 
-        while let Some(conn_req) = conn.incoming().next().await {
+        let incoming = conn.incoming();
+
+        loop {
+            let conn_req = tokio::select! {
+                conn_req = incoming.next() => match conn_req {
+                    Some(conn_req) => conn_req,
+                    None => break,
+                },
+                _ = shutdown.triggered() => break,
+            };
+
             let session_manager = self.session_manager.clone();
+            let shutdown = shutdown.clone();
 
             tokio::spawn(async move {
-                if let Err(err) = handle_request(session_manager, conn_req).await {
+                if let Err(err) = handle_request(session_manager, conn_req, shutdown).await {
                     error!(%err);
                 }
             });
         }
+
+        info!("Stopped accepting SRT connections");
     }
 }
 
@@ -96,13 +111,14 @@ fn parse_stream_id(stream_id: &StreamId) -> Result<AccessControlList, Error> {
 async fn authorize(
     session_handle: session::ManagerHandle,
     stream_id: Option<&StreamId>,
+    peer_info: PeerInfo,
 ) -> Result<Peer, Error> {
     let stream_id = stream_id.ok_or(Error::StreamIdMissing)?;
 
     let acl = parse_stream_id(stream_id)?;
     let acl = acl.0.into_iter().map(StandardAccessControlEntry::try_from);
 
-    let mut user_ident = None;
+    let mut stream_key = None;
     let mut res_name = None;
     let mut conn_mode = None;
 
@@ -110,8 +126,8 @@ async fn authorize(
         let entry = entry.map_err(|_| Error::InvalidAccessControlParams)?;
 
         match entry {
-            StandardAccessControlEntry::UserName(uname) => {
-                user_ident = Some(uname);
+            StandardAccessControlEntry::SessionId(key) => {
+                stream_key = Some(key);
             }
             StandardAccessControlEntry::ResourceName(rname) => {
                 res_name = Some(rname);
@@ -123,39 +139,53 @@ async fn authorize(
         }
     }
 
-    let (user_ident, res_name, conn_mode) = match (user_ident, res_name, conn_mode) {
-        (Some(u), Some(r), Some(m)) => (u, r, m),
-        (Some(u), Some(r), None) => (u, r, ConnectionMode::Request),
-        _ => return Err(Error::MissingAccessControlParams),
-    };
+    let res_name = res_name.ok_or(Error::MissingAccessControlParams)?;
+    let conn_mode = conn_mode.unwrap_or(ConnectionMode::Request);
+
+    // resources are addressed as `app/stream`, credentials travel separately in `s`
+    let path = res_name
+        .parse::<StreamPath>()
+        .map_err(|_| Error::InvalidAccessControlParams)?;
 
     let peer = match conn_mode {
         ConnectionMode::Publish => {
             let (tx, rx) = oneshot::channel();
 
-            let message =
-                ManagerMessage::CreateSession((res_name.to_string(), user_ident.to_string(), tx));
+            let message = ManagerMessage::CreateSession((
+                path.clone(),
+                stream_key.unwrap_or_default(),
+                peer_info,
+                tx,
+            ));
 
             session_handle
                 .send(message)
                 .map_err(|_| Error::Unauthorized)?;
 
-            let session_tx = rx.await.map_err(|_| Error::Unauthorized)?;
+            let (session_id, session_tx) = rx.await.map_err(|_| Error::Unauthorized)?;
 
-            Peer::new_publishing(session_tx)
+            Peer::new_publishing(path, session_id, session_tx)
         }
         ConnectionMode::Request => {
             let (tx, rx) = oneshot::channel();
 
-            let message = ManagerMessage::JoinSession((res_name.to_string(), tx));
+            let message = ManagerMessage::JoinSession((path, stream_key, peer_info, tx));
 
             session_handle
                 .send(message)
                 .map_err(|_| Error::Unauthorized)?;
 
-            let (session_tx, session_rx) = rx.await.map_err(|_| Error::Unauthorized)?;
-
-            Peer::new_receiving(session_tx, session_rx)
+            // The transport stream starts at the next keyframe, there is no init data
+            let (_init_data, session_rx) =
+                rx.await
+                    .unwrap_or(Err(JoinError::NotLive))
+                    .map_err(|why| match why {
+                        JoinError::NotLive => Error::NotLive,
+                        JoinError::NotAuthorized => Error::Unauthorized,
+                        JoinError::Unavailable => Error::Unavailable,
+                    })?;
+
+            Peer::new_receiving(session_rx)
         }
         _ => return Err(Error::ModeNotSupported),
     };
@@ -167,14 +197,23 @@ async fn authorize(
 async fn handle_request(
     session_handle: session::ManagerHandle,
     conn_req: ConnectionRequest,
+    shutdown: Shutdown,
 ) -> Result<(), Error> {
     let stream_id = conn_req.stream_id();
+    let peer_info = PeerInfo::new(Protocol::Srt, conn_req.remote());
 
-    match authorize(session_handle, stream_id).await {
+    match authorize(session_handle.clone(), stream_id, peer_info).await {
         Ok(peer) => {
             trace!("Accepting request");
-            let sock = conn_req.accept(None).await?;
-            tokio::spawn(async move { handle_peer(peer, sock).await });
+            let sock = match conn_req.accept(None).await {
+                Ok(sock) => sock,
+                Err(err) => {
+                    // the session would keep the stream path taken otherwise
+                    peer.release(&session_handle);
+                    return Err(err.into());
+                }
+            };
+            tokio::spawn(async move { handle_peer(peer, sock, session_handle, shutdown).await });
         }
         Err(err) => {
             reject_request(conn_req, err).await?;
@@ -194,6 +233,8 @@ async fn reject_request(conn_req: ConnectionRequest, error: Error) -> Result<(),
         | Error::MissingAccessControlParams => ServerRejectReason::BadRequest,
         Error::ModeNotSupported => ServerRejectReason::BadMode,
         Error::Unauthorized => ServerRejectReason::Unauthorized,
+        Error::NotLive => ServerRejectReason::NotFound,
+        Error::Unavailable => ServerRejectReason::Overload,
         Error::Io(_) => ServerRejectReason::InternalServerError,
     };
 
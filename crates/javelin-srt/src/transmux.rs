@@ -0,0 +1,276 @@
+use javelin_codec::aac::AudioDataTransportStream;
+use javelin_codec::avc::config::DecoderConfigurationRecord;
+use javelin_codec::avc::nal::{self, UnitType};
+use javelin_codec::avc::{AnnexB, Avcc};
+use javelin_codec::flv::tag::audio::{AacPacketType, AudioFormat};
+use javelin_codec::flv::tag::video::{AvcPacketType, FrameType};
+use javelin_codec::flv::tag::{AudioData, VideoData};
+use javelin_codec::mpegts::{Demuxer, ElementaryPacket, StreamType};
+use javelin_codec::{CodecError, ReadFormat, WriteFormat};
+use javelin_types::{packet, Packet, Timescale, Timestamp};
+use tracing::{debug, trace};
+
+
+/// Samples per AAC frame, used to space out frames that share a single PES timestamp.
+const AAC_FRAME_SAMPLES: u64 = 1024;
+
+
+/// Turns the transport stream of a publisher into the FLV packets RTMP ingest produces.
+///
+/// Sequence headers are derived from the in-band parameter sets and ADTS headers, and sent
+/// whenever they change.
+#[derive(Default)]
+pub(crate) struct Transmuxer {
+    demuxer: Demuxer,
+    dcr: Option<DecoderConfigurationRecord>,
+    video_seq_header: Vec<u8>,
+    audio_seq_header: Vec<u8>,
+}
+
+impl Transmuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, input: &[u8]) -> Vec<Packet> {
+        let elementary_packets = match self.demuxer.push(input) {
+            Ok(elementary_packets) => elementary_packets,
+            Err(why) => {
+                debug!("Failed to demux transport stream: {}", why);
+                return Vec::new();
+            }
+        };
+
+        let mut packets = Vec::new();
+
+        for elementary_packet in elementary_packets {
+            let result = match elementary_packet.stream_type {
+                StreamType::H264 => self.push_video(elementary_packet, &mut packets),
+                StreamType::AdtsAac => self.push_audio(elementary_packet, &mut packets),
+            };
+
+            if let Err(why) = result {
+                debug!("Dropping elementary stream packet: {}", why);
+            }
+        }
+
+        packets
+    }
+
+    fn push_video(
+        &mut self,
+        elementary_packet: ElementaryPacket,
+        packets: &mut Vec<Packet>,
+    ) -> Result<(), CodecError> {
+        let nalus: Vec<nal::Unit> = AnnexB.read_format(&elementary_packet.data, &())?.into();
+
+        let sps = nalus
+            .iter()
+            .find(|nalu| nalu.kind == UnitType::SequenceParameterSet);
+        let pps = nalus
+            .iter()
+            .find(|nalu| nalu.kind == UnitType::PictureParameterSet);
+
+        if let (Some(sps), Some(pps)) = (sps, pps) {
+            let dcr = DecoderConfigurationRecord::from_parameter_sets(sps.clone(), pps.clone())?;
+            let seq_header = Vec::from(&dcr);
+
+            if seq_header != self.video_seq_header {
+                trace!("Sending new video sequence header");
+                self.video_seq_header = seq_header.clone();
+                let tag = VideoData {
+                    frame_type: FrameType::KeyFrame,
+                    packet_type: AvcPacketType::SequenceHeader,
+                    composition_time: 0,
+                    body: seq_header.into(),
+                };
+                packets.push(Packet::new(
+                    packet::FLV_VIDEO_H264,
+                    Some(mpeg_timestamp(elementary_packet.dts)),
+                    Vec::from(tag),
+                ));
+            }
+
+            self.dcr = Some(dcr);
+        }
+
+        let Some(dcr) = &self.dcr else {
+            trace!("Waiting for parameter sets before forwarding video");
+            return Ok(());
+        };
+
+        let keyframe = nalus.iter().any(|nalu| nalu.kind == UnitType::IdrPicture);
+
+        // parameter sets travel in the sequence header, delimiters have no place in FLV
+        let nalus: Vec<_> = nalus
+            .into_iter()
+            .filter(|nalu| {
+                !matches!(
+                    nalu.kind,
+                    UnitType::SequenceParameterSet
+                        | UnitType::PictureParameterSet
+                        | UnitType::AccessUnitDelimiter
+                )
+            })
+            .collect();
+
+        if nalus.is_empty() {
+            return Ok(());
+        }
+
+        let composition_time = elementary_packet.pts.saturating_sub(elementary_packet.dts) / 90;
+        let tag = VideoData {
+            frame_type: if keyframe {
+                FrameType::KeyFrame
+            } else {
+                FrameType::InterFrame
+            },
+            packet_type: AvcPacketType::NalUnit,
+            composition_time: composition_time as i32,
+            body: Avcc.write_format(nalus.into(), dcr)?.into(),
+        };
+        packets.push(Packet::new(
+            packet::FLV_VIDEO_H264,
+            Some(mpeg_timestamp(elementary_packet.dts)),
+            Vec::from(tag),
+        ));
+
+        Ok(())
+    }
+
+    fn push_audio(
+        &mut self,
+        elementary_packet: ElementaryPacket,
+        packets: &mut Vec<Packet>,
+    ) -> Result<(), CodecError> {
+        let frames = AudioDataTransportStream::read_frames(&elementary_packet.data)?;
+
+        for (index, (asc, frame)) in frames.into_iter().enumerate() {
+            let frequency = asc.sampling_frequency_index.frequency().unwrap_or(44100);
+            let ticks_per_second = Timescale::Mpeg.ticks_per_second();
+            let offset = index as u64 * AAC_FRAME_SAMPLES * ticks_per_second / frequency as u64;
+            let timestamp = mpeg_timestamp(elementary_packet.pts + offset);
+
+            let seq_header = Vec::from(&asc);
+            if seq_header != self.audio_seq_header {
+                trace!("Sending new audio sequence header");
+                self.audio_seq_header = seq_header.clone();
+                let tag = aac_tag(AacPacketType::SequenceHeader, seq_header, frequency);
+                packets.push(Packet::new(
+                    packet::FLV_AUDIO_AAC,
+                    Some(timestamp),
+                    Vec::from(tag),
+                ));
+            }
+
+            let tag = aac_tag(AacPacketType::Raw, frame.into(), frequency);
+            packets.push(Packet::new(
+                packet::FLV_AUDIO_AAC,
+                Some(timestamp),
+                Vec::from(tag),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Keeps the full precision of the transport stream, the session unwraps the 33 bit rollover.
+fn mpeg_timestamp(ticks: u64) -> Timestamp {
+    Timestamp::new(ticks, Timescale::Mpeg)
+}
+
+
+fn aac_tag(aac_packet_type: AacPacketType, body: Vec<u8>, frequency: u32) -> AudioData {
+    // the actual configuration is in the sequence header, FLV players ignore these fields
+    AudioData {
+        format: AudioFormat::Aac,
+        sampling_rate: frequency.into(),
+        sample_size: 16,
+        stereo: true,
+        aac_packet_type,
+        body: body.into(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // PAT, PMT, an H.264 keyframe with parameter sets, two ADTS frames and two more
+    // H.264 frames, the last of which stays pending in the demuxer
+    const SAMPLE: &[u8] = include_bytes!("../testdata/h264_aac.ts");
+
+    fn summary(packets: &[Packet]) -> Vec<(packet::ContentType, Option<Timestamp>, Vec<u8>)> {
+        packets
+            .iter()
+            .map(|p| (p.content_type, p.timestamp, p.payload.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn transmuxes_sample() {
+        let packets = Transmuxer::new().push(SAMPLE);
+
+        let types: Vec<_> = packets
+            .iter()
+            .map(|p| (p.content_type, p.timestamp))
+            .collect();
+        assert_eq!(types, [
+            (packet::FLV_AUDIO_AAC, Some(mpeg_timestamp(90000))),
+            (packet::FLV_AUDIO_AAC, Some(mpeg_timestamp(90000))),
+            (packet::FLV_AUDIO_AAC, Some(mpeg_timestamp(92089))),
+            (packet::FLV_VIDEO_H264, Some(mpeg_timestamp(90000))),
+            (packet::FLV_VIDEO_H264, Some(mpeg_timestamp(90000))),
+            (packet::FLV_VIDEO_H264, Some(mpeg_timestamp(93003))),
+        ]);
+
+        // AAC LC, 44.1 kHz, stereo
+        assert_eq!(packets[0].payload[..], [0xAF, 0x00, 0x12, 0x10]);
+        assert_eq!(packets[1].payload[..4], [0xAF, 0x01, 0x21, 0x10]);
+        assert_eq!(packets[1].payload.len(), 2 + 25);
+        assert_eq!(packets[2].payload.len(), 2 + 29);
+
+        assert_eq!(packets[3].payload[..5], [0x17, 0x00, 0x00, 0x00, 0x00]);
+        let dcr = DecoderConfigurationRecord::try_from(&packets[3].payload[5..]).unwrap();
+        assert_eq!((dcr.profile_indication, dcr.level_indication), (0x64, 0x1F));
+        assert_eq!((dcr.sps.len(), dcr.pps.len()), (1, 1));
+
+        // one length prefixed NAL unit each, 33 ms composition offset
+        for (packet, header, unit_type) in [(&packets[4], 0x17, 0x65), (&packets[5], 0x27, 0x41)] {
+            let payload = &packet.payload;
+            assert_eq!(payload[..5], [header, 0x01, 0x00, 0x00, 0x21]);
+
+            let len = u32::from_be_bytes([payload[5], payload[6], payload[7], payload[8]]);
+            assert_eq!(payload.len(), 9 + len as usize);
+            assert_eq!(payload[9], unit_type);
+        }
+    }
+
+    #[test]
+    fn accepts_unaligned_input() {
+        let mut transmuxer = Transmuxer::new();
+        let packets: Vec<_> = SAMPLE
+            .chunks(100)
+            .flat_map(|chunk| transmuxer.push(chunk))
+            .collect();
+
+        assert_eq!(summary(&packets), summary(&Transmuxer::new().push(SAMPLE)));
+    }
+
+    #[test]
+    fn sends_sequence_headers_once() {
+        let mut transmuxer = Transmuxer::new();
+        transmuxer.push(SAMPLE);
+
+        let packets = transmuxer.push(SAMPLE);
+        let headers = packets
+            .iter()
+            .filter(|p| matches!(p.payload[..2], [0xAF, 0x00] | [0x17, 0x00]))
+            .count();
+
+        assert_eq!(headers, 0);
+    }
+}
@@ -12,49 +12,106 @@ type StringMap = HashMap<String, String>;
 type StrMap<'a> = HashMap<&'a str, String>;
 
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+/// Unit a [`Timestamp`] is counted in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Timescale {
+    /// Used by RTMP and FLV.
+    #[default]
+    Milliseconds,
+
+    /// The 90 kHz clock of MPEG transport streams.
+    Mpeg,
+}
+
+impl Timescale {
+    pub const fn ticks_per_second(self) -> u64 {
+        match self {
+            Self::Milliseconds => 1_000,
+            Self::Mpeg => 90_000,
+        }
+    }
+}
+
+
+/// Presentation time of a packet.
+///
+/// Conversions from and to plain integers are in milliseconds, like RTMP timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Timestamp {
     value: u64,
+    #[serde(default)]
+    timescale: Timescale,
+}
+
+impl Timestamp {
+    pub const fn new(value: u64, timescale: Timescale) -> Self {
+        Self { value, timescale }
+    }
+
+    pub const fn value(&self) -> u64 {
+        self.value
+    }
+
+    pub const fn timescale(&self) -> Timescale {
+        self.timescale
+    }
+
+    /// Converts into another unit, rounding down.
+    pub fn rescale(self, timescale: Timescale) -> Self {
+        let value = self.value as u128 * timescale.ticks_per_second() as u128
+            / self.timescale.ticks_per_second() as u128;
+
+        Self {
+            value: value as u64,
+            timescale,
+        }
+    }
+
+    pub fn as_millis(self) -> u64 {
+        self.rescale(Timescale::Milliseconds).value
+    }
 }
 
 impl From<u32> for Timestamp {
     fn from(val: u32) -> Self {
-        Self { value: val.into() }
+        Self::new(val.into(), Timescale::Milliseconds)
     }
 }
 
+/// Wraps around like RTMP timestamps do.
 impl From<Timestamp> for u32 {
     fn from(val: Timestamp) -> Self {
-        val.value as u32
+        val.as_millis() as u32
     }
 }
 
 impl From<u64> for Timestamp {
     fn from(val: u64) -> Self {
-        Self { value: val }
+        Self::new(val, Timescale::Milliseconds)
     }
 }
 
 impl From<Timestamp> for u64 {
     fn from(val: Timestamp) -> Self {
-        val.value
+        val.as_millis()
     }
 }
 
 impl From<i64> for Timestamp {
     fn from(val: i64) -> Self {
-        Self { value: val as u64 }
+        Self::new(val.max(0) as u64, Timescale::Milliseconds)
     }
 }
 
 impl From<Timestamp> for i64 {
     fn from(val: Timestamp) -> Self {
-        val.value as i64
+        val.as_millis() as i64
     }
 }
 
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Metadata(StringMap);
 
 impl Metadata {
@@ -9,5 +9,5 @@ pub type Error = Box<dyn std::error::Error>;
 // foreign re-exports
 pub use async_trait::async_trait;
 
-pub use self::data::{Metadata, Timestamp};
+pub use self::data::{Metadata, Timescale, Timestamp};
 pub use self::packet::Packet;
@@ -1,8 +1,26 @@
+mod memory;
+
+
+use std::time::Duration;
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+pub use self::memory::MemoryRepository;
 use crate::async_trait;
 
 
+/// Name of the key managed by [`UserRepository::add_user_with_key`].
+pub const DEFAULT_KEY_NAME: &str = "default";
+
+/// Characters of a generated key kept in the clear, see [`StreamKey::prefix`].
+pub const KEY_PREFIX_LEN: usize = 4;
+
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Database lookup failed")]
@@ -10,26 +28,280 @@ pub enum Error {
 
     #[error("Database update failed")]
     UpdateFailed,
+
+    #[error("No user named {0}")]
+    NoSuchUser(String),
+
+    #[error("Failed to hash stream key")]
+    HashingFailed,
+
+    #[error("Users can not be changed in this repository")]
+    ReadOnly,
 }
 
 
+/// Owner of an application, its keys are valid for every stream published below it.
 #[derive(Debug)]
 pub struct User {
     pub name: String,
-    pub key: String,
+    /// Salted hash of the key viewers have to present, streams without one are public.
+    pub play_key: Option<String>,
+}
+
+
+/// Guardrails for the streams of a user, each one is unlimited while unset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limits {
+    /// Streams published at the same time.
+    #[serde(default)]
+    pub max_sessions: Option<u32>,
+
+    /// Ingest bitrate per stream in bits per second, averaged over a few seconds.
+    #[serde(default)]
+    pub max_bitrate: Option<u64>,
+
+    /// Viewers per stream, internal ones like the HLS writer are not counted. HLS clients
+    /// count once per address while they keep reloading the playlist.
+    #[serde(default)]
+    pub max_viewers: Option<u32>,
+
+    /// Streams are ended once they ran for this long, given in seconds.
+    #[serde(default, with = "secs")]
+    pub max_duration: Option<Duration>,
+}
+
+
+/// Named stream key of a user, only a salted hash of the key itself is kept.
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamKey {
+    pub name: String,
+    #[serde(skip)]
+    pub hash: String,
+    /// First characters of a generated key, narrows down which hash to verify. Keys chosen by
+    /// users would give away too much of themselves, they have none and are always verified.
+    #[serde(skip)]
+    pub prefix: Option<String>,
+    /// Only valid for publishing this stream, for every stream of the user while unset.
+    pub stream_name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
 }
 
+impl StreamKey {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+
+    /// Whether the key may be used to publish the stream.
+    pub fn permits(&self, stream_name: &str) -> bool {
+        self.stream_name
+            .as_deref()
+            .is_none_or(|scope| scope == stream_name)
+    }
+
+    /// Whether the key could be this one, without the cost of verifying it.
+    pub fn may_match(&self, key: &str) -> bool {
+        self.prefix
+            .as_deref()
+            .is_none_or(|prefix| key.starts_with(prefix))
+    }
+
+    /// Compares in constant time, malformed hashes never match.
+    pub fn verify(&self, key: &str) -> bool {
+        verify_key(&self.hash, key)
+    }
+}
+
+
+/// A finished publishing session, kept for accounting and troubleshooting.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionRecord {
+    /// Application the stream was published to, named after the user owning it.
+    pub app_name: String,
+    pub stream_name: String,
+    pub protocol: String,
+    /// Address of the last publisher, if it came from outside the server.
+    pub client_ip: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub bytes_in: u64,
+    /// Bytes delivered to viewers, including playlists and segments served to HLS clients
+    /// while the stream was live.
+    pub bytes_out: u64,
+    pub peak_viewers: u64,
+    pub end_reason: String,
+}
+
+
+/// Hashes a stream or play key with a random salt, for storage in [`StreamKey::hash`] or
+/// [`User::play_key`].
+pub fn hash_key(key: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    let hash = Argon2::default()
+        .hash_password(key.as_bytes(), &salt)
+        .map_err(|_| Error::HashingFailed)?;
+
+    Ok(hash.to_string())
+}
+
+
+/// Checks a key against a hash made by [`hash_key`], malformed hashes never match.
+pub fn verify_key(hash: &str, key: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(hash) else {
+        return false;
+    };
+
+    Argon2::default()
+        .verify_password(key.as_bytes(), &hash)
+        .is_ok()
+}
+
+
+/// Prefix of a stream key, for storage in [`StreamKey::prefix`].
+pub fn key_prefix(key: &str) -> String {
+    key.chars().take(KEY_PREFIX_LEN).collect()
+}
+
+
 #[async_trait]
 pub trait UserRepository {
     async fn user_by_name(&self, name: &str) -> Result<Option<User>, Error>;
 
-    async fn add_user_with_key(&mut self, name: &str, key: &str) -> Result<(), Error>;
+    async fn list_users(&self) -> Result<Vec<User>, Error>;
+
+    /// Returns whether the user was created, existing users are left as they are.
+    async fn add_user(&mut self, name: &str) -> Result<bool, Error>;
+
+    /// Only a hash of the play key is kept.
+    async fn set_play_key(&mut self, name: &str, play_key: Option<&str>) -> Result<(), Error>;
+
+    /// Returns whether the user existed, its keys are removed as well.
+    async fn remove_user(&mut self, name: &str) -> Result<bool, Error>;
+
+    /// All keys of the user, including expired and revoked ones.
+    async fn keys_of(&self, name: &str) -> Result<Vec<StreamKey>, Error>;
+
+    /// Adds a key to the user, replacing any key with the same name.
+    ///
+    /// Only keys that were `generated` at random keep a [prefix](StreamKey::prefix).
+    async fn add_key(
+        &mut self,
+        name: &str,
+        key_name: &str,
+        key: &str,
+        generated: bool,
+        stream_name: Option<&str>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), Error>;
+
+    /// Returns whether an active key with that name was revoked.
+    async fn revoke_key(&mut self, name: &str, key_name: &str) -> Result<bool, Error>;
+
+    /// Records that the key was just used to publish.
+    async fn touch_key(&self, name: &str, key_name: &str) -> Result<(), Error>;
+
+    /// Limits of the user, unknown users have none.
+    async fn limits_of(&self, name: &str) -> Result<Limits, Error>;
+
+    async fn set_limits(&mut self, name: &str, limits: Limits) -> Result<(), Error>;
+
+    async fn record_session(&mut self, record: &SessionRecord) -> Result<(), Error>;
+
+    /// Most recent sessions first, of a single application if one is given.
+    async fn session_history(
+        &self,
+        app_name: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SessionRecord>, Error>;
 
-    async fn user_has_key(&self, name: &str, key: &str) -> Result<bool, Error> {
-        if let Some(user) = self.user_by_name(name).await? {
-            return Ok(user.key == key);
+    /// Creates the user if needed and sets its default key.
+    async fn add_user_with_key(&mut self, name: &str, key: &str) -> Result<(), Error> {
+        self.add_user(name).await?;
+        self.add_key(name, DEFAULT_KEY_NAME, key, false, None, None)
+            .await
+    }
+
+    /// Whether the user may publish the stream with the key.
+    ///
+    /// Usually only the key with a matching prefix is verified, on a blocking thread as
+    /// hashing takes a while.
+    async fn user_has_key(&self, name: &str, stream_name: &str, key: &str) -> Result<bool, Error> {
+        let now = Utc::now();
+
+        let candidates: Vec<_> = self
+            .keys_of(name)
+            .await?
+            .into_iter()
+            .filter(|stream_key| {
+                stream_key.is_active(now)
+                    && stream_key.permits(stream_name)
+                    && stream_key.may_match(key)
+            })
+            .collect();
+
+        if candidates.is_empty() {
+            return Ok(false);
         }
 
-        Ok(false)
+        let key = key.to_string();
+        let matched = tokio::task::spawn_blocking(move || {
+            candidates
+                .into_iter()
+                .find(|stream_key| stream_key.verify(&key))
+        })
+        .await
+        .map_err(|_| Error::LookupFailed)?;
+
+        match matched {
+            Some(stream_key) => {
+                self.touch_key(name, &stream_key.name).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Play keys are verified like stream keys, on a blocking thread.
+    async fn viewer_has_access(&self, name: &str, play_key: Option<&str>) -> Result<bool, Error> {
+        let Some(user) = self.user_by_name(name).await? else {
+            return Ok(false);
+        };
+
+        let (hash, play_key) = match (user.play_key, play_key) {
+            (None, _) => return Ok(true),
+            (Some(hash), Some(play_key)) => (hash, play_key.to_string()),
+            (Some(_), None) => return Ok(false),
+        };
+
+        tokio::task::spawn_blocking(move || verify_key(&hash, &play_key))
+            .await
+            .map_err(|_| Error::LookupFailed)
+    }
+}
+
+
+mod secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        duration
+            .map(|duration| duration.as_secs())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let secs = Option::<u64>::deserialize(deserializer)?;
+        Ok(secs.map(Duration::from_secs))
     }
 }
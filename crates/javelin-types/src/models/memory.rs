@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+
+use super::{hash_key, key_prefix, Error, Limits, SessionRecord, StreamKey, User, UserRepository};
+use crate::async_trait;
+
+
+/// Number of finished sessions kept, older ones are forgotten.
+const HISTORY_CAPACITY: usize = 1000;
+
+
+/// Keeps users in memory only, for tests and embedded deployments.
+///
+/// Clones share the same users.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRepository {
+    users: Arc<Mutex<BTreeMap<String, Entry>>>,
+    history: Arc<Mutex<VecDeque<SessionRecord>>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    play_key: Option<String>,
+    keys: BTreeMap<String, StreamKey>,
+    limits: Limits,
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns whether the user was created, existing users are left as they are.
+    ///
+    /// The play key has to be hashed beforehand, like keys given to [`Self::insert_key`].
+    pub fn insert_user(&self, name: &str, play_key: Option<String>) -> bool {
+        let mut users = self.users.lock().unwrap();
+        if users.contains_key(name) {
+            return false;
+        }
+
+        let entry = Entry {
+            play_key,
+            keys: BTreeMap::new(),
+            limits: Limits::default(),
+        };
+        users.insert(name.to_string(), entry);
+        true
+    }
+
+    /// Adds a key that was hashed beforehand, replacing any key with the same name.
+    pub fn insert_key(&self, name: &str, key: StreamKey) -> Result<(), Error> {
+        let mut users = self.users.lock().unwrap();
+        let entry = users
+            .get_mut(name)
+            .ok_or_else(|| Error::NoSuchUser(name.to_string()))?;
+        entry.keys.insert(key.name.clone(), key);
+        Ok(())
+    }
+
+    /// Sets the limits without going through the async trait, e.g. while loading users.
+    pub fn insert_limits(&self, name: &str, limits: Limits) -> Result<(), Error> {
+        let mut users = self.users.lock().unwrap();
+        let entry = users
+            .get_mut(name)
+            .ok_or_else(|| Error::NoSuchUser(name.to_string()))?;
+        entry.limits = limits;
+        Ok(())
+    }
+
+    /// Takes over all users of `other`, dropping the current ones. The history is kept.
+    pub fn replace_with(&self, other: MemoryRepository) {
+        let users = std::mem::take(&mut *other.users.lock().unwrap());
+        *self.users.lock().unwrap() = users;
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+    async fn user_by_name(&self, name: &str) -> Result<Option<User>, Error> {
+        let users = self.users.lock().unwrap();
+        Ok(users.get(name).map(|entry| User {
+            name: name.to_string(),
+            play_key: entry.play_key.clone(),
+        }))
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, Error> {
+        let users = self.users.lock().unwrap();
+        Ok(users
+            .iter()
+            .map(|(name, entry)| User {
+                name: name.clone(),
+                play_key: entry.play_key.clone(),
+            })
+            .collect())
+    }
+
+    async fn add_user(&mut self, name: &str) -> Result<bool, Error> {
+        Ok(self.insert_user(name, None))
+    }
+
+    async fn set_play_key(&mut self, name: &str, play_key: Option<&str>) -> Result<(), Error> {
+        let play_key = play_key.map(hash_key).transpose()?;
+
+        let mut users = self.users.lock().unwrap();
+        let entry = users
+            .get_mut(name)
+            .ok_or_else(|| Error::NoSuchUser(name.to_string()))?;
+        entry.play_key = play_key;
+        Ok(())
+    }
+
+    async fn remove_user(&mut self, name: &str) -> Result<bool, Error> {
+        Ok(self.users.lock().unwrap().remove(name).is_some())
+    }
+
+    async fn keys_of(&self, name: &str) -> Result<Vec<StreamKey>, Error> {
+        let users = self.users.lock().unwrap();
+        Ok(users
+            .get(name)
+            .map(|entry| entry.keys.values().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn add_key(
+        &mut self,
+        name: &str,
+        key_name: &str,
+        key: &str,
+        generated: bool,
+        stream_name: Option<&str>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), Error> {
+        let key = StreamKey {
+            name: key_name.to_string(),
+            hash: hash_key(key)?,
+            prefix: generated.then(|| key_prefix(key)),
+            stream_name: stream_name.map(str::to_string),
+            created_at: Utc::now(),
+            expires_at,
+            revoked_at: None,
+            last_used_at: None,
+        };
+
+        self.insert_key(name, key)
+    }
+
+    async fn revoke_key(&mut self, name: &str, key_name: &str) -> Result<bool, Error> {
+        let mut users = self.users.lock().unwrap();
+        let key = users
+            .get_mut(name)
+            .and_then(|entry| entry.keys.get_mut(key_name))
+            .filter(|key| key.revoked_at.is_none());
+
+        let Some(key) = key else {
+            return Ok(false);
+        };
+
+        key.revoked_at = Some(Utc::now());
+        Ok(true)
+    }
+
+    async fn touch_key(&self, name: &str, key_name: &str) -> Result<(), Error> {
+        let mut users = self.users.lock().unwrap();
+        if let Some(key) = users
+            .get_mut(name)
+            .and_then(|entry| entry.keys.get_mut(key_name))
+        {
+            key.last_used_at = Some(Utc::now());
+        }
+
+        Ok(())
+    }
+
+    async fn limits_of(&self, name: &str) -> Result<Limits, Error> {
+        let users = self.users.lock().unwrap();
+        Ok(users
+            .get(name)
+            .map(|entry| entry.limits)
+            .unwrap_or_default())
+    }
+
+    async fn set_limits(&mut self, name: &str, limits: Limits) -> Result<(), Error> {
+        self.insert_limits(name, limits)
+    }
+
+    async fn record_session(&mut self, record: &SessionRecord) -> Result<(), Error> {
+        let mut history = self.history.lock().unwrap();
+        if history.len() == HISTORY_CAPACITY {
+            history.pop_back();
+        }
+        history.push_front(record.clone());
+        Ok(())
+    }
+
+    async fn session_history(
+        &self,
+        app_name: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SessionRecord>, Error> {
+        let history = self.history.lock().unwrap();
+        Ok(history
+            .iter()
+            .filter(|record| app_name.is_none_or(|app_name| record.app_name == app_name))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+}
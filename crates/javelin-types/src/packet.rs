@@ -4,19 +4,99 @@ use serde::{Deserialize, Serialize};
 use crate::Timestamp;
 
 
-// FIXME: remove temporary content id values
-pub const METADATA: ContentType = ContentType::new(0);
-pub const FLV_VIDEO_H264: ContentType = ContentType::new(1);
-pub const FLV_AUDIO_AAC: ContentType = ContentType::new(2);
-pub const CONTAINER_MPEGTS: ContentType = ContentType::new(3);
+pub const METADATA: ContentType = ContentType::new(Container::Raw, Codec::Metadata);
+pub const FLV_VIDEO_H264: ContentType = ContentType::new(Container::Flv, Codec::H264);
+pub const FLV_AUDIO_AAC: ContentType = ContentType::new(Container::Flv, Codec::Aac);
+pub const CONTAINER_MPEGTS: ContentType = ContentType::new(Container::MpegTs, Codec::Unknown);
 
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct ContentType(u32);
+pub type TrackId = u32;
+
+
+/// How the payload of a packet is framed.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Container {
+    /// A single FLV tag body, as sent over RTMP.
+    Flv,
+
+    /// One or more MPEG transport stream packets.
+    MpegTs,
+
+    /// Plain elementary stream data.
+    Raw,
+
+    /// Written by a newer version, kept around so packets can still be passed on.
+    #[serde(other)]
+    Unknown,
+}
+
+
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    H264,
+    Hevc,
+    Av1,
+    Aac,
+    Mp3,
+    Opus,
+
+    /// Serialized stream [`Metadata`](crate::Metadata).
+    Metadata,
+
+    /// Not known, or not a single codec like in multiplexed transport streams.
+    #[serde(other)]
+    Unknown,
+}
+
+impl Codec {
+    pub fn kind(&self) -> MediaKind {
+        match self {
+            Self::H264 | Self::Hevc | Self::Av1 => MediaKind::Video,
+            Self::Aac | Self::Mp3 | Self::Opus => MediaKind::Audio,
+            Self::Metadata => MediaKind::Data,
+            Self::Unknown => MediaKind::Unknown,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MediaKind {
+    Video,
+    Audio,
+    Data,
+    Unknown,
+}
+
+
+/// Describes what a packet carries, e.g. H.264 video in FLV tags on the first track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ContentType {
+    pub container: Container,
+    pub codec: Codec,
+    #[serde(default)]
+    pub track: TrackId,
+}
 
 impl ContentType {
-    const fn new(content_id: u32) -> Self {
-        Self(content_id)
+    pub const fn new(container: Container, codec: Codec) -> Self {
+        Self {
+            container,
+            codec,
+            track: 0,
+        }
+    }
+
+    pub const fn with_track(self, track: TrackId) -> Self {
+        Self { track, ..self }
+    }
+
+    pub fn kind(&self) -> MediaKind {
+        self.codec.kind()
     }
 }
 
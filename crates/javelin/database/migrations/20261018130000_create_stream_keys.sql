@@ -0,0 +1,29 @@
+-- Keys move into their own table, existing ones are marked for hashing when the server opens
+-- the database next.
+ALTER TABLE users RENAME TO users_old;
+
+CREATE TABLE users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    play_key TEXT
+);
+
+INSERT INTO users (id, name, play_key)
+SELECT id, name, play_key FROM users_old;
+
+CREATE TABLE stream_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT,
+    revoked_at TEXT,
+    last_used_at TEXT,
+    UNIQUE (user_id, name)
+);
+
+INSERT INTO stream_keys (user_id, name, hash, created_at)
+SELECT id, 'default', 'plain$' || key, strftime('%Y-%m-%dT%H:%M:%SZ', 'now') FROM users_old;
+
+DROP TABLE users_old;
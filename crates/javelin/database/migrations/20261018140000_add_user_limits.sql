@@ -0,0 +1,5 @@
+ALTER TABLE users ADD COLUMN max_sessions INTEGER;
+ALTER TABLE users ADD COLUMN max_bitrate INTEGER;
+ALTER TABLE users ADD COLUMN max_viewers INTEGER;
+-- in seconds
+ALTER TABLE users ADD COLUMN max_duration INTEGER;
@@ -0,0 +1,16 @@
+-- Not tied to users, the history outlives them for accounting.
+CREATE TABLE session_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    app_name TEXT NOT NULL,
+    stream_name TEXT NOT NULL,
+    protocol TEXT NOT NULL,
+    client_ip TEXT,
+    started_at TEXT NOT NULL,
+    ended_at TEXT NOT NULL,
+    bytes_in INTEGER NOT NULL,
+    bytes_out INTEGER NOT NULL,
+    peak_viewers INTEGER NOT NULL,
+    end_reason TEXT NOT NULL
+);
+
+CREATE INDEX session_history_app_name ON session_history (app_name, started_at);
@@ -0,0 +1,2 @@
+-- keys without a stream name are valid for every stream of the user
+ALTER TABLE stream_keys ADD COLUMN stream_name TEXT;
@@ -0,0 +1,2 @@
+-- first characters of each key, so only the matching hash has to be verified
+ALTER TABLE stream_keys ADD COLUMN key_prefix TEXT;
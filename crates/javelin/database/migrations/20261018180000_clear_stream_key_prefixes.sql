@@ -0,0 +1,2 @@
+-- prefixes were kept for keys chosen by users as well, which can't be told apart from generated ones
+UPDATE stream_keys SET key_prefix = NULL;
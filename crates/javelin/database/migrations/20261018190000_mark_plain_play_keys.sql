@@ -0,0 +1,3 @@
+-- Play keys are hashed like stream keys, existing ones are marked for hashing when the database
+-- is migrated or opened next.
+UPDATE users SET play_key = 'plain$' || play_key WHERE play_key IS NOT NULL;
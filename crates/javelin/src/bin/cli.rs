@@ -2,10 +2,20 @@
 
 use std::path::PathBuf;
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-use javelin::database::{Database, UserRepository};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use javelin::database::{hash_key, Database, Limits, StreamKey, User, UserRepository};
+use javelin::repository::{Backend, RepositoryConfig};
 use javelin_core::Config;
+use javelin_types::models::DEFAULT_KEY_NAME;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Serialize;
+
+
+/// Length of generated stream keys.
+const GENERATED_KEY_LENGTH: usize = 32;
 
 
 #[derive(Parser)]
@@ -14,10 +24,19 @@ pub struct CliArgs {
     #[arg(short, long, default_value = "./config")]
     pub config_dir: PathBuf,
 
+    #[arg(long, global = true, value_enum, default_value_t = Format::Table)]
+    pub format: Format,
+
     #[command(subcommand)]
     pub cmd: Command,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+}
+
 #[derive(Subcommand)]
 pub enum Command {
     PermitStream {
@@ -26,6 +45,120 @@ pub enum Command {
         #[arg(long, required = true)]
         key: String,
     },
+    /// Requires viewers to present a key, omitting it makes the stream public again
+    SetPlayKey {
+        #[arg(long, required = true)]
+        user: String,
+        #[arg(long)]
+        key: Option<String>,
+    },
+    ListUsers,
+    ShowUser {
+        #[arg(long, required = true)]
+        user: String,
+    },
+    /// Removes the user along with all of its keys
+    RemoveUser {
+        #[arg(long, required = true)]
+        user: String,
+    },
+    /// Adds a named key, a random one is generated and printed if none is given
+    AddKey {
+        #[arg(long, required = true)]
+        user: String,
+        #[arg(long, default_value = DEFAULT_KEY_NAME)]
+        name: String,
+        #[arg(long)]
+        key: Option<String>,
+        /// Only valid for publishing this stream, instead of every stream of the user
+        #[arg(long)]
+        stream: Option<String>,
+        /// RFC 3339 timestamp, or a duration from now like `30d`, `12h` or `15m`
+        #[arg(long, value_parser = parse_expiry)]
+        expires: Option<DateTime<Utc>>,
+    },
+    /// Replaces an active key with a random one, which is printed only this once
+    RotateKey {
+        #[arg(long, required = true)]
+        user: String,
+        #[arg(long, default_value = DEFAULT_KEY_NAME)]
+        name: String,
+    },
+    RevokeKey {
+        #[arg(long, required = true)]
+        user: String,
+        #[arg(long, default_value = DEFAULT_KEY_NAME)]
+        name: String,
+    },
+    /// Replaces the limits of a user, the ones left out are lifted
+    SetLimits {
+        #[arg(long, required = true)]
+        user: String,
+        /// Streams published at the same time
+        #[arg(long)]
+        max_sessions: Option<u32>,
+        /// Ingest bitrate per stream in bits per second
+        #[arg(long)]
+        max_bitrate: Option<u64>,
+        /// Viewers per stream
+        #[arg(long)]
+        max_viewers: Option<u32>,
+        /// Duration after which streams are ended, like `90m` or `4h`
+        #[arg(long, value_parser = parse_duration)]
+        max_duration: Option<std::time::Duration>,
+    },
+    /// Lists streams that already ended, most recent first
+    History {
+        #[arg(long)]
+        user: Option<String>,
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+    },
+    /// Prints the hash of a key, for use in a static credentials file
+    HashKey {
+        #[arg(long, required = true)]
+        key: String,
+    },
+    /// Manages the database schema
+    Db {
+        #[command(subcommand)]
+        cmd: DbCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DbCommand {
+    /// Applies all pending migrations
+    Migrate,
+    /// Lists all migrations and whether they were applied
+    Status,
+}
+
+
+#[derive(Serialize)]
+struct UserSummary {
+    name: String,
+    private: bool,
+    active_keys: usize,
+}
+
+
+#[derive(Serialize)]
+struct UserDetails {
+    name: String,
+    private: bool,
+    limits: Limits,
+    keys: Vec<StreamKey>,
+}
+
+
+#[derive(Serialize)]
+struct NewKey {
+    user: String,
+    name: String,
+    key: String,
+    stream_name: Option<String>,
+    expires_at: Option<DateTime<Utc>>,
 }
 
 
@@ -33,11 +166,220 @@ pub enum Command {
 async fn main() -> Result<()> {
     let args = CliArgs::parse();
 
+    if let Command::HashKey { key } = &args.cmd {
+        println!("{}", hash_key(key)?);
+        return Ok(());
+    }
+
     let config = Config::try_from_path(&args.config_dir)?;
+    let repo_config = RepositoryConfig::from_config(&config)?;
+    let format = args.format;
+
+    if let Command::Db { cmd } = &args.cmd {
+        let database_handle = Database::connect(repo_config.sqlite()?).await?;
+        return run_db_command(&database_handle, cmd, format).await;
+    }
+
+    let mut database_handle = open_database(&repo_config).await?;
 
     match args.cmd {
         Command::PermitStream { user, key } => {
-            permit_stream(&user, &key, &config).await?;
+            database_handle.add_user_with_key(&user, &key).await?;
+        }
+        Command::SetPlayKey { user, key } => {
+            database_handle.set_play_key(&user, key.as_deref()).await?;
+        }
+        Command::ListUsers => {
+            list_users(&database_handle, format).await?;
+        }
+        Command::ShowUser { user } => {
+            show_user(&database_handle, &user, format).await?;
+        }
+        Command::RemoveUser { user } => {
+            if !database_handle.remove_user(&user).await? {
+                bail!("No user named {}", user);
+            }
+        }
+        Command::AddKey {
+            user,
+            name,
+            key,
+            stream,
+            expires,
+        } => {
+            let new_key = add_key(&mut database_handle, user, name, key, stream, expires).await?;
+            if let Some(new_key) = new_key {
+                print_new_key(&new_key, format)?;
+            }
+        }
+        Command::RotateKey { user, name } => {
+            let new_key = rotate_key(&mut database_handle, user, name).await?;
+            print_new_key(&new_key, format)?;
+        }
+        Command::RevokeKey { user, name } => {
+            if !database_handle.revoke_key(&user, &name).await? {
+                bail!("User {} has no active key named {}", user, name);
+            }
+        }
+        Command::SetLimits {
+            user,
+            max_sessions,
+            max_bitrate,
+            max_viewers,
+            max_duration,
+        } => {
+            let limits = Limits {
+                max_sessions,
+                max_bitrate,
+                max_viewers,
+                max_duration,
+            };
+            database_handle.set_limits(&user, limits).await?;
+        }
+        Command::History { user, limit } => {
+            show_history(&database_handle, user.as_deref(), limit, format).await?;
+        }
+        Command::HashKey { .. } | Command::Db { .. } => {
+            unreachable!("handled before opening the database")
+        }
+    }
+
+    Ok(())
+}
+
+
+async fn open_database(repo_config: &RepositoryConfig) -> Result<Database> {
+    if repo_config.backend != Backend::Sqlite {
+        bail!(
+            "Users can only be managed here with the SQLite backend, database.backend is {:?}",
+            repo_config.backend
+        );
+    }
+
+    Database::new(repo_config.sqlite()?).await
+}
+
+
+async fn run_db_command(database_handle: &Database, cmd: &DbCommand, format: Format) -> Result<()> {
+    let migrations = match cmd {
+        DbCommand::Migrate => database_handle.migrate().await?,
+        DbCommand::Status => database_handle.migration_status().await?,
+    };
+
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&migrations)?),
+        Format::Table if migrations.is_empty() => println!("Database is up to date"),
+        Format::Table => {
+            let rows = migrations
+                .into_iter()
+                .map(|migration| {
+                    let status = if migration.applied {
+                        "applied"
+                    } else {
+                        "pending"
+                    };
+                    vec![
+                        migration.version.to_string(),
+                        migration.description,
+                        status.to_string(),
+                    ]
+                })
+                .collect();
+            print_table(&["VERSION", "DESCRIPTION", "STATUS"], rows);
+        }
+    }
+
+    Ok(())
+}
+
+
+async fn list_users(database_handle: &Database, format: Format) -> Result<()> {
+    let now = Utc::now();
+    let mut users = Vec::new();
+
+    for User { name, play_key } in database_handle.list_users().await? {
+        let keys = database_handle.keys_of(&name).await?;
+        users.push(UserSummary {
+            active_keys: keys.iter().filter(|key| key.is_active(now)).count(),
+            private: play_key.is_some(),
+            name,
+        });
+    }
+
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&users)?),
+        Format::Table => {
+            let rows = users
+                .into_iter()
+                .map(|user| {
+                    vec![
+                        user.name,
+                        yes_no(user.private),
+                        user.active_keys.to_string(),
+                    ]
+                })
+                .collect();
+            print_table(&["NAME", "PRIVATE", "ACTIVE KEYS"], rows);
+        }
+    }
+
+    Ok(())
+}
+
+
+async fn show_user(database_handle: &Database, name: &str, format: Format) -> Result<()> {
+    let Some(user) = database_handle.user_by_name(name).await? else {
+        bail!("No user named {}", name);
+    };
+
+    let details = UserDetails {
+        keys: database_handle.keys_of(name).await?,
+        limits: database_handle.limits_of(name).await?,
+        private: user.play_key.is_some(),
+        name: user.name,
+    };
+
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&details)?),
+        Format::Table => {
+            let limits = details.limits;
+            println!("User:         {}", details.name);
+            println!("Private:      {}", yes_no(details.private));
+            println!("Max sessions: {}", format_limit(limits.max_sessions));
+            println!("Max bitrate:  {}", format_limit(limits.max_bitrate));
+            println!("Max viewers:  {}", format_limit(limits.max_viewers));
+            println!(
+                "Max duration: {}",
+                format_limit(limits.max_duration.map(|max| format!("{}s", max.as_secs())))
+            );
+            println!();
+
+            let now = Utc::now();
+            let rows = details
+                .keys
+                .into_iter()
+                .map(|key| {
+                    let status = if key.revoked_at.is_some() {
+                        "revoked"
+                    } else if !key.is_active(now) {
+                        "expired"
+                    } else {
+                        "active"
+                    };
+                    vec![
+                        key.name,
+                        key.stream_name.unwrap_or_else(|| "*".to_string()),
+                        status.to_string(),
+                        format_time(Some(key.created_at)),
+                        format_time(key.expires_at),
+                        format_time(key.last_used_at),
+                    ]
+                })
+                .collect();
+            print_table(
+                &["KEY", "STREAM", "STATUS", "CREATED", "EXPIRES", "LAST USED"],
+                rows,
+            );
         }
     }
 
@@ -45,10 +387,253 @@ async fn main() -> Result<()> {
 }
 
 
-async fn permit_stream(user: &str, key: &str, config: &Config) -> Result<()> {
-    let mut database_handle = Database::new(config).await;
+/// Returns the key if it had to be generated.
+async fn add_key(
+    database_handle: &mut Database,
+    user: String,
+    name: String,
+    key: Option<String>,
+    stream_name: Option<String>,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<Option<NewKey>> {
+    if expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        bail!("Expiry has to be in the future");
+    }
+
+    let (key, generated) = match key {
+        Some(key) if key.is_empty() => bail!("Stream key can not be empty"),
+        Some(key) => (key, false),
+        None => (generate_key(), true),
+    };
+
+    database_handle
+        .add_key(
+            &user,
+            &name,
+            &key,
+            generated,
+            stream_name.as_deref(),
+            expires_at,
+        )
+        .await?;
+
+    Ok(generated.then_some(NewKey {
+        user,
+        name,
+        key,
+        stream_name,
+        expires_at,
+    }))
+}
+
+
+/// Keeps the stream and expiry of the previous key, revoked and expired keys stay as they are.
+async fn rotate_key(database_handle: &mut Database, user: String, name: String) -> Result<NewKey> {
+    let keys = database_handle.keys_of(&user).await?;
+    let Some(previous) = keys.into_iter().find(|key| key.name == name) else {
+        bail!("User {} has no key named {}", user, name);
+    };
+
+    if !previous.is_active(Utc::now()) {
+        bail!(
+            "Key {} of user {} is revoked or expired, add a new one instead",
+            name,
+            user
+        );
+    }
+
+    let key = generate_key();
+    database_handle
+        .add_key(
+            &user,
+            &name,
+            &key,
+            true,
+            previous.stream_name.as_deref(),
+            previous.expires_at,
+        )
+        .await?;
+
+    Ok(NewKey {
+        user,
+        name,
+        key,
+        stream_name: previous.stream_name,
+        expires_at: previous.expires_at,
+    })
+}
+
+
+fn print_new_key(new_key: &NewKey, format: Format) -> Result<()> {
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(new_key)?),
+        Format::Table => {
+            println!("{}", new_key.key);
+            eprintln!("Store this key now, it can not be shown again.");
+        }
+    }
+
+    Ok(())
+}
+
+
+fn generate_key() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(GENERATED_KEY_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+
+fn parse_expiry(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+
+    let invalid = || format!("invalid expiry {value:?}, expected RFC 3339 or a duration");
+    let duration = parse_duration(value).map_err(|_| invalid())?;
+    let duration = Duration::from_std(duration).map_err(|_| invalid())?;
+
+    Ok(Utc::now() + duration)
+}
+
+
+fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    let invalid = || format!("invalid duration {value:?}, expected e.g. `15m`, `12h` or `30d`");
+    let (split, _) = value.char_indices().last().ok_or_else(invalid)?;
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+
+    let unit_secs = match unit {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+
+    amount
+        .checked_mul(unit_secs)
+        .map(std::time::Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+
+async fn show_history(
+    database_handle: &Database,
+    user: Option<&str>,
+    limit: usize,
+    format: Format,
+) -> Result<()> {
+    let history = database_handle.session_history(user, limit).await?;
 
-    database_handle.add_user_with_key(user, key).await?;
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&history)?),
+        Format::Table => {
+            let rows = history
+                .into_iter()
+                .map(|record| {
+                    let duration = record.ended_at - record.started_at;
+                    vec![
+                        format_time(Some(record.started_at)),
+                        format_duration(duration),
+                        format!("{}/{}", record.app_name, record.stream_name),
+                        record.protocol,
+                        record.client_ip.unwrap_or_else(|| "-".to_string()),
+                        record.bytes_in.to_string(),
+                        record.bytes_out.to_string(),
+                        record.peak_viewers.to_string(),
+                        record.end_reason,
+                    ]
+                })
+                .collect();
+            print_table(
+                &[
+                    "STARTED",
+                    "DURATION",
+                    "STREAM",
+                    "PROTOCOL",
+                    "CLIENT",
+                    "BYTES IN",
+                    "BYTES OUT",
+                    "PEAK VIEWERS",
+                    "END REASON",
+                ],
+                rows,
+            );
+        }
+    }
 
     Ok(())
 }
+
+
+fn print_table(header: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<_> = header.iter().map(|column| column.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header = header.iter().map(|column| column.to_string()).collect();
+    for row in std::iter::once(header).chain(rows) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+
+fn format_time(timestamp: Option<DateTime<Utc>>) -> String {
+    timestamp
+        .map(|timestamp| timestamp.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.num_seconds().max(0);
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+
+fn format_limit<T: ToString>(limit: Option<T>) -> String {
+    limit
+        .map(|limit| limit.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::parse_duration;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(
+            parse_duration("2w"),
+            Ok(Duration::from_secs(14 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for value in ["", "m", "15", "15s", "15µ", "ü", "-1d"] {
+            assert!(parse_duration(value).is_err(), "{value:?}");
+        }
+    }
+}
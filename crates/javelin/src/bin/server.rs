@@ -1,12 +1,20 @@
 #![warn(clippy::all)]
 
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::Result;
 use clap::Parser;
-use javelin::database::Database;
-use javelin_core::Config;
-use javelin_core::session;
+use javelin::repository::Repository;
+use javelin_core::session::{self, ManagerMessage};
+use javelin_core::{metrics, shutdown, Config};
+use serde::{Deserialize, Deserializer};
+use tracing::{error, info, warn, Level};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::{reload, Registry};
+
+
+type LogHandle = reload::Handle<Targets, Registry>;
 
 
 #[derive(Parser)]
@@ -17,70 +25,220 @@ pub struct ServerArgs {
 }
 
 
+#[derive(Debug, Clone, Deserialize)]
+struct ServerConfig {
+    #[serde(default = "default_shutdown_timeout")]
+    shutdown_timeout: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            shutdown_timeout: default_shutdown_timeout(),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct LogConfig {
+    /// Verbosity of the server's own log messages, dependencies only ever log errors.
+    #[serde(default, deserialize_with = "deserialize_level")]
+    level: Option<Level>,
+}
+
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    if let Err(why) = init_tracing() {
-        eprintln!("Failed to initialize logger: {}", why);
+    let log_handle = match init_tracing() {
+        Ok(log_handle) => Some(log_handle),
+        Err(why) => {
+            eprintln!("Failed to initialize logger: {}", why);
+            None
+        }
     };
 
     let args = ServerArgs::parse();
 
     let config = Config::try_from_path(&args.config_dir)?;
+    config.register_section::<ServerConfig>("server", &[]);
+    config.register_section::<LogConfig>("log", &[]);
+    apply_log_config(&config, log_handle.as_ref());
+    metrics::install(&config)?;
 
-    let mut handles = Vec::new();
+    let mut shutdown = shutdown::Coordinator::new();
 
-    let database_handle = Database::new(&config).await;
+    let user_repo = Repository::new(&config).await?;
 
-    let session = session::Manager::new(database_handle.clone());
+    let session = session::Manager::new(user_repo.clone(), &config)?;
     let session_handle = session.handle();
-    handles.push(tokio::spawn(session.run()));
+    tokio::spawn(session.run());
 
-    tokio::spawn(javelin_srt::Service::new(session_handle.clone(), &config).run());
+    tokio::spawn({
+        javelin_srt::Service::new(session_handle.clone(), &config).run(shutdown.subscribe())
+    });
 
     #[cfg(feature = "hls")]
-    handles.push(tokio::spawn({
-        javelin_hls::Service::new(session_handle.clone(), &config).run()
-    }));
+    tokio::spawn({
+        javelin_hls::Service::new(session_handle.clone(), user_repo.clone(), &config)
+            .run(shutdown.subscribe())
+    });
+
+    #[cfg(feature = "recorder")]
+    tokio::spawn({
+        javelin_recorder::Service::new(session_handle.clone(), &config).run(shutdown.subscribe())
+    });
 
     #[cfg(feature = "rtmp")]
-    handles.push(tokio::spawn({
-        javelin_rtmp::Service::new(session_handle, &config).run()
-    }));
+    tokio::spawn({
+        javelin_rtmp::Service::new(session_handle.clone(), &config).run(shutdown.subscribe())
+    });
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(config.clone(), log_handle));
+
+    wait_for_signal().await?;
+
+    let server_config: ServerConfig = config.get("server").unwrap_or_default();
+    let shutdown_timeout = server_config.shutdown_timeout;
+    info!(
+        "Shutting down, waiting up to {:?} for connections to close",
+        shutdown_timeout
+    );
+
+    shutdown.trigger();
+    if session_handle.send(ManagerMessage::Shutdown).is_err() {
+        error!("Failed to close sessions");
+    }
 
-    // Wait for all spawned processes to complete
-    for handle in handles {
-        handle.await?;
+    tokio::select! {
+        _ = shutdown.drained() => info!("All connections closed"),
+        _ = tokio::time::sleep(shutdown_timeout) => {
+            warn!("Shutdown timed out, dropping remaining connections");
+        }
+        result = wait_for_signal() => {
+            result?;
+            warn!("Received second signal, exiting immediately");
+        }
     }
 
     Ok(())
 }
 
 
-fn init_tracing() -> Result<()> {
-    use tracing::Level;
-    use tracing_subscriber::filter::Targets;
+/// Resolves on Ctrl-C, or on SIGTERM where available.
+async fn wait_for_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => (),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
+
+
+/// Reloads the configuration whenever the process receives SIGHUP.
+#[cfg(unix)]
+async fn reload_on_hangup(config: Config, log_handle: Option<LogHandle>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(why) => {
+            error!(
+                "Failed to listen for SIGHUP, reloading is disabled: {}",
+                why
+            );
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("Reloading configuration");
+
+        match config.reload() {
+            Ok(restart_required) => {
+                for setting in restart_required {
+                    warn!("Changed setting {} requires a restart", setting);
+                }
+                apply_log_config(&config, log_handle.as_ref());
+            }
+            Err(why) => error!("Keeping current configuration: {:#}", why),
+        }
+    }
+}
+
+
+fn apply_log_config(config: &Config, log_handle: Option<&LogHandle>) {
+    let Some(log_handle) = log_handle else {
+        return;
+    };
+
+    let log_config: LogConfig = config.get("log").unwrap_or_default();
+    let level = log_config.level.unwrap_or_else(default_log_level);
+
+    if let Err(why) = log_handle.reload(log_filter(level)) {
+        error!("Failed to change log level: {}", why);
+    }
+}
+
+
+fn init_tracing() -> Result<LogHandle> {
     use tracing_subscriber::layer::SubscriberExt;
     use tracing_subscriber::util::SubscriberInitExt;
 
-    let max_level = if cfg!(debug_assertions) {
-        Level::TRACE
-    } else {
-        Level::INFO
-    };
+    let (filter_layer, log_handle) = reload::Layer::new(log_filter(default_log_level()));
+
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(tracing_subscriber::fmt::Layer::default())
+        .try_init()?;
+
+    Ok(log_handle)
+}
+
 
-    let filter_layer = Targets::new()
+fn log_filter(max_level: Level) -> Targets {
+    Targets::new()
+        .with_target("server", max_level)
         .with_target("javelin", max_level)
         .with_target("javelin_rtmp", max_level)
         .with_target("javelin_srt", max_level)
         .with_target("javelin_hls", max_level)
+        .with_target("javelin_recorder", max_level)
         .with_target("javelin_core", max_level)
         .with_target("javelin_codec", max_level)
-        .with_default(Level::ERROR);
+        .with_default(Level::ERROR)
+}
 
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::Layer::default())
-        .with(filter_layer)
-        .try_init()?;
 
-    Ok(())
+fn default_log_level() -> Level {
+    if cfg!(debug_assertions) {
+        Level::TRACE
+    } else {
+        Level::INFO
+    }
+}
+
+
+fn default_shutdown_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+
+fn deserialize_level<'de, D>(deserializer: D) -> Result<Option<Level>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let level = String::deserialize(deserializer)?;
+    level.parse().map(Some).map_err(serde::de::Error::custom)
 }
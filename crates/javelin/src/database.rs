@@ -1,27 +1,206 @@
-use anyhow::Result;
-use javelin_core::Config;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
 use javelin_types::async_trait;
-pub use javelin_types::models::{Error, User, UserRepository};
-use tracing::{debug, trace};
+pub use javelin_types::models::{
+    hash_key, key_prefix, Error, Limits, SessionRecord, StreamKey, User, UserRepository,
+};
+use serde::Serialize;
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::sqlite::SqliteConnectOptions;
+use tracing::{debug, info, trace};
+
+use crate::repository::SqliteConfig;
 
 
 type Pool = sqlx::SqlitePool;
 
 
+/// Marks stream and play keys stored in plain text, see [`Database::hash_legacy_keys`].
+const LEGACY_KEY_PREFIX: &str = "plain$";
+
+
+static MIGRATOR: Migrator = sqlx::migrate!("database/migrations");
+
+
 #[derive(Clone)]
 pub struct Database {
     pool: Pool,
 }
 
 
+#[derive(Debug, Serialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+
 impl Database {
-    pub async fn new(config: &Config) -> Self {
-        let path: String = config.get("database.sqlite.path").unwrap();
-        let pool = sqlx::SqlitePool::connect(&format!("sqlite:{path}"))
+    /// Opens the database and brings its schema up to date, as far as the config allows.
+    pub async fn new(config: &SqliteConfig) -> Result<Self> {
+        let database = Self::connect(config).await?;
+
+        if config.auto_migrate {
+            database.migrate().await?;
+        } else if database
+            .migration_status()
+            .await?
+            .iter()
+            .any(|migration| !migration.applied)
+        {
+            bail!("Database schema is out of date, apply pending migrations with `cli db migrate`");
+        }
+
+        database
+            .hash_legacy_keys()
+            .await
+            .context("Failed to hash stream keys")?;
+
+        Ok(database)
+    }
+
+    /// Opens the database as it is, creating an empty one if the file does not exist.
+    pub async fn connect(config: &SqliteConfig) -> Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(&config.path)
+            .create_if_missing(true);
+
+        let pool = Pool::connect_with(options)
+            .await
+            .with_context(|| format!("Failed to open database at {}", config.path.display()))?;
+
+        Ok(Self { pool })
+    }
+
+    /// Applies all pending migrations and returns the ones that were applied.
+    pub async fn migrate(&self) -> Result<Vec<MigrationStatus>> {
+        let mut pending: Vec<_> = self
+            .migration_status()
+            .await?
+            .into_iter()
+            .filter(|migration| !migration.applied)
+            .collect();
+
+        MIGRATOR
+            .run(&self.pool)
+            .await
+            .context("Failed to apply migrations")?;
+
+        self.hash_legacy_keys()
+            .await
+            .context("Failed to hash stream keys")?;
+
+        for migration in &mut pending {
+            migration.applied = true;
+            info!(
+                "Applied migration {} ({})",
+                migration.version, migration.description
+            );
+        }
+
+        Ok(pending)
+    }
+
+    /// All migrations known to this build, and whether they were applied yet.
+    pub async fn migration_status(&self) -> Result<Vec<MigrationStatus>> {
+        let mut conn = self.pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+
+        let applied: HashSet<_> = conn
+            .list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|migration| migration.version)
+            .collect();
+
+        let status = MIGRATOR
+            .iter()
+            .filter(|migration| !migration.migration_type.is_down_migration())
+            .map(|migration| MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                applied: applied.contains(&migration.version),
+            })
+            .collect();
+
+        Ok(status)
+    }
+
+    /// Replaces stream and play keys stored in plain text before keys were hashed.
+    ///
+    /// Runs with every migration, and on startup for databases migrated by older versions.
+    async fn hash_legacy_keys(&self) -> Result<(), Error> {
+        let legacy_keys = sqlx::query!(
+            "SELECT id, hash FROM stream_keys WHERE instr(hash, $1) = 1",
+            LEGACY_KEY_PREFIX
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| Error::LookupFailed)?;
+
+        let legacy_play_keys = sqlx::query!(
+            "SELECT id, play_key FROM users WHERE instr(play_key, $1) = 1",
+            LEGACY_KEY_PREFIX
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| Error::LookupFailed)?;
+
+        if !legacy_play_keys.is_empty() {
+            info!(
+                "Hashing {} play keys stored in plain text",
+                legacy_play_keys.len()
+            );
+        }
+
+        for legacy_user in legacy_play_keys {
+            let Some(key) = legacy_user
+                .play_key
+                .as_deref()
+                .and_then(|play_key| play_key.strip_prefix(LEGACY_KEY_PREFIX))
+            else {
+                continue;
+            };
+            let hash = hash_key(key)?;
+            sqlx::query!(
+                "UPDATE users SET play_key = $1 WHERE id = $2",
+                hash,
+                legacy_user.id
+            )
+            .execute(&self.pool)
             .await
-            .expect("Failed to connect to database");
+            .map_err(|_| Error::UpdateFailed)?;
+        }
 
-        Self { pool }
+        if legacy_keys.is_empty() {
+            return Ok(());
+        }
+
+        info!(
+            "Hashing {} stream keys stored in plain text",
+            legacy_keys.len()
+        );
+
+        for legacy_key in legacy_keys {
+            let Some(key) = legacy_key.hash.strip_prefix(LEGACY_KEY_PREFIX) else {
+                continue;
+            };
+            let hash = hash_key(key)?;
+            sqlx::query!(
+                "UPDATE stream_keys SET hash = $1 WHERE id = $2",
+                hash,
+                legacy_key.id
+            )
+            .execute(&self.pool)
+            .await
+            .map_err(|_| Error::UpdateFailed)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -29,35 +208,309 @@ impl Database {
 impl UserRepository for Database {
     async fn user_by_name(&self, name: &str) -> Result<Option<User>, Error> {
         trace!(%name, "Querying user");
-        let user = sqlx::query_as!(User, "SELECT name, key FROM users WHERE name = $1", name)
-            .fetch_optional(&self.pool)
-            .await
-            .map_err(|_| Error::LookupFailed)?;
+        let user = sqlx::query_as!(
+            User,
+            "SELECT name, play_key FROM users WHERE name = $1",
+            name
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| Error::LookupFailed)?;
         trace!(?user);
         Ok(user)
     }
 
-    async fn add_user_with_key(&mut self, name: &str, key: &str) -> Result<(), Error> {
-        let query = match self.user_by_name(name).await? {
-            Some(user) if user.key == key => {
-                debug!("User with key already exists, no update required");
-                return Ok(());
-            }
-            Some(_) => {
-                debug!("Updating existing user");
-                sqlx::query!("UPDATE users SET key = $1 WHERE name = $2", key, name)
-            }
-            None => {
-                debug!("Creating new user");
-                sqlx::query!("INSERT INTO users (name, key) VALUES ($1, $2)", name, key)
-            }
-        };
+    async fn list_users(&self) -> Result<Vec<User>, Error> {
+        trace!("Querying all users");
+        let users = sqlx::query_as!(User, "SELECT name, play_key FROM users ORDER BY name")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|_| Error::LookupFailed)?;
+        Ok(users)
+    }
+
+    async fn add_user(&mut self, name: &str) -> Result<bool, Error> {
+        let result = sqlx::query!(
+            "INSERT INTO users (name) VALUES ($1) ON CONFLICT (name) DO NOTHING",
+            name
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| Error::UpdateFailed)?;
+
+        let created = result.rows_affected() != 0;
+        if created {
+            debug!("Created new user");
+        }
+
+        Ok(created)
+    }
+
+    async fn set_play_key(&mut self, name: &str, play_key: Option<&str>) -> Result<(), Error> {
+        let play_key = play_key.map(hash_key).transpose()?;
+        let result = sqlx::query!(
+            "UPDATE users SET play_key = $1 WHERE name = $2",
+            play_key,
+            name
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| Error::UpdateFailed)?;
+
+        if result.rows_affected() == 0 {
+            debug!("No user to set play key for");
+            return Err(Error::NoSuchUser(name.to_string()));
+        }
 
-        query
+        Ok(())
+    }
+
+    async fn remove_user(&mut self, name: &str) -> Result<bool, Error> {
+        let result = sqlx::query!("DELETE FROM users WHERE name = $1", name)
             .execute(&self.pool)
             .await
             .map_err(|_| Error::UpdateFailed)?;
 
+        Ok(result.rows_affected() != 0)
+    }
+
+    async fn keys_of(&self, name: &str) -> Result<Vec<StreamKey>, Error> {
+        trace!(%name, "Querying stream keys");
+        let keys = sqlx::query_as!(
+            StreamKey,
+            r#"SELECT
+                stream_keys.name,
+                stream_keys.hash,
+                stream_keys.key_prefix AS prefix,
+                stream_keys.stream_name,
+                stream_keys.created_at AS "created_at: DateTime<Utc>",
+                stream_keys.expires_at AS "expires_at: DateTime<Utc>",
+                stream_keys.revoked_at AS "revoked_at: DateTime<Utc>",
+                stream_keys.last_used_at AS "last_used_at: DateTime<Utc>"
+            FROM stream_keys
+            JOIN users ON users.id = stream_keys.user_id
+            WHERE users.name = $1
+            ORDER BY stream_keys.name"#,
+            name
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| Error::LookupFailed)?;
+        Ok(keys)
+    }
+
+    async fn add_key(
+        &mut self,
+        name: &str,
+        key_name: &str,
+        key: &str,
+        generated: bool,
+        stream_name: Option<&str>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), Error> {
+        let hash = hash_key(key)?;
+        let prefix = generated.then(|| key_prefix(key));
+        let now = Utc::now();
+
+        let result = sqlx::query!(
+            "INSERT INTO stream_keys (user_id, name, hash, key_prefix, stream_name, created_at, expires_at)
+            SELECT id, $1, $2, $3, $4, $5, $6 FROM users WHERE name = $7
+            ON CONFLICT (user_id, name) DO UPDATE SET
+                hash = excluded.hash,
+                key_prefix = excluded.key_prefix,
+                stream_name = excluded.stream_name,
+                created_at = excluded.created_at,
+                expires_at = excluded.expires_at,
+                revoked_at = NULL,
+                last_used_at = NULL",
+            key_name,
+            hash,
+            prefix,
+            stream_name,
+            now,
+            expires_at,
+            name
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| Error::UpdateFailed)?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::NoSuchUser(name.to_string()));
+        }
+
+        debug!(%key_name, "Stored stream key");
+
         Ok(())
     }
+
+    async fn revoke_key(&mut self, name: &str, key_name: &str) -> Result<bool, Error> {
+        let now = Utc::now();
+
+        let result = sqlx::query!(
+            "UPDATE stream_keys SET revoked_at = $1
+            WHERE name = $2
+                AND revoked_at IS NULL
+                AND user_id = (SELECT id FROM users WHERE name = $3)",
+            now,
+            key_name,
+            name
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| Error::UpdateFailed)?;
+
+        Ok(result.rows_affected() != 0)
+    }
+
+    async fn touch_key(&self, name: &str, key_name: &str) -> Result<(), Error> {
+        let now = Utc::now();
+
+        sqlx::query!(
+            "UPDATE stream_keys SET last_used_at = $1
+            WHERE name = $2 AND user_id = (SELECT id FROM users WHERE name = $3)",
+            now,
+            key_name,
+            name
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| Error::UpdateFailed)?;
+
+        Ok(())
+    }
+
+    async fn limits_of(&self, name: &str) -> Result<Limits, Error> {
+        let row = sqlx::query!(
+            "SELECT max_sessions, max_bitrate, max_viewers, max_duration FROM users WHERE name = $1",
+            name
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| Error::LookupFailed)?;
+
+        let Some(row) = row else {
+            return Ok(Limits::default());
+        };
+
+        Ok(Limits {
+            max_sessions: row.max_sessions.and_then(|max| max.try_into().ok()),
+            max_bitrate: row.max_bitrate.and_then(|max| max.try_into().ok()),
+            max_viewers: row.max_viewers.and_then(|max| max.try_into().ok()),
+            max_duration: row
+                .max_duration
+                .and_then(|secs| secs.try_into().ok())
+                .map(Duration::from_secs),
+        })
+    }
+
+    async fn set_limits(&mut self, name: &str, limits: Limits) -> Result<(), Error> {
+        // anything beyond what SQLite can hold is as good as unlimited
+        let max_sessions = limits.max_sessions.map(i64::from);
+        let max_bitrate = limits.max_bitrate.and_then(|max| i64::try_from(max).ok());
+        let max_viewers = limits.max_viewers.map(i64::from);
+        let max_duration = limits
+            .max_duration
+            .and_then(|max| i64::try_from(max.as_secs()).ok());
+
+        let result = sqlx::query!(
+            "UPDATE users SET max_sessions = $1, max_bitrate = $2, max_viewers = $3, max_duration = $4
+            WHERE name = $5",
+            max_sessions,
+            max_bitrate,
+            max_viewers,
+            max_duration,
+            name
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| Error::UpdateFailed)?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::NoSuchUser(name.to_string()));
+        }
+
+        debug!(%name, "Updated limits");
+
+        Ok(())
+    }
+
+    async fn record_session(&mut self, record: &SessionRecord) -> Result<(), Error> {
+        // counters beyond what SQLite can hold are capped
+        let bytes_in = i64::try_from(record.bytes_in).unwrap_or(i64::MAX);
+        let bytes_out = i64::try_from(record.bytes_out).unwrap_or(i64::MAX);
+        let peak_viewers = i64::try_from(record.peak_viewers).unwrap_or(i64::MAX);
+
+        sqlx::query!(
+            "INSERT INTO session_history (
+                app_name, stream_name, protocol, client_ip, started_at, ended_at,
+                bytes_in, bytes_out, peak_viewers, end_reason
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            record.app_name,
+            record.stream_name,
+            record.protocol,
+            record.client_ip,
+            record.started_at,
+            record.ended_at,
+            bytes_in,
+            bytes_out,
+            peak_viewers,
+            record.end_reason
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| Error::UpdateFailed)?;
+
+        Ok(())
+    }
+
+    async fn session_history(
+        &self,
+        app_name: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SessionRecord>, Error> {
+        trace!(?app_name, "Querying session history");
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+
+        let rows = sqlx::query!(
+            r#"SELECT
+                app_name,
+                stream_name,
+                protocol,
+                client_ip,
+                started_at AS "started_at: DateTime<Utc>",
+                ended_at AS "ended_at: DateTime<Utc>",
+                bytes_in,
+                bytes_out,
+                peak_viewers,
+                end_reason
+            FROM session_history
+            WHERE $1 IS NULL OR app_name = $1
+            ORDER BY started_at DESC
+            LIMIT $2"#,
+            app_name,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| Error::LookupFailed)?;
+
+        let history = rows
+            .into_iter()
+            .map(|row| SessionRecord {
+                app_name: row.app_name,
+                stream_name: row.stream_name,
+                protocol: row.protocol,
+                client_ip: row.client_ip,
+                started_at: row.started_at,
+                ended_at: row.ended_at,
+                bytes_in: row.bytes_in.try_into().unwrap_or_default(),
+                bytes_out: row.bytes_out.try_into().unwrap_or_default(),
+                peak_viewers: row.peak_viewers.try_into().unwrap_or_default(),
+                end_reason: row.end_reason,
+            })
+            .collect();
+
+        Ok(history)
+    }
 }
@@ -1,3 +1,4 @@
 #![warn(clippy::all)]
 
 pub mod database;
+pub mod repository;
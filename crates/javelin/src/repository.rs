@@ -0,0 +1,224 @@
+mod file;
+
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use javelin_core::Config;
+use javelin_types::async_trait;
+use javelin_types::models::{
+    Error, Limits, MemoryRepository, SessionRecord, StreamKey, User, UserRepository,
+};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+pub use self::file::FileRepository;
+use crate::database::Database;
+
+
+/// Where users and their keys are kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// SQLite database at `database.sqlite.path`, managed with the CLI or the admin API.
+    #[default]
+    Sqlite,
+
+    /// Static credentials file at `database.file.path`, re-read whenever it changes.
+    File,
+
+    /// Starts out empty and forgets all users on shutdown.
+    Memory,
+}
+
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RepositoryConfig {
+    #[serde(default)]
+    pub backend: Backend,
+
+    #[serde(default)]
+    pub sqlite: Option<SqliteConfig>,
+
+    #[serde(default)]
+    pub file: Option<FileConfig>,
+}
+
+impl RepositoryConfig {
+    /// Reads the `[database]` section, which is checked again on every reload.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        config.register_section::<Self>("database", &["backend", "sqlite", "file"]);
+
+        match config.get("database") {
+            Ok(repo_config) => Ok(repo_config),
+            Err(why) if is_not_found(&why) => Ok(Self::default()),
+            Err(why) => Err(why).context("Invalid [database] section"),
+        }
+    }
+
+    pub fn sqlite(&self) -> Result<&SqliteConfig> {
+        match &self.sqlite {
+            Some(sqlite_config) => Ok(sqlite_config),
+            None => bail!("No database configured, set database.sqlite.path"),
+        }
+    }
+
+    pub fn file(&self) -> Result<&FileConfig> {
+        match &self.file {
+            Some(file_config) => Ok(file_config),
+            None => bail!("No credentials file configured, set database.file.path"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SqliteConfig {
+    /// Created along with the schema if it does not exist yet.
+    pub path: PathBuf,
+
+    /// Applies pending migrations on startup, otherwise they have to be applied with
+    /// `cli db migrate` first.
+    #[serde(default = "default_auto_migrate")]
+    pub auto_migrate: bool,
+}
+
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileConfig {
+    /// TOML or YAML, told apart by the file extension.
+    pub path: PathBuf,
+
+    /// How often the file is checked for changes.
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: Duration,
+}
+
+
+/// The user repository selected by `database.backend`.
+#[derive(Clone)]
+pub enum Repository {
+    Sqlite(Database),
+    File(FileRepository),
+    Memory(MemoryRepository),
+}
+
+impl Repository {
+    pub async fn new(config: &Config) -> Result<Self> {
+        let repo_config = RepositoryConfig::from_config(config)?;
+
+        let repository = match repo_config.backend {
+            Backend::Sqlite => Self::Sqlite(Database::new(repo_config.sqlite()?).await?),
+            Backend::File => Self::File(FileRepository::new(repo_config.file()?)?),
+            Backend::Memory => {
+                warn!("Users are kept in memory only and will be lost on shutdown");
+                Self::Memory(MemoryRepository::new())
+            }
+        };
+
+        info!("Using {:?} user repository", repo_config.backend);
+
+        Ok(repository)
+    }
+}
+
+
+macro_rules! dispatch {
+    ($repository:expr, $method:ident($($arg:expr),*)) => {
+        match $repository {
+            Repository::Sqlite(inner) => inner.$method($($arg),*).await,
+            Repository::File(inner) => inner.$method($($arg),*).await,
+            Repository::Memory(inner) => inner.$method($($arg),*).await,
+        }
+    };
+}
+
+#[async_trait]
+impl UserRepository for Repository {
+    async fn user_by_name(&self, name: &str) -> Result<Option<User>, Error> {
+        dispatch!(self, user_by_name(name))
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, Error> {
+        dispatch!(self, list_users())
+    }
+
+    async fn add_user(&mut self, name: &str) -> Result<bool, Error> {
+        dispatch!(self, add_user(name))
+    }
+
+    async fn set_play_key(&mut self, name: &str, play_key: Option<&str>) -> Result<(), Error> {
+        dispatch!(self, set_play_key(name, play_key))
+    }
+
+    async fn remove_user(&mut self, name: &str) -> Result<bool, Error> {
+        dispatch!(self, remove_user(name))
+    }
+
+    async fn keys_of(&self, name: &str) -> Result<Vec<StreamKey>, Error> {
+        dispatch!(self, keys_of(name))
+    }
+
+    async fn add_key(
+        &mut self,
+        name: &str,
+        key_name: &str,
+        key: &str,
+        generated: bool,
+        stream_name: Option<&str>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), Error> {
+        dispatch!(
+            self,
+            add_key(name, key_name, key, generated, stream_name, expires_at)
+        )
+    }
+
+    async fn revoke_key(&mut self, name: &str, key_name: &str) -> Result<bool, Error> {
+        dispatch!(self, revoke_key(name, key_name))
+    }
+
+    async fn touch_key(&self, name: &str, key_name: &str) -> Result<(), Error> {
+        dispatch!(self, touch_key(name, key_name))
+    }
+
+    async fn limits_of(&self, name: &str) -> Result<Limits, Error> {
+        dispatch!(self, limits_of(name))
+    }
+
+    async fn set_limits(&mut self, name: &str, limits: Limits) -> Result<(), Error> {
+        dispatch!(self, set_limits(name, limits))
+    }
+
+    async fn record_session(&mut self, record: &SessionRecord) -> Result<(), Error> {
+        dispatch!(self, record_session(record))
+    }
+
+    async fn session_history(
+        &self,
+        app_name: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SessionRecord>, Error> {
+        dispatch!(self, session_history(app_name, limit))
+    }
+}
+
+
+fn is_not_found(why: &anyhow::Error) -> bool {
+    matches!(
+        why.downcast_ref::<config::ConfigError>(),
+        Some(config::ConfigError::NotFound(_))
+    )
+}
+
+
+fn default_auto_migrate() -> bool {
+    true
+}
+
+
+fn default_poll_interval() -> Duration {
+    Duration::from_secs(5)
+}
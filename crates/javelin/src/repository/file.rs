@@ -0,0 +1,278 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use javelin_types::async_trait;
+use javelin_types::models::{
+    hash_key, Error, Limits, MemoryRepository, SessionRecord, StreamKey, User, UserRepository,
+    DEFAULT_KEY_NAME,
+};
+use serde::Deserialize;
+use tracing::{debug, error, info};
+
+use super::FileConfig;
+
+
+/// Prefix of keys that are already hashed, everything else is taken as a plain key.
+const HASH_PREFIX: &str = "$argon2";
+
+
+/// Users read from a static credentials file, picked up again whenever the file changes.
+///
+/// ```toml
+/// [[users]]
+/// name = "alice"
+/// play_key = "for-viewers"  # optional, plain or hashed, makes the streams private
+///
+/// [[users.keys]]
+/// key = "secret"            # plain, or hashed with `cli hash-key`
+///
+/// [[users.keys]]
+/// name = "backup"
+/// key = "$argon2id$v=19$..."
+/// stream = "main"           # optional, only valid for publishing this stream
+/// expires_at = 2027-01-01T00:00:00Z
+///
+/// [users.limits]            # optional, see `Limits`
+/// max_viewers = 100
+/// max_duration = 14400
+/// ```
+///
+/// Changes are only possible by editing the file, the repository itself is read-only.
+#[derive(Clone)]
+pub struct FileRepository {
+    users: MemoryRepository,
+}
+
+
+#[derive(Debug, Deserialize)]
+struct CredentialsFile {
+    #[serde(default)]
+    users: Vec<UserEntry>,
+}
+
+
+#[derive(Debug, Deserialize)]
+struct UserEntry {
+    name: String,
+
+    #[serde(default)]
+    play_key: Option<String>,
+
+    #[serde(default)]
+    keys: Vec<KeyEntry>,
+
+    #[serde(default)]
+    limits: Limits,
+}
+
+
+#[derive(Debug, Deserialize)]
+struct KeyEntry {
+    #[serde(default = "default_key_name")]
+    name: String,
+
+    key: String,
+
+    #[serde(default)]
+    stream: Option<String>,
+
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+
+impl FileRepository {
+    /// Reads the file once and keeps watching it in the background.
+    pub fn new(config: &FileConfig) -> Result<Self> {
+        if config.poll_interval.is_zero() {
+            bail!("database.file.poll_interval has to be longer than zero");
+        }
+
+        // taken first, so changes made while loading are picked up
+        let last_modified = modified(&config.path);
+        let users = load(&config.path)?;
+        info!("Loaded credentials from {}", config.path.display());
+
+        tokio::spawn({
+            let users = users.clone();
+            watch(
+                users,
+                config.path.clone(),
+                last_modified,
+                config.poll_interval,
+            )
+        });
+
+        Ok(Self { users })
+    }
+}
+
+
+#[async_trait]
+impl UserRepository for FileRepository {
+    async fn user_by_name(&self, name: &str) -> Result<Option<User>, Error> {
+        self.users.user_by_name(name).await
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, Error> {
+        self.users.list_users().await
+    }
+
+    async fn add_user(&mut self, _name: &str) -> Result<bool, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    async fn set_play_key(&mut self, _name: &str, _play_key: Option<&str>) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    async fn remove_user(&mut self, _name: &str) -> Result<bool, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    async fn keys_of(&self, name: &str) -> Result<Vec<StreamKey>, Error> {
+        self.users.keys_of(name).await
+    }
+
+    async fn add_key(
+        &mut self,
+        _name: &str,
+        _key_name: &str,
+        _key: &str,
+        _generated: bool,
+        _stream_name: Option<&str>,
+        _expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    async fn revoke_key(&mut self, _name: &str, _key_name: &str) -> Result<bool, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Last use is only tracked until the file is reloaded.
+    async fn touch_key(&self, name: &str, key_name: &str) -> Result<(), Error> {
+        self.users.touch_key(name, key_name).await
+    }
+
+    async fn limits_of(&self, name: &str) -> Result<Limits, Error> {
+        self.users.limits_of(name).await
+    }
+
+    async fn set_limits(&mut self, _name: &str, _limits: Limits) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    /// The history is kept in memory only.
+    async fn record_session(&mut self, record: &SessionRecord) -> Result<(), Error> {
+        self.users.record_session(record).await
+    }
+
+    async fn session_history(
+        &self,
+        app_name: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SessionRecord>, Error> {
+        self.users.session_history(app_name, limit).await
+    }
+}
+
+
+/// Reloads the file whenever its modification time changes, the current users are kept
+/// if it turns out to be invalid.
+async fn watch(
+    users: MemoryRepository,
+    path: PathBuf,
+    mut last_modified: Option<SystemTime>,
+    poll_interval: Duration,
+) {
+    let mut interval = tokio::time::interval(poll_interval);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let current = modified(&path);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+
+        debug!("Credentials file changed");
+
+        let loaded = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || load(&path)).await
+        };
+
+        match loaded {
+            Ok(Ok(loaded)) => {
+                users.replace_with(loaded);
+                info!("Reloaded credentials from {}", path.display());
+            }
+            Ok(Err(why)) => error!("Keeping current credentials: {:#}", why),
+            Err(why) => error!("Failed to reload credentials: {}", why),
+        }
+    }
+}
+
+
+fn load(path: &Path) -> Result<MemoryRepository> {
+    let credentials: CredentialsFile = config::Config::builder()
+        .add_source(config::File::from(path))
+        .build()
+        .and_then(config::Config::try_deserialize)
+        .with_context(|| format!("Failed to read credentials from {}", path.display()))?;
+
+    let users = MemoryRepository::new();
+    let now = Utc::now();
+
+    for user in credentials.users {
+        let play_key = match user.play_key {
+            Some(play_key) if play_key.starts_with(HASH_PREFIX) => Some(play_key),
+            Some(play_key) => Some(hash_key(&play_key)?),
+            None => None,
+        };
+
+        if !users.insert_user(&user.name, play_key) {
+            bail!("User {} is listed more than once", user.name);
+        }
+        users.insert_limits(&user.name, user.limits)?;
+
+        for key in user.keys {
+            let hash = if key.key.starts_with(HASH_PREFIX) {
+                key.key
+            } else {
+                hash_key(&key.key)?
+            };
+
+            let stream_key = StreamKey {
+                name: key.name,
+                hash,
+                prefix: None,
+                stream_name: key.stream,
+                created_at: now,
+                expires_at: key.expires_at,
+                revoked_at: None,
+                last_used_at: None,
+            };
+            users.insert_key(&user.name, stream_key)?;
+        }
+    }
+
+    Ok(users)
+}
+
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+
+fn default_key_name() -> String {
+    DEFAULT_KEY_NAME.to_string()
+}